@@ -7,3 +7,8 @@ mv 6502/v1/*.json .
 rm -rf 6502
 rm README.md
 rm .gitignore
+cd ../..
+
+# Klaus Dormann's functional test (prebuilt binary, success trap at $3469)
+mkdir -p tests/roms
+curl -L -o tests/roms/6502_functional_test.bin https://github.com/Klaus2m5/6502_65C02_functional_tests/raw/master/bin_files/6502_functional_test.bin
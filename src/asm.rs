@@ -0,0 +1,228 @@
+use super::*;
+use crate::symbols::SymbolTable;
+use std::fmt;
+
+/* A one line at a time assembler, the kind found in machine language monitors. It understands
+the usual syntax for every addressing mode:
+
+    LDA #$10    LDA $10     LDA $10,X   LDA $1234   LDA $1234,X   LDA $1234,Y
+    LDA ($10,X) LDA ($10),Y JMP ($1234) ASL A (or just ASL)       BNE $0410
+
+Values are $hex, %binary, decimal or a symbol, optionally with + and - and a < or > prefix for
+the low or high byte. '*' is the address being assembled at. Zero page addressing is picked when
+the value fits and the instruction has a zero page form. Undocumented mnemonics (LAX, SAX, etc.)
+are accepted too, though documented opcodes are preferred. */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(message: impl Into<String>) -> Self {
+        AsmError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// How the operand was written, before picking between zero page and absolute
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(u16),
+    Address(u16),
+    IndexedX(u16),
+    IndexedY(u16),
+    Indirect(u16),
+    IndirectX(u16),
+    IndirectY(u16),
+}
+
+// Assemble a single instruction that's going to live at 'address'
+pub fn assemble(
+    line: &str,
+    address: u16,
+    symbols: Option<&SymbolTable>,
+) -> Result<Vec<u8>, AsmError> {
+    let line = line.trim();
+    let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+        None => (line, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_lowercase();
+
+    if !OPCODES.iter().any(|op| op.name == mnemonic) {
+        return Err(AsmError::new(format!("Unknown instruction '{}'", mnemonic)));
+    }
+
+    let value = |text: &str| parse_value(text, address, symbols);
+    let operand = parse_operand(operand, value)?;
+
+    // Branches take their target address, which is turned into an offset
+    if let Operand::Address(target) = operand {
+        if let Some(opcode) = find(&mnemonic, AddrMode::REL0) {
+            let offset = target.wrapping_sub(address.wrapping_add(2)) as i16;
+            if !(-128..=127).contains(&offset) {
+                return Err(AsmError::new(format!(
+                    "Branch to ${:04X} is out of range",
+                    target
+                )));
+            }
+            return Ok(vec![opcode, offset as u8]);
+        }
+    }
+
+    let zero_page = |value: u16, zpg: AddrMode, abs: AddrMode| match value < 0x100 {
+        true => find(&mnemonic, zpg)
+            .map(|op| vec![op, value as u8])
+            .or_else(|| find(&mnemonic, abs).map(|op| word(op, value))),
+        false => find(&mnemonic, abs).map(|op| word(op, value)),
+    };
+    let byte = |value: u16, mode: AddrMode| match value < 0x100 {
+        true => find(&mnemonic, mode).map(|op| vec![op, value as u8]),
+        false => None,
+    };
+
+    let bytes = match operand {
+        Operand::None => find(&mnemonic, AddrMode::IMP0)
+            .or_else(|| find(&mnemonic, AddrMode::ACM0))
+            .map(|op| vec![op]),
+        Operand::Accumulator => find(&mnemonic, AddrMode::ACM0).map(|op| vec![op]),
+        Operand::Immediate(value) => byte(value, AddrMode::IMM0),
+        Operand::Address(value) => zero_page(value, AddrMode::ZPG0, AddrMode::ABS0),
+        Operand::IndexedX(value) => zero_page(value, AddrMode::ZPGX, AddrMode::ABSX),
+        Operand::IndexedY(value) => zero_page(value, AddrMode::ZPGY, AddrMode::ABSY),
+        Operand::Indirect(value) => find(&mnemonic, AddrMode::IND0).map(|op| word(op, value)),
+        Operand::IndirectX(value) => byte(value, AddrMode::INDX),
+        Operand::IndirectY(value) => byte(value, AddrMode::INDY),
+    };
+
+    bytes.ok_or_else(|| {
+        AsmError::new(format!(
+            "Invalid operand for {}",
+            mnemonic.to_ascii_uppercase()
+        ))
+    })
+}
+
+// The opcode for an instruction and addressing mode, documented opcodes winning over others
+fn find(mnemonic: &str, mode: AddrMode) -> Option<u8> {
+    let matching = |op: &u8| {
+        let opcode = &OPCODES[*op as usize];
+        opcode.name == mnemonic && opcode.mode == mode
+    };
+
+    (0..=0xFF)
+        .filter(|op| !disasm::is_illegal(*op))
+        .find(matching)
+        .or_else(|| (0..=0xFF).find(matching))
+}
+
+fn word(opcode: u8, value: u16) -> Vec<u8> {
+    vec![opcode, value as u8, (value >> 8) as u8]
+}
+
+fn parse_operand(
+    text: &str,
+    value: impl Fn(&str) -> Result<u16, AsmError>,
+) -> Result<Operand, AsmError> {
+    let upper = text.to_ascii_uppercase().replace(' ', "");
+    let text = text.replace(' ', "");
+
+    if text.is_empty() {
+        return Ok(Operand::None);
+    }
+    if upper == "A" {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(rest) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(value(rest)?));
+    }
+
+    // Strip a suffix regardless of case, e.g. ",X" or ",x"
+    let strip = |suffix: &str| match upper.ends_with(suffix) {
+        true => Some(&text[..text.len() - suffix.len()]),
+        false => None,
+    };
+
+    if text.starts_with('(') {
+        if let Some(inner) = strip(",X)") {
+            return Ok(Operand::IndirectX(value(&inner[1..])?));
+        }
+        if let Some(inner) = strip("),Y") {
+            return Ok(Operand::IndirectY(value(&inner[1..])?));
+        }
+        if let Some(inner) = strip(")") {
+            return Ok(Operand::Indirect(value(&inner[1..])?));
+        }
+        return Err(AsmError::new(format!("Bad indirect operand '{}'", text)));
+    }
+
+    if let Some(base) = strip(",X") {
+        return Ok(Operand::IndexedX(value(base)?));
+    }
+    if let Some(base) = strip(",Y") {
+        return Ok(Operand::IndexedY(value(base)?));
+    }
+    Ok(Operand::Address(value(&text)?))
+}
+
+// A sum of terms with an optional < (low byte) or > (high byte) in front
+pub fn parse_value(text: &str, here: u16, symbols: Option<&SymbolTable>) -> Result<u16, AsmError> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix('<') {
+        return parse_value(rest, here, symbols).map(|v| v & 0xFF);
+    }
+    if let Some(rest) = text.strip_prefix('>') {
+        return parse_value(rest, here, symbols).map(|v| v >> 8);
+    }
+
+    let mut total: u16 = 0;
+    let mut negate = false;
+    let mut term = String::new();
+    for c in text.chars().chain(std::iter::once('+')) {
+        match c {
+            '+' | '-' if !term.is_empty() => {
+                let value = parse_term(&term, here, symbols)?;
+                total = match negate {
+                    true => total.wrapping_sub(value),
+                    false => total.wrapping_add(value),
+                };
+                negate = c == '-';
+                term.clear();
+            }
+            '-' if term.is_empty() => negate = !negate,
+            _ => term.push(c),
+        }
+    }
+    Ok(total)
+}
+
+fn parse_term(term: &str, here: u16, symbols: Option<&SymbolTable>) -> Result<u16, AsmError> {
+    let bad = || AsmError::new(format!("Bad value '{}'", term));
+    let number = |digits: &str, radix: u32| u16::from_str_radix(digits, radix).map_err(|_| bad());
+
+    if term == "*" {
+        return Ok(here);
+    }
+    if let Some(hex) = term.strip_prefix('$') {
+        return number(hex, 16);
+    }
+    if let Some(bin) = term.strip_prefix('%') {
+        return number(bin, 2);
+    }
+    if term.starts_with(|c: char| c.is_ascii_digit()) {
+        return number(term, 10);
+    }
+
+    symbols
+        .and_then(|s| s.address(term))
+        .ok_or_else(|| AsmError::new(format!("Unknown symbol '{}'", term)))
+}
@@ -0,0 +1,12 @@
+use rust_6502::dap::{self, DapServer};
+use rust_6502::monitor::Monitor;
+use std::io;
+
+/* Debug Adapter Protocol server on stdin/stdout, to be started by an editor. See the dap module
+for the launch arguments. */
+fn main() {
+    let mut server = DapServer::new(Monitor::new());
+    if let Err(e) = dap::serve(&mut server, io::stdin(), io::stdout()) {
+        eprintln!("{}", e);
+    }
+}
@@ -0,0 +1,46 @@
+use rust_6502::debuginfo::is_debug_info_path;
+use rust_6502::monitor::Monitor;
+use std::env;
+use std::io::{self, BufRead, Write};
+
+/* Interactive machine language monitor. Files given on the command line are loaded before the
+prompt shows up, as "file" for a PRG or "file@addr" for a raw binary. Debug info (.dbg) and
+label files (.lbl, .vs) get loaded as symbols:
+
+    cargo run --bin monitor -- program.bin@0400 program.dbg
+*/
+fn main() {
+    let mut monitor = Monitor::new();
+
+    // Ctrl-C breaks into a running program rather than ending the monitor
+    let interrupt = monitor.interrupt_handle();
+    ctrlc::set_handler(move || interrupt.stop()).expect("Can't handle Ctrl-C");
+
+    for arg in env::args().skip(1) {
+        let command = match arg.rsplit_once('@') {
+            Some((file, address)) => format!("l {} {}", file, address),
+            None if is_debug_info_path(&arg) => format!("dbg {}", arg),
+            None => format!("l {}", arg),
+        };
+        run(&mut monitor, &command);
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    while !monitor.is_done() {
+        print!("{}", monitor.prompt());
+        io::stdout().flush().unwrap();
+
+        match lines.next() {
+            Some(Ok(line)) => run(&mut monitor, &line),
+            _ => break,
+        }
+    }
+}
+
+fn run(monitor: &mut Monitor, command: &str) {
+    match monitor.execute(command) {
+        Ok(output) => print!("{}", output),
+        Err(e) => println!("? {}", e),
+    }
+}
@@ -0,0 +1,27 @@
+use rust_6502::debuginfo::is_debug_info_path;
+use rust_6502::monitor::Monitor;
+use rust_6502::tui::{self, Tui};
+use std::env;
+
+/* Full screen debugger. Files given on the command line are loaded like with the monitor, as
+"file" for a PRG or "file@addr" for a raw binary, and the PC starts at the first one. Debug info
+(.dbg) and label files (.lbl, .vs) get loaded as symbols. */
+fn main() {
+    let mut tui = Tui::new(Monitor::new());
+
+    for (i, arg) in env::args().skip(1).enumerate() {
+        let (command, address) = match arg.rsplit_once('@') {
+            Some((file, address)) => (format!("l {} {}", file, address), address.to_string()),
+            None if is_debug_info_path(&arg) => (format!("dbg {}", arg), String::new()),
+            None => (format!("l {}", arg), String::new()),
+        };
+        tui.command(&command);
+        if i == 0 && !address.is_empty() {
+            tui.command(&format!("r pc={}", address));
+        }
+    }
+
+    if let Err(e) = tui::term::run(tui) {
+        eprintln!("{}", e);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::symbols::{LineTable, SymbolTable};
+use crate::Registers;
+use std::collections::VecDeque;
+use std::fmt;
+
+/* A shadow call stack, kept alongside the real one when call tracking is enabled on the CPU.
+Every JSR, BRK and interrupt pushes a frame, RTS and RTI pop it again. 6502 code doesn't always
+play along though: RTS is a common way to do a computed jump, and routines sometimes pull their
+return address off the stack and never return. So a return only pops the frame whose stack
+pointer it lands on, and anything that moves the stack pointer above a frame throws it away.
+Those cases are recorded as anomalies rather than being treated as errors. */
+
+// Only the most recent anomalies are kept
+const MAX_ANOMALIES: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Break,
+    Interrupt { vector: u16 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+
+    // Address of the JSR/BRK, or of the instruction that got interrupted
+    pub caller: u16,
+    pub target: u16,
+    pub return_address: u16,
+
+    // Stack pointer before the call, which is where it's back to once the call returns
+    pub sp: u8,
+
+    // Total cycle count when the call was made
+    pub cycle: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    // RTS/RTI that didn't match any frame, i.e. a computed jump
+    ComputedJump {
+        pc: u16,
+        target: u16,
+        cycle: u64,
+    },
+
+    // Returned to somewhere other than the return address, which was changed on the stack
+    Redirected {
+        pc: u16,
+        expected: u16,
+        target: u16,
+        cycle: u64,
+    },
+
+    // Frames dropped without returning, the instruction at 'pc' moved the stack above them
+    Unwound {
+        pc: u16,
+        frames: usize,
+        cycle: u64,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Anomaly::ComputedJump { pc, target, cycle } => write!(
+                f,
+                "${:04X}: return to ${:04X} without a matching call (cycle {})",
+                pc, target, cycle
+            ),
+            Anomaly::Redirected {
+                pc,
+                expected,
+                target,
+                cycle,
+            } => write!(
+                f,
+                "${:04X}: returned to ${:04X} instead of ${:04X} (cycle {})",
+                pc, target, expected, cycle
+            ),
+            Anomaly::Unwound { pc, frames, cycle } => write!(
+                f,
+                "${:04X}: {} frame(s) left without returning (cycle {})",
+                pc, frames, cycle
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    anomalies: VecDeque<Anomaly>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    // Outermost frame first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn anomalies(&self) -> &VecDeque<Anomaly> {
+        &self.anomalies
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.anomalies.clear();
+    }
+
+    // Called after every instruction with the PC and stack pointer from before it ran
+    pub(crate) fn instruction(&mut self, opcode: u8, pc: u16, s: u8, r: &Registers, cycle: u64) {
+        match opcode {
+            0x20 => self.push(FrameKind::Call, pc, s, r.pc, pc.wrapping_add(3), cycle),
+            0x00 => self.push(FrameKind::Break, pc, s, r.pc, pc.wrapping_add(2), cycle),
+            0x40 | 0x60 => self.ret(pc, r, cycle),
+            _ => self.discard(pc, r.s, cycle),
+        }
+    }
+
+    pub(crate) fn interrupt(&mut self, pc: u16, s: u8, vector: u16, target: u16, cycle: u64) {
+        self.push(FrameKind::Interrupt { vector }, pc, s, target, pc, cycle);
+    }
+
+    fn push(&mut self, kind: FrameKind, caller: u16, sp: u8, target: u16, ret: u16, cycle: u64) {
+        self.frames.push(Frame {
+            kind,
+            caller,
+            target,
+            return_address: ret,
+            sp,
+            cycle,
+        });
+    }
+
+    fn ret(&mut self, pc: u16, r: &Registers, cycle: u64) {
+        let Some(i) = self.frames.iter().rposition(|f| f.sp == r.s) else {
+            self.anomaly(Anomaly::ComputedJump {
+                pc,
+                target: r.pc,
+                cycle,
+            });
+            self.discard(pc, r.s, cycle);
+            return;
+        };
+
+        let frames = self.frames.len() - 1 - i;
+        if frames != 0 {
+            self.anomaly(Anomaly::Unwound { pc, frames, cycle });
+        }
+
+        let frame = self.frames[i];
+        self.frames.truncate(i);
+        if frame.return_address != r.pc {
+            self.anomaly(Anomaly::Redirected {
+                pc,
+                expected: frame.return_address,
+                target: r.pc,
+                cycle,
+            });
+        }
+    }
+
+    // Drop frames whose return address has been pulled off the stack
+    fn discard(&mut self, pc: u16, s: u8, cycle: u64) {
+        let Some(i) = self
+            .frames
+            .iter()
+            .position(|f| f.sp.wrapping_sub(s) as i8 <= 0)
+        else {
+            return;
+        };
+
+        let frames = self.frames.len() - i;
+        self.frames.truncate(i);
+        self.anomaly(Anomaly::Unwound { pc, frames, cycle });
+    }
+
+    fn anomaly(&mut self, anomaly: Anomaly) {
+        if self.anomalies.len() == MAX_ANOMALIES {
+            self.anomalies.pop_front();
+        }
+        self.anomalies.push_back(anomaly);
+    }
+
+    /* One line per frame, innermost first, starting with where the CPU is now:
+
+        #0  $0516  recurse+6
+        #1  $0513  recurse+3    S:$FB  cycle 40
+        #2  $0500  main         S:$FF  cycle 6
+
+    With source lines each frame ends with where it is in the source, like "main.s:42".
+    */
+    pub fn backtrace(
+        &self,
+        pc: u16,
+        symbols: Option<&SymbolTable>,
+        lines: Option<&LineTable>,
+    ) -> String {
+        let describe = |address: u16| {
+            symbols
+                .and_then(|s| s.describe(address))
+                .unwrap_or_default()
+        };
+        let location = |address: u16| match lines.and_then(|l| l.describe(address)) {
+            Some(location) => format!("  {}", location),
+            None => String::new(),
+        };
+
+        let mut out = format!("#0  ${:04X}  {}{}", pc, describe(pc), location(pc))
+            .trim_end()
+            .to_string()
+            + "\n";
+        for (n, frame) in self.frames.iter().rev().enumerate() {
+            let how = match frame.kind {
+                FrameKind::Call => String::new(),
+                FrameKind::Break => " [BRK]".to_string(),
+                FrameKind::Interrupt { vector } => match vector {
+                    0xFFFA => " [NMI]".to_string(),
+                    0xFFFE => " [IRQ]".to_string(),
+                    _ => format!(" [interrupt ${:04X}]", vector),
+                },
+            };
+            out += &format!(
+                "#{:<2} ${:04X}  {:<16} S:${:02X}  cycle {}{}{}\n",
+                n + 1,
+                frame.caller,
+                describe(frame.caller),
+                frame.sp,
+                frame.cycle,
+                how,
+                location(frame.caller)
+            );
+        }
+        out
+    }
+}
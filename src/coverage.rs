@@ -0,0 +1,222 @@
+use super::*;
+use std::collections::BTreeMap;
+use symbols::LineTable;
+
+/* Code and data coverage: what every byte of memory was used for while it was attached. Bytes
+are executed as an opcode or an operand, read as data, written, or never touched at all. Reads
+are whatever the CPU put on the bus, so the dummy reads some addressing modes make count too,
+except those of the instruction's own bytes (which the 6502 likes to read again).
+
+    cpu.set_coverage(Some(Coverage::new()));
+    ...
+    let coverage = cpu.coverage().unwrap();
+    print!("{}", coverage.summary(&[("game", 0x0800, 0x1FFF)]));
+    fs::write("coverage.info", coverage.lcov(&info.lines));
+
+The lcov tracefile is made against source lines from debug info (see the debuginfo module), so
+CI tooling like genhtml or a coverage gate can read it like any other. */
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Usage: u8 {
+        const OPCODE = 1 << 0;
+        const OPERAND = 1 << 1;
+        const READ = 1 << 2;
+        const WRITTEN = 1 << 3;
+    }
+}
+
+// Number of bytes in a range used each way, a byte can count more than once
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RangeStats {
+    pub bytes: usize,
+    pub opcode: usize,
+    pub operand: usize,
+    pub read: usize,
+    pub written: usize,
+    pub untouched: usize,
+}
+
+impl RangeStats {
+    // Share of the bytes that were used at all
+    pub fn percent(&self) -> f64 {
+        match self.bytes {
+            0 => 0.0,
+            bytes => (bytes - self.untouched) as f64 * 100.0 / bytes as f64,
+        }
+    }
+}
+
+pub struct Coverage {
+    usage: Vec<Usage>,
+    executions: Vec<u64>,
+    pc: u16, // The instruction running, whose bytes don't count as read
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            usage: vec![Usage::empty(); memory::MEM_SIZE],
+            executions: vec![0; memory::MEM_SIZE],
+            pc: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Coverage::new();
+    }
+
+    pub fn usage(&self, address: u16) -> Usage {
+        self.usage[address as usize]
+    }
+
+    // Times an instruction at the address executed
+    pub fn executions(&self, address: u16) -> u64 {
+        self.executions[address as usize]
+    }
+
+    pub fn range(&self, start: u16, end: u16) -> RangeStats {
+        let mut stats = RangeStats::default();
+        for address in start..=end {
+            let usage = self.usage(address);
+            stats.bytes += 1;
+            stats.opcode += usage.contains(Usage::OPCODE) as usize;
+            stats.operand += usage.contains(Usage::OPERAND) as usize;
+            stats.read += usage.contains(Usage::READ) as usize;
+            stats.written += usage.contains(Usage::WRITTEN) as usize;
+            stats.untouched += usage.is_empty() as usize;
+        }
+        stats
+    }
+
+    /* A table of how the bytes in each named range were used:
+
+        Range                Start   End   Bytes  Opcode Operand    Read Written Untouched    Used
+        main                 $0400 $04FF     256      12      10       0       0       234   8.59%
+    */
+    pub fn summary(&self, ranges: &[(&str, u16, u16)]) -> String {
+        let mut out = format!(
+            "{:<16} {:>9} {:>5} {:>7} {:>7} {:>7} {:>7} {:>7} {:>9} {:>7}\n",
+            "Range",
+            "Start",
+            "End",
+            "Bytes",
+            "Opcode",
+            "Operand",
+            "Read",
+            "Written",
+            "Untouched",
+            "Used"
+        );
+        for (name, start, end) in ranges {
+            let stats = self.range(*start, *end);
+            out += &format!(
+                "{:<16} {:>9} {:>5} {:>7} {:>7} {:>7} {:>7} {:>7} {:>9} {:>6.2}%\n",
+                name,
+                format!("${:04X}", start),
+                format!("${:04X}", end),
+                stats.bytes,
+                stats.opcode,
+                stats.operand,
+                stats.read,
+                stats.written,
+                stats.untouched,
+                stats.percent()
+            );
+        }
+        out
+    }
+
+    /* An lcov tracefile with a record for every file in the line table. A line's hit count is
+    the number of times its instruction executed, or 1 for a line of data that was read or
+    written (how often isn't kept). */
+    pub fn lcov(&self, lines: &LineTable) -> String {
+        let mut files: BTreeMap<&str, BTreeMap<u32, u64>> = BTreeMap::new();
+        for (file, line, start, end) in lines.iter() {
+            let hits = files.entry(file).or_default().entry(line).or_default();
+            *hits = (*hits).max(self.hits(start, end));
+        }
+
+        let mut out = String::new();
+        for (file, lines) in files {
+            out += &format!("TN:\nSF:{}\n", file);
+            for (line, hits) in &lines {
+                out += &format!("DA:{},{}\n", line, hits);
+            }
+            let hit = lines.values().filter(|hits| **hits > 0).count();
+            out += &format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), hit);
+        }
+        out
+    }
+
+    fn hits(&self, start: u16, end: u16) -> u64 {
+        let executed = (start..=end).map(|a| self.executions(a)).max().unwrap_or(0);
+        let touched = (start..=end).any(|a| !self.usage(a).is_empty());
+        match executed {
+            0 => touched as u64,
+            executed => executed,
+        }
+    }
+
+    // Whether a read is of the running instruction's own bytes
+    fn own_bytes(&self, address: usize) -> bool {
+        (address as u16).wrapping_sub(self.pc) < 3
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    // Start (or with None stop) keeping coverage
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn coverage_mut(&mut self) -> Option<&mut Coverage> {
+        self.coverage.as_mut()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // Called by tick() ahead of an instruction or interrupt
+    pub(crate) fn cover_start(&mut self) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.pc = self.registers.pc;
+        }
+    }
+
+    // Called by tick() after an instruction
+    pub(crate) fn cover_instruction(&mut self, opcode: u8, pc: u16) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.usage[pc as usize] |= Usage::OPCODE;
+            coverage.executions[pc as usize] += 1;
+            for i in 1..disasm::instr_len(opcode) {
+                coverage.usage[pc.wrapping_add(i as u16) as usize] |= Usage::OPERAND;
+            }
+        }
+    }
+
+    pub(crate) fn cover_read(&mut self, address: usize) {
+        if let Some(coverage) = &mut self.coverage {
+            if !coverage.own_bytes(address) {
+                coverage.usage[address] |= Usage::READ;
+            }
+        }
+    }
+
+    pub(crate) fn cover_write(&mut self, address: usize) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.usage[address] |= Usage::WRITTEN;
+        }
+    }
+}
@@ -0,0 +1,797 @@
+use crate::debugger::expr::Expr;
+use crate::debugger::*;
+use crate::debuginfo::DebugInfo;
+use crate::disasm::Instruction;
+use crate::monitor::Monitor;
+use crate::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+/* A Debug Adapter Protocol server, so 6502 programs can be debugged from editors that speak
+DAP (VS Code and friends). Messages are JSON with a Content-Length header, over stdin/stdout:
+
+    cargo run --bin dap
+
+A launch request loads the program, with these arguments:
+
+    "program"      the file to load (required)
+    "address"      where to load it, e.g. "$0400". Without one it's loaded as a PRG.
+    "debugInfo"    a cc65 debug file or VICE label file with the symbols and source lines
+    "start"        where execution starts, the load address by default. Can be a label.
+    "stopOnEntry"  stop before the first instruction
+
+The 6502 is a single thread whose stack frames come from the shadow call stack. Each frame has
+scopes for the registers, the flags and any memory being watched. Breakpoints can be set by
+source line (for addresses the debugger's line table knows about), on instructions and on memory
+(data breakpoints), all with conditions in the expression language, and an exception filter
+stops on undocumented opcodes. Stepping is by instruction. Evaluating in the debug console runs
+monitor commands, other contexts evaluate expressions. Stepping over and out, and the monitor's
+g, n, ret and u, run in slices like continue does, so they can be paused. */
+
+const THREAD: u64 = 1;
+
+// Cycles run between looking for requests while the CPU runs
+const SLICE_CYCLES: u64 = 20_000;
+
+// Variable references of the scopes
+const REGISTERS: u64 = 1;
+const FLAGS: u64 = 2;
+const WATCHED: u64 = 3;
+
+const FLAG_NAMES: [(&str, StatusFlags); 7] = [
+    ("N", StatusFlags::N),
+    ("V", StatusFlags::V),
+    ("B", StatusFlags::B),
+    ("D", StatusFlags::D),
+    ("I", StatusFlags::I),
+    ("Z", StatusFlags::Z),
+    ("C", StatusFlags::C),
+];
+
+pub struct DapServer {
+    pub monitor: Monitor,
+
+    running: bool,
+    resumed: bool,
+    goal: Goal, // Where the run stops, continuing as well as stepping over and out
+    stop_on_entry: bool,
+    done: bool,
+
+    // Breakpoints by the request that set them, which replaces them all the next time
+    source_breakpoints: HashMap<String, Vec<BreakpointId>>,
+    instruction_breakpoints: Vec<BreakpointId>,
+    data_breakpoints: Vec<BreakpointId>,
+    exception_breakpoints: Vec<BreakpointId>,
+
+    // Events to send after the current response
+    events: Vec<Value>,
+}
+
+type Reply = Result<Value, String>;
+
+impl DapServer {
+    pub fn new(monitor: Monitor) -> Self {
+        DapServer {
+            monitor,
+            running: false,
+            resumed: false,
+            goal: Goal::Continue,
+            stop_on_entry: false,
+            done: false,
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            data_breakpoints: Vec::new(),
+            exception_breakpoints: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // The client disconnected
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn debugger(&self) -> &Debugger<'static> {
+        &self.monitor.debugger
+    }
+
+    // Answer a request, followed by any events it caused. The messages don't have a seq yet.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let reply = match command {
+            "initialize" => self.initialize(),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => self.set_exception_breakpoints(args),
+            "dataBreakpointInfo" => self.data_breakpoint_info(args),
+            "setDataBreakpoints" => self.set_data_breakpoints(args),
+            "configurationDone" => {
+                match self.stop_on_entry {
+                    true => self.event("stopped", stopped_body("entry", None, None)),
+                    false => self.resume(),
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({"threads": [{"id": THREAD, "name": "6502"}]})),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => Ok(json!({"scopes": [
+                {"name": "Registers", "variablesReference": REGISTERS, "expensive": false},
+                {"name": "Flags", "variablesReference": FLAGS, "expensive": false},
+                {"name": "Watched memory", "variablesReference": WATCHED, "expensive": false},
+            ]})),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => {
+                self.resume();
+                Ok(json!({"allThreadsContinued": true}))
+            }
+            "next" => match self.debugger().step_over_goal() {
+                Some(goal) => {
+                    self.start(goal);
+                    Ok(Value::Null)
+                }
+                None => self.step(|d| d.step()),
+            },
+            "stepIn" => self.step(|d| d.step()),
+            "stepOut" => {
+                let goal = self.debugger().step_out_goal();
+                self.start(goal);
+                Ok(Value::Null)
+            }
+            "pause" => {
+                if self.running {
+                    self.running = false;
+                    self.event("stopped", stopped_body("pause", None, None));
+                }
+                Ok(Value::Null)
+            }
+            "disassemble" => self.disassemble(args),
+            "readMemory" => self.read_memory(args),
+            "disconnect" => {
+                self.running = false;
+                self.done = true;
+                Ok(Value::Null)
+            }
+            "terminate" => {
+                self.running = false;
+                self.event("terminated", Value::Null);
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": reply.is_ok(),
+        });
+        match reply {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+
+        let mut messages = vec![response];
+        messages.append(&mut self.events);
+        messages
+    }
+
+    // Keep a running CPU going for about 'cycles' cycles, returning a stopped event if it stops
+    pub fn run_slice(&mut self, cycles: u64) -> Vec<Value> {
+        if !self.running {
+            return Vec::new();
+        }
+
+        let resuming = std::mem::take(&mut self.resumed);
+        match self
+            .monitor
+            .debugger
+            .run_slice_to(&mut self.goal, cycles, resuming)
+        {
+            Stop::CycleLimit => {}
+            stop => self.stopped(stop),
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({"type": "event", "event": event});
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.events.push(message);
+    }
+
+    fn resume(&mut self) {
+        self.start(Goal::Continue);
+    }
+
+    // Run in slices like continuing does, so a pause gets through
+    fn start(&mut self, goal: Goal) {
+        self.running = true;
+        self.resumed = true;
+        self.goal = goal;
+    }
+
+    fn step(&mut self, step: impl FnOnce(&mut Debugger<'static>) -> Stop) -> Reply {
+        let stop = step(&mut self.monitor.debugger);
+        self.stopped(stop);
+        Ok(Value::Null)
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        self.running = false;
+        let body = match stop {
+            Stop::Breakpoint { id, .. } => stopped_body("breakpoint", Some(id), None),
+            Stop::Watchpoint { id, .. } => {
+                stopped_body("data breakpoint", Some(id), Some(stop.to_string()))
+            }
+            Stop::Opcode { id, .. } => stopped_body("exception", Some(id), Some(stop.to_string())),
+            Stop::Halted => stopped_body("exception", None, Some(stop.to_string())),
+            _ => stopped_body("step", None, None),
+        };
+        self.event("stopped", body);
+    }
+
+    fn initialize(&mut self) -> Reply {
+        self.event("initialized", Value::Null);
+        Ok(json!({
+            "supportsConfigurationDoneRequest": true,
+            "supportsConditionalBreakpoints": true,
+            "supportsInstructionBreakpoints": true,
+            "supportsDataBreakpoints": true,
+            "supportsDisassembleRequest": true,
+            "supportsReadMemoryRequest": true,
+            "supportsSetVariable": true,
+            "supportsSteppingGranularity": false,
+            "exceptionBreakpointFilters": [{
+                "filter": "undocumented",
+                "label": "Undocumented opcodes",
+                "default": false,
+            }],
+        }))
+    }
+
+    fn launch(&mut self, args: &Value) -> Reply {
+        let program = args["program"].as_str().ok_or("Missing program")?;
+        let data = fs::read(program).map_err(|e| format!("Can't read {}: {}", program, e))?;
+
+        // Without an address it's a PRG, which starts with its load address
+        let (address, data) = match &args["address"] {
+            Value::Null if data.len() >= 2 => (u16::from_le_bytes([data[0], data[1]]), &data[2..]),
+            Value::Null => return Err(format!("{} is too short for a PRG", program)),
+            address => (self.address(address)?, &data[..]),
+        };
+        self.monitor.ram.load(address, data);
+
+        // Symbols and source lines, so start can be a label
+        if let Some(path) = args["debugInfo"].as_str() {
+            let info = DebugInfo::load(path).map_err(|e| e.to_string())?;
+            self.monitor.debugger.add_debug_info(&info);
+        }
+
+        let start = match &args["start"] {
+            Value::Null => address,
+            start => self.address(start)?,
+        };
+        self.monitor.debugger.cpu.registers.pc = start;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    // A number, a "0x" memory reference or an expression
+    fn address(&self, value: &Value) -> Result<u16, String> {
+        if let Some(n) = value.as_u64() {
+            return u16::try_from(n).map_err(|_| format!("Bad address {}", n));
+        }
+        let text = value.as_str().ok_or("Bad address")?;
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u16::from_str_radix(hex, 16).map_err(|_| format!("Bad address '{}'", text));
+        }
+
+        let expr = Expr::parse(text).map_err(|e| e.to_string())?;
+        let value = self.debugger().eval(&expr).map_err(|e| e.to_string())?;
+        Ok(value as u16)
+    }
+
+    fn condition(args: &Value) -> Result<Option<Expr>, String> {
+        match args["condition"].as_str() {
+            Some(text) if !text.trim().is_empty() => {
+                Expr::parse(text).map(Some).map_err(|e| e.to_string())
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn add_breakpoint(&mut self, kind: BreakKind, condition: Option<Expr>) -> BreakpointId {
+        let id = self.monitor.debugger.add(kind);
+        self.monitor.debugger.set_condition(id, condition);
+        id
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Reply {
+        let source = &args["source"];
+        let path = source["path"]
+            .as_str()
+            .or(source["name"].as_str())
+            .ok_or("Missing source")?
+            .to_string();
+
+        for id in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            self.monitor.debugger.remove(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+            let found = self.debugger().lines.address(&path, line);
+            let condition = Self::condition(requested);
+
+            breakpoints.push(match (found, condition) {
+                (Some((address, line)), Ok(condition)) => {
+                    let id = self.add_breakpoint(BreakKind::Exec(address), condition);
+                    ids.push(id);
+                    json!({
+                        "id": id,
+                        "verified": true,
+                        "line": line,
+                        "source": source,
+                        "instructionReference": memory_reference(address),
+                    })
+                }
+                (None, _) => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code for this line",
+                }),
+                (_, Err(message)) => json!({"verified": false, "line": line, "message": message}),
+            });
+        }
+
+        self.source_breakpoints.insert(path, ids);
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Reply {
+        for id in std::mem::take(&mut self.instruction_breakpoints) {
+            self.monitor.debugger.remove(id);
+        }
+
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let offset = requested["offset"].as_i64().unwrap_or(0);
+            let address = self
+                .address(&requested["instructionReference"])
+                .map(|a| a.wrapping_add(offset as u16));
+
+            breakpoints.push(match (address, Self::condition(requested)) {
+                (Ok(address), Ok(condition)) => {
+                    let id = self.add_breakpoint(BreakKind::Exec(address), condition);
+                    self.instruction_breakpoints.push(id);
+                    json!({
+                        "id": id,
+                        "verified": true,
+                        "instructionReference": memory_reference(address),
+                    })
+                }
+                (Err(message), _) | (_, Err(message)) => {
+                    json!({"verified": false, "message": message})
+                }
+            });
+        }
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn set_exception_breakpoints(&mut self, args: &Value) -> Reply {
+        for id in std::mem::take(&mut self.exception_breakpoints) {
+            self.monitor.debugger.remove(id);
+        }
+
+        let filters = args["filters"].as_array().into_iter().flatten();
+        if filters
+            .filter_map(Value::as_str)
+            .any(|f| f == "undocumented")
+        {
+            let id = self.add_breakpoint(BreakKind::Opcode(OpcodeMatch::Undocumented), None);
+            self.exception_breakpoints.push(id);
+        }
+        Ok(Value::Null)
+    }
+
+    /* Memory can be watched from a watched memory variable, or from an expression (hovering
+    over a label, say), but not from registers and flags */
+    fn data_breakpoint_info(&self, args: &Value) -> Reply {
+        let name = args["name"].as_str().unwrap_or_default();
+        let address = match args["variablesReference"].as_u64() {
+            Some(WATCHED) => name.split('-').next().map(|a| self.address(&json!(a))),
+            Some(_) => None,
+            None => Some(self.address(&json!(name))),
+        };
+
+        Ok(match address {
+            Some(Ok(address)) => json!({
+                "dataId": format!("${:04X}", address),
+                "description": format!("Memory at ${:04X}", address),
+                "accessTypes": ["read", "write", "readWrite"],
+                "canPersist": true,
+            }),
+            _ => json!({"dataId": null, "description": "Not an address"}),
+        })
+    }
+
+    fn set_data_breakpoints(&mut self, args: &Value) -> Reply {
+        for id in std::mem::take(&mut self.data_breakpoints) {
+            self.monitor.debugger.remove(id);
+        }
+
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let kind = match requested["accessType"].as_str() {
+                Some("read") => WatchKind::Read,
+                Some("readWrite") => WatchKind::Access,
+                _ => WatchKind::Write,
+            };
+
+            breakpoints.push(
+                match (
+                    self.address(&requested["dataId"]),
+                    Self::condition(requested),
+                ) {
+                    (Ok(address), Ok(condition)) => {
+                        let watch = BreakKind::Watch {
+                            start: address,
+                            end: address,
+                            kind,
+                        };
+                        let id = self.add_breakpoint(watch, condition);
+                        self.data_breakpoints.push(id);
+                        json!({"id": id, "verified": true})
+                    }
+                    (Err(message), _) | (_, Err(message)) => {
+                        json!({"verified": false, "message": message})
+                    }
+                },
+            );
+        }
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    // The current instruction, then the JSR (or interrupted instruction) of every frame
+    fn stack_trace(&self, args: &Value) -> Reply {
+        let debugger = self.debugger();
+        let mut locations = vec![debugger.cpu.registers.pc];
+        if let Some(call_stack) = debugger.cpu.call_stack() {
+            locations.extend(call_stack.frames().iter().rev().map(|f| f.caller));
+        }
+
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64() {
+            Some(0) | None => locations.len(),
+            Some(levels) => levels as usize,
+        };
+
+        let frames: Vec<Value> = locations
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, address)| {
+                let name = debugger
+                    .symbols
+                    .describe(*address)
+                    .unwrap_or_else(|| format!("${:04X}", address));
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": memory_reference(*address),
+                });
+                if let Some((file, line)) = debugger.lines.location(*address) {
+                    frame["source"] = source(file);
+                    frame["line"] = line.into();
+                    frame["column"] = 1.into();
+                }
+                frame
+            })
+            .collect();
+
+        Ok(json!({"stackFrames": frames, "totalFrames": locations.len()}))
+    }
+
+    fn variables(&self, args: &Value) -> Reply {
+        let cpu = &self.debugger().cpu;
+        let r = &cpu.registers;
+        let byte = |name: &str, value: u8| variable(name, format!("${:02X}", value));
+
+        let variables = match args["variablesReference"].as_u64() {
+            Some(REGISTERS) => vec![
+                byte("A", r.a),
+                byte("X", r.x),
+                byte("Y", r.y),
+                byte("SP", r.s),
+                variable("PC", format!("${:04X}", r.pc)),
+                byte("P", r.p.bits()),
+            ],
+            Some(FLAGS) => FLAG_NAMES
+                .iter()
+                .map(|(name, flag)| variable(name, (r.p.contains(*flag) as u8).to_string()))
+                .collect(),
+            Some(WATCHED) => self.watched(),
+            _ => return Err("Unknown variables reference".to_string()),
+        };
+        Ok(json!({"variables": variables}))
+    }
+
+    // The memory of every watchpoint, whoever set it
+    fn watched(&self) -> Vec<Value> {
+        let cpu = &self.debugger().cpu;
+        self.debugger()
+            .breakpoints()
+            .iter()
+            .filter_map(|b| match b.kind {
+                BreakKind::Watch { start, end, .. } => Some((start, end)),
+                _ => None,
+            })
+            .map(|(start, end)| {
+                let bytes: Vec<String> = (start..=end.min(start.saturating_add(15)))
+                    .map(|a| match cpu.peek(a as usize) {
+                        Some(value) => format!("{:02X}", value),
+                        None => "??".to_string(),
+                    })
+                    .collect();
+                let name = match start == end {
+                    true => format!("${:04X}", start),
+                    false => format!("${:04X}-${:04X}", start, end),
+                };
+                let mut var = variable(&name, bytes.join(" "));
+                var["memoryReference"] = memory_reference(start).into();
+                var
+            })
+            .collect()
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Reply {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = self.address(&args["value"])?;
+        let r = &mut self.monitor.debugger.cpu.registers;
+
+        match args["variablesReference"].as_u64() {
+            Some(REGISTERS) => match name {
+                "A" => r.a = value as u8,
+                "X" => r.x = value as u8,
+                "Y" => r.y = value as u8,
+                "SP" => r.s = value as u8,
+                "PC" => r.pc = value,
+                "P" => r.p = StatusFlags::from_bits_retain(value as u8),
+                _ => return Err(format!("Unknown register {}", name)),
+            },
+            Some(FLAGS) => {
+                let (_, flag) = FLAG_NAMES
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .ok_or(format!("Unknown flag {}", name))?;
+                r.p.set(*flag, value != 0);
+            }
+            _ => return Err("Only registers and flags can be set".to_string()),
+        }
+
+        let value = match (args["variablesReference"].as_u64(), name) {
+            (Some(FLAGS), _) => ((value != 0) as u8).to_string(),
+            (_, "PC") => format!("${:04X}", value),
+            _ => format!("${:02X}", value as u8),
+        };
+        Ok(json!({"value": value}))
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Reply {
+        let expression = args["expression"].as_str().unwrap_or_default();
+
+        if args["context"].as_str() == Some("repl") {
+            // Commands that run the program run in slices, and report back with a stopped event
+            if let Some(goal) = self
+                .monitor
+                .run_goal(expression)
+                .map_err(|e| e.to_string())?
+            {
+                self.start(goal);
+                return Ok(json!({"result": "", "variablesReference": 0}));
+            }
+
+            let output = self
+                .monitor
+                .execute(expression)
+                .map_err(|e| e.to_string())?;
+            return Ok(json!({"result": output.trim_end(), "variablesReference": 0}));
+        }
+
+        let expr = Expr::parse(expression).map_err(|e| e.to_string())?;
+        let value = self.debugger().eval(&expr).map_err(|e| e.to_string())?;
+        Ok(json!({
+            "result": format!("${:X} ({})", value, value),
+            "variablesReference": 0,
+            "memoryReference": memory_reference(value as u16),
+        }))
+    }
+
+    fn disassemble(&self, args: &Value) -> Reply {
+        let debugger = self.debugger();
+        let peek = |a: u16| debugger.cpu.peek(a as usize).unwrap_or(0);
+
+        let base = self.address(&args["memoryReference"])?;
+        let base = base.wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+        let offset = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_u64().unwrap_or(0) as usize;
+
+        let mut address = base;
+        if offset < 0 {
+            address = disasm::start_before(base, offset.unsigned_abs() as usize, peek);
+        }
+        for _ in 0..offset.max(0) {
+            address = Instruction::fetch(address, peek).next();
+        }
+
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let instr = Instruction::fetch(address, peek);
+            let bytes: Vec<String> = instr.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+            let mut line = json!({
+                "address": memory_reference(address),
+                "instructionBytes": bytes.join(" "),
+                "instruction": debugger.disassemble(&instr),
+            });
+            if let Some(name) = debugger.symbols.name(address) {
+                line["symbol"] = name.into();
+            }
+            if let Some((file, number)) = debugger.lines.location(address) {
+                line["location"] = source(file);
+                line["line"] = number.into();
+            }
+            instructions.push(line);
+            address = instr.next();
+        }
+        Ok(json!({"instructions": instructions}))
+    }
+
+    fn read_memory(&self, args: &Value) -> Reply {
+        let address = self.address(&args["memoryReference"])?;
+        let address = address.wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+        let count = args["count"]
+            .as_u64()
+            .unwrap_or(0)
+            .min(0x10000 - address as u64);
+
+        let cpu = &self.debugger().cpu;
+        let data: Option<Vec<u8>> = (0..count)
+            .map(|i| cpu.peek(address as usize + i as usize))
+            .collect();
+        let data = data.ok_or("The CPU has no peek callback")?;
+        Ok(json!({"address": memory_reference(address), "data": base64(&data)}))
+    }
+}
+
+fn stopped_body(reason: &str, id: Option<BreakpointId>, text: Option<String>) -> Value {
+    let mut body = json!({"reason": reason, "threadId": THREAD, "allThreadsStopped": true});
+    if let Some(id) = id {
+        body["hitBreakpointIds"] = json!([id]);
+    }
+    if let Some(text) = text {
+        body["text"] = text.into();
+    }
+    body
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({"name": name, "value": value, "variablesReference": 0})
+}
+
+fn source(path: &str) -> Value {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    json!({"name": name, "path": path})
+}
+
+fn memory_reference(address: u16) -> String {
+    format!("0x{:04X}", address)
+}
+
+fn base64(data: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(match i <= chunk.len() {
+                true => DIGITS[(bits >> (18 - 6 * i) & 0x3F) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    out
+}
+
+// A message with its Content-Length header, None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() && len.is_some() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; len.unwrap()];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/* Serve a client until it disconnects or the input ends. Requests are read on their own thread
+so a running program can be paused. */
+pub fn serve(
+    server: &mut DapServer,
+    input: impl Read + Send + 'static,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut seq = 0;
+    while !server.is_done() {
+        let messages = match server.is_running() {
+            true => match requests.try_recv() {
+                Ok(request) => server.handle(&request),
+                Err(TryRecvError::Empty) => server.run_slice(SLICE_CYCLES),
+                Err(TryRecvError::Disconnected) => break,
+            },
+            false => match requests.recv() {
+                Ok(request) => server.handle(&request),
+                Err(_) => break,
+            },
+        };
+
+        for mut message in messages {
+            seq += 1;
+            message["seq"] = seq.into();
+            write_message(&mut output, &message)?;
+        }
+    }
+    Ok(())
+}
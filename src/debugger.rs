@@ -0,0 +1,625 @@
+use super::*;
+use callstack::CallStack;
+use debuginfo::DebugInfo;
+use disasm::Instruction;
+use expr::{Context, EvalError, Expr, HitContext, Register};
+use std::fmt;
+use symbols::{LineTable, SymbolTable};
+
+pub mod expr;
+
+/* A debugging layer around the CPU. Breakpoints stop execution before the instruction at their
+address runs, opcode breakpoints before a matching instruction runs, and watchpoints right after
+the instruction that accessed the watched memory. Whatever stopped the CPU is reported back from
+step()/cont() as a Stop, so none of this has to live in the memory callbacks.
+
+Opcode breakpoints look at the instruction ahead without side effects, so they need the CPU to
+have a peek callback (a CPU made by memory::Ram has one). Without one they never fire.
+
+Any breakpoint can be given a condition (see the expr module), in which case it only stops when
+the condition holds. Its hit count still goes up every time it's reached.
+
+Besides single steps there's step_over(), step_out() and run_to(), which keep track of the stack
+pointer so recursion and interrupts firing in the middle of them don't throw them off.
+
+With execution history enabled on the CPU (see the history module) step_back() and
+reverse_cont() go the other way. Going backwards, watchpoints only see writes. */
+
+pub type BreakpointId = u32;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+// Instructions run between asking whether an interruptible run should stop
+const INTERRUPT_CHECK: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Either
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpcodeMatch {
+    Opcode(u8),
+    Undocumented,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakKind {
+    Exec(u16),
+    ExecRange {
+        start: u16,
+        end: u16,
+    }, // Inclusive range
+    Watch {
+        start: u16,
+        end: u16,
+        kind: WatchKind,
+    }, // Inclusive range
+    Opcode(OpcodeMatch),
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub kind: BreakKind,
+    pub enabled: bool,
+
+    // Removed once it has been hit
+    pub temporary: bool,
+
+    pub condition: Option<Expr>,
+    pub hits: u64,
+}
+
+// What a run done in slices (see Debugger::run_slice_to()) is after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
+    Continue, // Only stops on a breakpoint or the like
+
+    // Back at 'pc' with the stack no deeper than 's', after a JSR or an interrupt
+    Return { pc: u16, s: u8, interrupt: bool },
+
+    StepOut { s: u8 }, // An RTS or RTI pops the stack above 's'
+    Address(u16),
+}
+
+impl Goal {
+    // Checked after every instruction
+    fn reached(&self, cpu: &Cpu6502) -> bool {
+        match *self {
+            Goal::Continue => false,
+            Goal::Return { pc, s, .. } => {
+                cpu.registers.pc == pc && stack_depth(cpu.registers.s, s) <= 0
+            }
+            Goal::StepOut { s } => match cpu.last_event() {
+                TickEvent::Instruction { opcode, .. } => {
+                    (opcode == RTS || opcode == RTI) && stack_depth(cpu.registers.s, s) < 0
+                }
+                _ => false,
+            },
+            Goal::Address(address) => cpu.registers.pc == address,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Step, // A single step finished without anything else happening
+    Breakpoint {
+        id: BreakpointId,
+        pc: u16,
+    },
+    Watchpoint {
+        id: BreakpointId,
+        access: BusAccess,
+    },
+    Opcode {
+        id: BreakpointId,
+        pc: u16,
+        opcode: u8,
+    },
+    Halted,
+    CycleLimit,
+    Interrupted,  // Asked to stop from outside, see cont_interruptible()
+    HistoryStart, // Went back as far as the execution history goes
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Step => write!(f, "Step"),
+            Stop::Breakpoint { id, pc } => write!(f, "Breakpoint {} at ${:04X}", id, pc),
+            Stop::Watchpoint { id, access } => {
+                let what = match access.kind {
+                    AccessKind::Write => "write",
+                    _ => "read",
+                };
+                write!(
+                    f,
+                    "Watchpoint {}: {} ${:02X} at ${:04X}",
+                    id, what, access.value, access.address
+                )
+            }
+            Stop::Opcode { id, pc, opcode } => {
+                write!(f, "Opcode break {}: ${:02X} at ${:04X}", id, opcode, pc)
+            }
+            Stop::Halted => write!(f, "CPU halted"),
+            Stop::CycleLimit => write!(f, "Cycle limit reached"),
+            Stop::Interrupted => write!(f, "Interrupted"),
+            Stop::HistoryStart => write!(f, "Reached the start of the history"),
+        }
+    }
+}
+
+// The CPU together with the debugger's symbols, for evaluating expressions
+pub struct DebugContext<'d, 'a> {
+    pub cpu: &'d Cpu6502<'a>,
+    pub symbols: &'d SymbolTable,
+}
+
+impl Context for DebugContext<'_, '_> {
+    fn register(&self, reg: Register) -> Option<i64> {
+        self.cpu.register(reg)
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Context::peek(self.cpu, address)
+    }
+
+    fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols.address(name).map(i64::from)
+    }
+}
+
+pub struct Debugger<'a> {
+    pub cpu: Cpu6502<'a>,
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+    breakpoints: Vec<Breakpoint>,
+    next_id: BreakpointId,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(mut cpu: Cpu6502<'a>) -> Self {
+        // Watchpoints are checked against the accesses of each instruction
+        cpu.set_access_log(true);
+        cpu.set_call_tracking(true);
+
+        Debugger {
+            cpu,
+            symbols: SymbolTable::new(),
+            lines: LineTable::new(),
+            breakpoints: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn add(&mut self, kind: BreakKind) -> BreakpointId {
+        self.insert(kind, false)
+    }
+
+    // A breakpoint that's removed the first time it's hit
+    pub fn add_temporary(&mut self, kind: BreakKind) -> BreakpointId {
+        self.insert(kind, true)
+    }
+
+    fn insert(&mut self, kind: BreakKind, temporary: bool) -> BreakpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            kind,
+            enabled: true,
+            temporary,
+            condition: None,
+            hits: 0,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn enable(&mut self, id: BreakpointId, enabled: bool) -> bool {
+        match self.breakpoints.iter_mut().find(|b| b.id == id) {
+            Some(b) => {
+                b.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_condition(&mut self, id: BreakpointId, condition: Option<Expr>) -> bool {
+        match self.breakpoints.iter_mut().find(|b| b.id == id) {
+            Some(b) => {
+                b.condition = condition;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn breakpoint(&self, id: BreakpointId) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|b| b.id == id)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn context(&self) -> DebugContext<'_, 'a> {
+        DebugContext {
+            cpu: &self.cpu,
+            symbols: &self.symbols,
+        }
+    }
+
+    pub fn eval(&self, expr: &Expr) -> Result<i64, EvalError> {
+        expr.eval(&self.context())
+    }
+
+    // An instruction with addresses in its operand replaced by symbols where there's one
+    pub fn disassemble(&self, instr: &Instruction) -> String {
+        let operand = instr.operand_with(|address, zero_page| match self.symbols.name(address) {
+            Some(name) => name.to_string(),
+            None => disasm::hex_address(address, zero_page),
+        });
+        match operand.is_empty() {
+            true => instr.mnemonic().to_uppercase(),
+            false => format!("{} {}", instr.mnemonic().to_uppercase(), operand),
+        }
+    }
+
+    // Where the CPU is now and the calls that led there, labelled with our symbols and lines
+    pub fn backtrace(&self) -> String {
+        let pc = self.cpu.registers.pc;
+        let (symbols, lines) = (Some(&self.symbols), Some(&self.lines));
+        match self.cpu.call_stack() {
+            Some(call_stack) => call_stack.backtrace(pc, symbols, lines),
+            None => CallStack::new().backtrace(pc, symbols, lines),
+        }
+    }
+
+    // Symbols and source lines from ld65 or a label file, on top of any we already have
+    pub fn add_debug_info(&mut self, info: &DebugInfo) {
+        for (name, address) in info.symbols.iter() {
+            self.symbols.insert(name, address);
+        }
+        self.lines.extend(&info.lines);
+    }
+
+    // "draw_sprite+3 (main.s:42)", or whichever of the two we know, for showing an address
+    pub fn describe(&self, address: u16) -> Option<String> {
+        match (self.symbols.describe(address), self.lines.describe(address)) {
+            (Some(symbol), Some(line)) => Some(format!("{} ({})", symbol, line)),
+            (symbol, line) => symbol.or(line),
+        }
+    }
+
+    // Execute a single instruction (or interrupt), reporting any watchpoint it triggered
+    pub fn step(&mut self) -> Stop {
+        if self.cpu.is_halted() {
+            return Stop::Halted;
+        }
+
+        self.cpu.tick();
+        match self.check_watchpoints() {
+            Some(stop) => stop,
+            None if self.cpu.is_halted() => Stop::Halted,
+            None => Stop::Step,
+        }
+    }
+
+    /* Run until something stops the CPU. The instruction at the current PC always executes, so
+    continuing from a breakpoint doesn't immediately stop on it again. */
+    pub fn cont(&mut self, max_cycles: Option<u64>) -> Stop {
+        self.run_until(max_cycles, |_| false)
+    }
+
+    /* Run for about 'max_cycles' cycles as one slice of a longer run, for front ends that need
+    to do other things in between. The first slice of a run ('resuming') executes the instruction
+    at the PC like cont() does, later ones check breakpoints first since the previous slice may
+    have ended right in front of one. */
+    pub fn run_slice(&mut self, max_cycles: u64, resuming: bool) -> Stop {
+        self.run_slice_to(&mut Goal::Continue, max_cycles, resuming)
+    }
+
+    /* Same as run_slice(), but for a step over, step out or run to done in slices. Getting to
+    the goal is reported as Stop::Step, running out of cycles as Stop::CycleLimit like always.
+    A step over an interrupt moves the goal on to the instruction after it. */
+    pub fn run_slice_to(&mut self, goal: &mut Goal, max_cycles: u64, resuming: bool) -> Stop {
+        if !resuming {
+            if let Some(stop) = self.check_breakpoints() {
+                return stop;
+            }
+        }
+
+        let start = self.cpu.total_cycles();
+        loop {
+            let used = self.cpu.total_cycles() - start;
+            if used >= max_cycles {
+                return Stop::CycleLimit;
+            }
+
+            let target = *goal;
+            let stop = self.run_until(Some(max_cycles - used), |cpu| target.reached(cpu));
+            match target {
+                Goal::Return {
+                    interrupt: true, ..
+                } if stop == Stop::Step => match self.step_over_goal() {
+                    Some(next) => *goal = next,
+                    None => return self.step(),
+                },
+                _ => return stop,
+            }
+        }
+    }
+
+    /* Where a step over from here is headed, None when the next instruction isn't a JSR (and no
+    interrupt is about to be serviced) so a plain step() does it */
+    pub fn step_over_goal(&self) -> Option<Goal> {
+        let pc = self.cpu.registers.pc;
+        let s = self.cpu.registers.s;
+        match self.cpu.interrupt_pending() {
+            true => Some(Goal::Return {
+                pc,
+                s,
+                interrupt: true,
+            }),
+            false if self.cpu.peek(pc as usize) == Some(JSR) => Some(Goal::Return {
+                pc: pc.wrapping_add(3),
+                s,
+                interrupt: false,
+            }),
+            false => None,
+        }
+    }
+
+    pub fn step_out_goal(&self) -> Goal {
+        Goal::StepOut {
+            s: self.cpu.registers.s,
+        }
+    }
+
+    /* Same as cont(), but every so often 'interrupted' is asked whether to stop. That's how a
+    front end can let the user break into a running program. */
+    pub fn cont_interruptible(
+        &mut self,
+        max_cycles: Option<u64>,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> Stop {
+        let mut count: u32 = 0;
+        let stop = self.run_until(max_cycles, |_| {
+            count += 1;
+            if count < INTERRUPT_CHECK {
+                return false;
+            }
+            count = 0;
+            interrupted()
+        });
+        match stop {
+            Stop::Step => Stop::Interrupted,
+            stop => stop,
+        }
+    }
+
+    /* Like step(), but a JSR runs until the subroutine returns to the instruction after it.
+    The stack pointer has to be back where it was too, so a recursive call returning to the same
+    address doesn't count. An interrupt that's about to be serviced is stepped over the same way
+    before the instruction itself. Breakpoints hit on the way still stop. */
+    pub fn step_over(&mut self, max_cycles: Option<u64>) -> Stop {
+        loop {
+            let Some(goal) = self.step_over_goal() else {
+                return self.step();
+            };
+            let stop = self.run_until(max_cycles, |cpu| goal.reached(cpu));
+
+            match goal {
+                Goal::Return {
+                    interrupt: true, ..
+                } if stop == Stop::Step => {}
+                _ => return stop,
+            }
+        }
+    }
+
+    /* Run until the current subroutine (or interrupt handler) returns, i.e. until an RTS or RTI
+    pops the stack above where it is now. */
+    pub fn step_out(&mut self, max_cycles: Option<u64>) -> Stop {
+        let goal = self.step_out_goal();
+        self.run_until(max_cycles, |cpu| goal.reached(cpu))
+    }
+
+    // Run until the PC gets to 'address' (or something else stops the CPU first)
+    pub fn run_to(&mut self, address: u16, max_cycles: Option<u64>) -> Stop {
+        self.run_until(max_cycles, |cpu| Goal::Address(address).reached(cpu))
+    }
+
+    // Keep stepping until 'done' is true after an instruction, which is reported as Stop::Step
+    fn run_until(
+        &mut self,
+        max_cycles: Option<u64>,
+        mut done: impl FnMut(&Cpu6502<'a>) -> bool,
+    ) -> Stop {
+        let start = self.cpu.total_cycles();
+        let mut first = true;
+
+        loop {
+            if !first {
+                if let Some(stop) = self.check_breakpoints() {
+                    return stop;
+                }
+            }
+            first = false;
+
+            match self.step() {
+                Stop::Step if done(&self.cpu) => return Stop::Step,
+                Stop::Step => {}
+                stop => return stop,
+            }
+
+            if let Some(max) = max_cycles {
+                if self.cpu.total_cycles() - start >= max {
+                    return Stop::CycleLimit;
+                }
+            }
+        }
+    }
+
+    // Undo the last instruction
+    pub fn step_back(&mut self) -> Stop {
+        match self.cpu.step_back() {
+            true => Stop::Step,
+            false => Stop::HistoryStart,
+        }
+    }
+
+    /* Go back until a breakpoint is in front of the CPU again or an instruction that wrote to a
+    watched address is undone, which leaves the CPU in front of that instruction. */
+    pub fn reverse_cont(&mut self) -> Stop {
+        loop {
+            let writes = match self.cpu.history().and_then(|h| h.previous_writes()) {
+                Some(writes) => writes.to_vec(),
+                None => return Stop::HistoryStart,
+            };
+            self.cpu.step_back();
+
+            // Latest first, read-modify-write instructions write twice
+            for write in writes.into_iter().rev() {
+                let index = self.triggered(|kind| match *kind {
+                    BreakKind::Watch { start, end, kind } => {
+                        (start..=end).contains(&write.address) && kind != WatchKind::Read
+                    }
+                    _ => false,
+                });
+                if let Some(index) = index {
+                    let access = BusAccess {
+                        address: write.address,
+                        value: write.new,
+                        kind: AccessKind::Write,
+                    };
+                    let id = self.hit(index);
+                    return Stop::Watchpoint { id, access };
+                }
+            }
+
+            if let Some(stop) = self.check_breakpoints() {
+                return stop;
+            }
+        }
+    }
+
+    /* Breakpoints that fire before the next instruction executes. cont() checks these itself,
+    this is for callers that run the CPU in slices and need to check when picking up again. */
+    pub fn check_breakpoints(&mut self) -> Option<Stop> {
+        // The next tick services an interrupt, so the instruction at PC doesn't run yet
+        if self.cpu.interrupt_pending() {
+            return None;
+        }
+
+        /* Only opcode breakpoints need to look at memory, so PC breakpoints still work on a CPU
+        without a peek callback */
+        let pc = self.cpu.registers.pc;
+        let opcode = match self
+            .breakpoints
+            .iter()
+            .any(|b| matches!(b.kind, BreakKind::Opcode(_)))
+        {
+            true => self.cpu.peek(pc as usize),
+            false => None,
+        };
+
+        let index = self.triggered(|kind| match (*kind, opcode) {
+            (BreakKind::Exec(addr), _) => addr == pc,
+            (BreakKind::ExecRange { start, end }, _) => (start..=end).contains(&pc),
+            (BreakKind::Opcode(OpcodeMatch::Opcode(op)), Some(opcode)) => op == opcode,
+            (BreakKind::Opcode(OpcodeMatch::Undocumented), Some(opcode)) => {
+                disasm::is_illegal(opcode)
+            }
+            _ => false,
+        })?;
+
+        let kind = self.breakpoints[index].kind;
+        let id = self.hit(index);
+        Some(match (kind, opcode) {
+            (BreakKind::Opcode(_), Some(opcode)) => Stop::Opcode { id, pc, opcode },
+            _ => Stop::Breakpoint { id, pc },
+        })
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        for a in 0..self.cpu.last_accesses().len() {
+            let access = self.cpu.last_accesses()[a];
+            let index = self.triggered(|kind| match *kind {
+                BreakKind::Watch { start, end, kind } => {
+                    (start..=end).contains(&access.address)
+                        && match access.kind {
+                            AccessKind::Fetch => false,
+                            AccessKind::Read => kind != WatchKind::Write,
+                            AccessKind::Write => kind != WatchKind::Read,
+                        }
+                }
+                _ => false,
+            });
+
+            if let Some(index) = index {
+                let id = self.hit(index);
+                return Some(Stop::Watchpoint { id, access });
+            }
+        }
+        None
+    }
+
+    /* Find the first enabled breakpoint that matches and whose condition holds, counting hits
+    along the way. A condition that can't be evaluated stops as well so it gets noticed. */
+    fn triggered(&mut self, matches: impl Fn(&BreakKind) -> bool) -> Option<usize> {
+        let ctx = DebugContext {
+            cpu: &self.cpu,
+            symbols: &self.symbols,
+        };
+        self.breakpoints.iter_mut().position(|b| {
+            if !b.enabled || !matches(&b.kind) {
+                return false;
+            }
+
+            b.hits += 1;
+            match &b.condition {
+                Some(condition) => {
+                    let ctx = HitContext {
+                        inner: &ctx,
+                        hits: b.hits,
+                    };
+                    condition.is_true(&ctx).unwrap_or(true)
+                }
+                None => true,
+            }
+        })
+    }
+
+    // Remove the breakpoint that stopped the CPU if it was temporary
+    fn hit(&mut self, index: usize) -> BreakpointId {
+        let b = &self.breakpoints[index];
+        let id = b.id;
+        if b.temporary {
+            self.breakpoints.remove(index);
+        }
+        id
+    }
+}
+
+// How much deeper the stack is at 's' than at 'base' (negative once it's been popped above it)
+fn stack_depth(s: u8, base: u8) -> i8 {
+    base.wrapping_sub(s) as i8
+}
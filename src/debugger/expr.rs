@@ -0,0 +1,454 @@
+use crate::*;
+use std::fmt;
+
+/* A small expression language over CPU state and memory, used for breakpoint conditions and for
+evaluating things from a monitor prompt:
+
+    A == $FF && P.C
+    [$D012] > 100
+    word[$FE] == $C000 || hits >= 10
+
+Numbers are decimal, $hex or %binary. Registers are A, X, Y, S (or SP), PC and P, with P.N,
+P.V, P.B, P.D, P.I, P.Z and P.C for the individual flags. [addr] reads a byte and word[addr] a
+little endian word, both through the side-effect free peek. 'hits' is the hit count of the
+breakpoint the expression belongs to and 'cycles' the total cycle count. The operators are the
+C ones (|| && | ^ & == != < <= > >= << >> + - * / % ! ~ and unary -), with non-zero meaning
+true. Any other name is looked up as a symbol. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    Pc,
+    P,
+    Flag(StatusFlags),
+    Cycles,
+    Hits,
+}
+
+// Where expressions get their values from
+pub trait Context {
+    fn register(&self, reg: Register) -> Option<i64>;
+    fn peek(&self, address: u16) -> Option<u8>;
+
+    fn symbol(&self, _name: &str) -> Option<i64> {
+        None
+    }
+}
+
+impl Context for Cpu6502<'_> {
+    fn register(&self, reg: Register) -> Option<i64> {
+        let r = &self.registers;
+        Some(match reg {
+            Register::A => r.a as i64,
+            Register::X => r.x as i64,
+            Register::Y => r.y as i64,
+            Register::S => r.s as i64,
+            Register::Pc => r.pc as i64,
+            Register::P => r.p.bits() as i64,
+            Register::Flag(flag) => r.p.contains(flag) as i64,
+            Register::Cycles => self.total_cycles() as i64,
+            Register::Hits => return None,
+        })
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Cpu6502::peek(self, address as usize)
+    }
+}
+
+// Adds the hit count of a breakpoint to another context
+pub struct HitContext<'c, C: Context + ?Sized> {
+    pub inner: &'c C,
+    pub hits: u64,
+}
+
+impl<C: Context + ?Sized> Context for HitContext<'_, C> {
+    fn register(&self, reg: Register) -> Option<i64> {
+        match reg {
+            Register::Hits => Some(self.hits as i64),
+            _ => self.inner.register(reg),
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.inner.peek(address)
+    }
+
+    fn symbol(&self, name: &str) -> Option<i64> {
+        self.inner.symbol(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnOp {
+    Not,
+    Neg,
+    Invert,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Symbol(String),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(UnOp, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    UnknownSymbol(String),
+    Unavailable(Register),
+    NoPeek,
+    DivideByZero,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnknownSymbol(name) => write!(f, "Unknown symbol '{}'", name),
+            EvalError::Unavailable(reg) => write!(f, "{:?} isn't available here", reg),
+            EvalError::NoPeek => write!(f, "Memory can't be peeked"),
+            EvalError::DivideByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let root = parser.expression(0)?;
+        parser.skip_space();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("Unexpected input"));
+        }
+
+        Ok(Expr {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, ctx: &(impl Context + ?Sized)) -> Result<i64, EvalError> {
+        eval(&self.root, ctx)
+    }
+
+    // Evaluate as a condition, non-zero meaning true
+    pub fn is_true(&self, ctx: &(impl Context + ?Sized)) -> Result<bool, EvalError> {
+        self.eval(ctx).map(|v| v != 0)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval(node: &Node, ctx: &(impl Context + ?Sized)) -> Result<i64, EvalError> {
+    let peek = |addr: i64| ctx.peek(addr as u16).ok_or(EvalError::NoPeek);
+
+    Ok(match node {
+        Node::Number(n) => *n,
+        Node::Register(reg) => ctx.register(*reg).ok_or(EvalError::Unavailable(*reg))?,
+        Node::Symbol(name) => ctx
+            .symbol(name)
+            .ok_or_else(|| EvalError::UnknownSymbol(name.clone()))?,
+        Node::Byte(addr) => peek(eval(addr, ctx)?)? as i64,
+        Node::Word(addr) => {
+            let addr = eval(addr, ctx)?;
+            (peek(addr + 1)? as i64) << 8 | peek(addr)? as i64
+        }
+        Node::Unary(op, value) => {
+            let value = eval(value, ctx)?;
+            match op {
+                UnOp::Not => (value == 0) as i64,
+                UnOp::Neg => value.wrapping_neg(),
+                UnOp::Invert => !value,
+            }
+        }
+        Node::Binary(op, lhs, rhs) => {
+            let lhs = eval(lhs, ctx)?;
+
+            // Short circuit the logical operators
+            match op {
+                BinOp::Or if lhs != 0 => return Ok(1),
+                BinOp::And if lhs == 0 => return Ok(0),
+                _ => {}
+            }
+
+            let rhs = eval(rhs, ctx)?;
+            match op {
+                BinOp::Or | BinOp::And => (rhs != 0) as i64,
+                BinOp::BitOr => lhs | rhs,
+                BinOp::BitXor => lhs ^ rhs,
+                BinOp::BitAnd => lhs & rhs,
+                BinOp::Eq => (lhs == rhs) as i64,
+                BinOp::Ne => (lhs != rhs) as i64,
+                BinOp::Lt => (lhs < rhs) as i64,
+                BinOp::Le => (lhs <= rhs) as i64,
+                BinOp::Gt => (lhs > rhs) as i64,
+                BinOp::Ge => (lhs >= rhs) as i64,
+                BinOp::Shl => lhs.wrapping_shl(rhs as u32),
+                BinOp::Shr => lhs.wrapping_shr(rhs as u32),
+                BinOp::Add => lhs.wrapping_add(rhs),
+                BinOp::Sub => lhs.wrapping_sub(rhs),
+                BinOp::Mul => lhs.wrapping_mul(rhs),
+                BinOp::Div | BinOp::Mod if rhs == 0 => return Err(EvalError::DivideByZero),
+                BinOp::Div => lhs.wrapping_div(rhs),
+                BinOp::Mod => lhs.wrapping_rem(rhs),
+            }
+        }
+    })
+}
+
+// Binary operators from lowest to highest precedence
+const PRECEDENCE: [&[(&str, BinOp)]; 10] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+    ],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            position: self.pos,
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek_str(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_space();
+        if self.peek_str(s) {
+            self.pos += s.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), ParseError> {
+        match self.eat(s) {
+            true => Ok(()),
+            false => Err(self.error(&format!("Expected '{}'", s))),
+        }
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Node, ParseError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.expression(level + 1)?;
+        'outer: loop {
+            self.skip_space();
+            for (s, op) in PRECEDENCE[level] {
+                // Don't mistake || for | or << for <, etc.
+                let longer = PRECEDENCE
+                    .iter()
+                    .flat_map(|ops| ops.iter())
+                    .any(|(o, _)| o.len() > s.len() && o.starts_with(s) && self.peek_str(o));
+                if !longer && self.eat(s) {
+                    let rhs = self.expression(level + 1)?;
+                    lhs = Node::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        if self.eat("!") {
+            return Ok(Node::Unary(UnOp::Not, Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Node::Unary(UnOp::Neg, Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Node::Unary(UnOp::Invert, Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        self.skip_space();
+        let start = self.pos;
+        let Some(&c) = self.chars.get(self.pos) else {
+            return Err(self.error("Unexpected end of expression"));
+        };
+
+        if self.eat("(") {
+            let node = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if self.eat("[") {
+            let node = self.expression(0)?;
+            self.expect("]")?;
+            return Ok(Node::Byte(Box::new(node)));
+        }
+        if c == '$' || c == '%' || c.is_ascii_digit() {
+            return self.number();
+        }
+        if c.is_alphabetic() || c == '_' || c == '.' || c == '@' {
+            let name = self.identifier();
+            if name.eq_ignore_ascii_case("word") && self.eat("[") {
+                let node = self.expression(0)?;
+                self.expect("]")?;
+                return Ok(Node::Word(Box::new(node)));
+            }
+            return Ok(match register(&name) {
+                Some(reg) => Node::Register(reg),
+                None => Node::Symbol(name),
+            });
+        }
+
+        self.pos = start;
+        Err(self.error(&format!("Unexpected '{}'", c)))
+    }
+
+    fn identifier(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_alphanumeric() || "_.@".contains(*c))
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn number(&mut self) -> Result<Node, ParseError> {
+        let start = self.pos;
+        let radix = match self.chars[self.pos] {
+            '$' => 16,
+            '%' => 2,
+            _ if self.peek_str("0x") => {
+                self.pos += 1;
+                16
+            }
+            _ => 10,
+        };
+        if radix != 10 {
+            self.pos += 1;
+        }
+
+        let digits_start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_digit(radix)) {
+            self.pos += 1;
+        }
+
+        let digits: String = self.chars[digits_start..self.pos].iter().collect();
+        match i64::from_str_radix(&digits, radix) {
+            Ok(n) => Ok(Node::Number(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("Invalid number"))
+            }
+        }
+    }
+}
+
+fn register(name: &str) -> Option<Register> {
+    let flag = |c: &str| match c {
+        "N" => Some(StatusFlags::N),
+        "V" => Some(StatusFlags::V),
+        "B" => Some(StatusFlags::B),
+        "D" => Some(StatusFlags::D),
+        "I" => Some(StatusFlags::I),
+        "Z" => Some(StatusFlags::Z),
+        "C" => Some(StatusFlags::C),
+        _ => None,
+    };
+
+    let name = name.to_ascii_uppercase();
+    Some(match name.as_str() {
+        "A" => Register::A,
+        "X" => Register::X,
+        "Y" => Register::Y,
+        "S" | "SP" => Register::S,
+        "PC" => Register::Pc,
+        "P" => Register::P,
+        "CYCLES" => Register::Cycles,
+        "HITS" => Register::Hits,
+        _ => Register::Flag(flag(name.strip_prefix("P.")?)?),
+    })
+}
@@ -0,0 +1,280 @@
+use crate::symbols::{LineTable, SymbolTable};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/* Symbols and source lines from the tools that built a program, so addresses can be shown as
+draw_sprite+3 and main.s:42. Two formats are read:
+
+The debug info written by ld65 (ld65 --dbgfile game.dbg), a line per item with tab separated
+key=value fields:
+
+    file    id=0,name="main.s",size=1234,mtime=0x5E2A1B3C,mod=0
+    line    id=3,file=0,line=42,span=7
+    seg     id=0,name="CODE",start=0x000800,size=0x0123,addrsize=absolute,type=ro
+    span    id=7,seg=0,start=16,size=3
+    scope   id=1,name="draw_sprite",mod=0,parent=0
+    sym     id=4,name="loop",addrsize=absolute,scope=1,val=0x810,seg=0,type=lab
+
+Labels inside a scope get the scope's name in front (draw_sprite::loop), cheap locals that of
+the label they belong to (main@loop). Only labels are taken as symbols, not constants.
+
+VICE label files (ld65 -Ln, or written by VICE itself) only have symbols:
+
+    al C:0810 .main
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugInfoError {
+    pub message: String,
+}
+
+impl DebugInfoError {
+    fn new(message: impl Into<String>) -> Self {
+        DebugInfoError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for DebugInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+}
+
+// Line types in the ld65 format, which decide who wins when several lines share code
+const LINE_ASM: u32 = 0;
+const LINE_EXTERNAL: u32 = 1; // C source for code compiled by cc65
+const LINE_MACRO: u32 = 2;
+
+struct Sym<'t> {
+    name: &'t str,
+    value: u32,
+    label: bool,
+    scope: Option<u32>,
+    parent: Option<u32>,
+}
+
+// Whether a file is debug info going by its name: ld65's .dbg, or a label file (.lbl or .vs)
+pub fn is_debug_info_path(path: impl AsRef<Path>) -> bool {
+    let extension = path.as_ref().extension().and_then(|e| e.to_str());
+    matches!(extension, Some("dbg" | "lbl" | "vs"))
+}
+
+impl DebugInfo {
+    // Either format, picked by looking at the file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DebugInfoError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| DebugInfoError::new(format!("Can't read {}: {}", path.display(), e)))?;
+
+        if !text.trim_start().starts_with("version") {
+            return DebugInfo::parse_vice_labels(&text);
+        }
+
+        // Source file names are relative to wherever ld65 ran, which is usually where it put
+        // the debug info too
+        let dir = path.parent().unwrap_or(Path::new(""));
+        DebugInfo::parse_ca65_with(&text, |name| {
+            let beside = dir.join(name);
+            match Path::new(name).is_relative() && beside.exists() {
+                true => beside.to_string_lossy().into_owned(),
+                false => name.to_string(),
+            }
+        })
+    }
+
+    pub fn parse_ca65(text: &str) -> Result<Self, DebugInfoError> {
+        DebugInfo::parse_ca65_with(text, str::to_string)
+    }
+
+    fn parse_ca65_with(
+        text: &str,
+        file_name: impl Fn(&str) -> String,
+    ) -> Result<Self, DebugInfoError> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut scopes = HashMap::new();
+        let mut symbols = BTreeMap::new(); // By id, so the first label for an address stays first
+        let mut lines = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let error =
+                |message: String| DebugInfoError::new(format!("Line {}: {}", n + 1, message));
+            let Some((kind, fields)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let fields = parse_fields(fields).map_err(error)?;
+            let get = |key: &str| fields.get(key).copied();
+            let number = |key: &str| match get(key) {
+                Some(value) => parse_number(value)
+                    .map(Some)
+                    .ok_or_else(|| error(format!("Bad number '{}' for {}", value, key))),
+                None => Ok(None),
+            };
+            let id = || number("id")?.ok_or_else(|| error(format!("{} without an id", kind)));
+
+            match kind {
+                "file" => {
+                    files.insert(id()?, file_name(get("name").unwrap_or_default()));
+                }
+                "seg" => {
+                    segments.insert(id()?, number("start")?.unwrap_or(0));
+                }
+                "span" => {
+                    let span = (
+                        number("seg")?.unwrap_or(0),
+                        number("start")?.unwrap_or(0),
+                        number("size")?.unwrap_or(0),
+                    );
+                    spans.insert(id()?, span);
+                }
+                "scope" => {
+                    let scope = (get("name").unwrap_or_default(), number("parent")?);
+                    scopes.insert(id()?, scope);
+                }
+                "sym" => {
+                    let sym = Sym {
+                        name: get("name").unwrap_or_default(),
+                        value: number("val")?.unwrap_or(0),
+                        label: get("type") == Some("lab"),
+                        scope: number("scope")?,
+                        parent: number("parent")?,
+                    };
+                    symbols.insert(id()?, sym);
+                }
+                "line" => {
+                    let (Some(file), Some(number_), Some(spans)) =
+                        (number("file")?, number("line")?, get("span"))
+                    else {
+                        continue; // Lines without code
+                    };
+                    let kind = number("type")?.unwrap_or(LINE_ASM);
+                    lines.push((kind, file, number_, spans));
+                }
+                _ => {}
+            }
+        }
+
+        let mut info = DebugInfo::default();
+
+        // Later inserts win, so macro bodies first and C source last
+        let rank = |kind: u32| match kind {
+            LINE_MACRO => 0,
+            LINE_EXTERNAL => 2,
+            _ => 1,
+        };
+        lines.sort_by_key(|line| rank(line.0));
+        for (_, file, line, span_ids) in lines {
+            let Some(file) = files.get(&file) else {
+                continue;
+            };
+            for span in span_ids.split('+').filter_map(parse_number) {
+                let Some(&(seg, start, size)) = spans.get(&span) else {
+                    continue;
+                };
+                let address = segments.get(&seg).copied().unwrap_or(0) + start;
+                if size > 0 && address <= 0xFFFF {
+                    let len = size.min(0x10000 - address).min(0xFFFF) as u16;
+                    info.lines.insert(file, line, address as u16, len);
+                }
+            }
+        }
+
+        for sym in symbols.values().filter(|s| s.label && s.value <= 0xFFFF) {
+            info.symbols
+                .insert(&full_name(sym, &symbols, &scopes), sym.value as u16);
+        }
+        Ok(info)
+    }
+
+    pub fn parse_vice_labels(text: &str) -> Result<Self, DebugInfoError> {
+        let mut info = DebugInfo::default();
+        for (n, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("al") {
+                continue; // Other monitor commands
+            }
+
+            let (Some(address), Some(name)) = (words.next(), words.next()) else {
+                return Err(DebugInfoError::new(format!(
+                    "Line {}: incomplete label",
+                    n + 1
+                )));
+            };
+            let address = address.strip_prefix("C:").unwrap_or(address);
+            let Ok(address) = u16::from_str_radix(address, 16) else {
+                return Err(DebugInfoError::new(format!(
+                    "Line {}: bad address '{}'",
+                    n + 1,
+                    address
+                )));
+            };
+            info.symbols.insert(name.trim_start_matches('.'), address);
+        }
+        Ok(info)
+    }
+}
+
+// key=value pairs separated by commas, where values can be quoted strings
+fn parse_fields(text: &str) -> Result<HashMap<&str, &str>, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("Bad field '{}'", rest))?;
+
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted
+                    .find('"')
+                    .ok_or_else(|| format!("Unterminated string for {}", key))?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => after.split_once(',').map_or((after, ""), |(v, a)| (v, a)),
+        };
+
+        fields.insert(key.trim(), value);
+        rest = after.trim_start_matches(',').trim();
+    }
+    Ok(fields)
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// A symbol's name with its scopes (or the label a cheap local belongs to) in front
+fn full_name(
+    sym: &Sym,
+    symbols: &BTreeMap<u32, Sym>,
+    scopes: &HashMap<u32, (&str, Option<u32>)>,
+) -> String {
+    if let Some(parent) = sym.parent.and_then(|p| symbols.get(&p)) {
+        return full_name(parent, symbols, scopes) + sym.name;
+    }
+
+    let mut names = vec![sym.name];
+    let mut scope = sym.scope;
+    while let Some(&(name, parent)) = scope.and_then(|s| scopes.get(&s)) {
+        if !name.is_empty() {
+            names.push(name);
+        }
+        scope = parent;
+    }
+    names.reverse();
+    names.join("::")
+}
@@ -0,0 +1,167 @@
+use super::*;
+use std::fmt;
+
+// Opcodes that aren't part of the documented NMOS instruction set
+const ILLEGAL_OPCODES: [u8; 105] = [
+    0x1A, 0x3A, 0x5A, 0x7A, 0xDA, 0xFA, 0x80, 0x82, 0x89, 0xC2, 0xE2, 0x04, 0x44, 0x64, 0x14, 0x34,
+    0x54, 0x74, 0xD4, 0xF4, 0x0C, 0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC, 0x4B, 0x0B, 0x2B, 0x8B, 0x6B,
+    0xC7, 0xD7, 0xCF, 0xDF, 0xDB, 0xC3, 0xD3, 0xE7, 0xF7, 0xEF, 0xFF, 0xFB, 0xE3, 0xF3, 0xBB, 0xA7,
+    0xB7, 0xAF, 0xBF, 0xA3, 0xB3, 0xAB, 0x27, 0x37, 0x2F, 0x3F, 0x3B, 0x23, 0x33, 0x67, 0x77, 0x6F,
+    0x7F, 0x7B, 0x63, 0x73, 0x87, 0x97, 0x8F, 0x83, 0xCB, 0x9F, 0x93, 0x9E, 0x9C, 0x07, 0x17, 0x0F,
+    0x1F, 0x1B, 0x03, 0x13, 0x47, 0x57, 0x4F, 0x5F, 0x5B, 0x43, 0x53, 0x9B, 0xEB, 0x02, 0x12, 0x22,
+    0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
+
+pub fn is_illegal(opcode: u8) -> bool {
+    ILLEGAL_OPCODES.contains(&opcode)
+}
+
+// Number of bytes (opcode included) the instruction occupies in memory
+pub fn instr_len(opcode: u8) -> u8 {
+    match OPCODES[opcode as usize].mode {
+        // BRK is listed as 2 bytes (it skips a padding byte), but only the opcode is part of it
+        AddrMode::IMP0 | AddrMode::ACM0 => 1,
+        _ => OPCODES[opcode as usize].bytes,
+    }
+}
+
+// A single decoded instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub operands: [u8; 2],
+}
+
+impl Instruction {
+    /* Decode the instruction starting at 'address'. The bytes slice should hold the opcode
+    followed by its operands, though missing operands are treated as zero. */
+    pub fn decode(address: u16, bytes: &[u8]) -> Self {
+        let mut operands = [0, 0];
+        for (i, b) in bytes.iter().skip(1).take(2).enumerate() {
+            operands[i] = *b;
+        }
+
+        Instruction {
+            address,
+            opcode: bytes.first().copied().unwrap_or(0),
+            operands,
+        }
+    }
+
+    // Decode the instruction at 'address' using a memory accessor
+    pub fn fetch(address: u16, mut peek: impl FnMut(u16) -> u8) -> Self {
+        let opcode = peek(address);
+        let mut bytes = [opcode, 0, 0];
+        for i in 1..instr_len(opcode) {
+            bytes[i as usize] = peek(address.wrapping_add(i as u16));
+        }
+
+        Instruction::decode(address, &bytes)
+    }
+
+    pub fn size(&self) -> u8 {
+        instr_len(self.opcode)
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode];
+        bytes.extend_from_slice(&self.operands[..self.size() as usize - 1]);
+        bytes
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        OPCODES[self.opcode as usize].name
+    }
+
+    pub fn is_illegal(&self) -> bool {
+        is_illegal(self.opcode)
+    }
+
+    // Address of the instruction that follows this one
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.size() as u16)
+    }
+
+    pub fn mode(&self) -> &'static AddrMode {
+        &OPCODES[self.opcode as usize].mode
+    }
+
+    // The 16-bit operand (absolute address or indirect pointer)
+    pub fn word(&self) -> u16 {
+        (self.operands[1] as u16) << 8 | self.operands[0] as u16
+    }
+
+    // Destination of a relative branch
+    pub fn branch_target(&self) -> Option<u16> {
+        match self.mode() {
+            AddrMode::REL0 => Some(
+                self.next()
+                    .wrapping_add((self.operands[0] as i8) as i16 as u16),
+            ),
+            _ => None,
+        }
+    }
+
+    // The operand as written in assembly, with addresses rendered by 'label'
+    pub fn operand_with(&self, mut label: impl FnMut(u16, bool) -> String) -> String {
+        let byte = self.operands[0];
+        match self.mode() {
+            AddrMode::ACM0 => "A".to_string(),
+            AddrMode::IMP0 => String::new(),
+            AddrMode::IMM0 => format!("#${:02X}", byte),
+            AddrMode::ABS0 => label(self.word(), false),
+            AddrMode::ABSX => format!("{},X", label(self.word(), false)),
+            AddrMode::ABSY => format!("{},Y", label(self.word(), false)),
+            AddrMode::IND0 => format!("({})", label(self.word(), false)),
+            AddrMode::INDX => format!("({},X)", label(byte as u16, true)),
+            AddrMode::INDY => format!("({}),Y", label(byte as u16, true)),
+            AddrMode::REL0 => label(self.branch_target().unwrap(), false),
+            AddrMode::ZPG0 => label(byte as u16, true),
+            AddrMode::ZPGX => format!("{},X", label(byte as u16, true)),
+            AddrMode::ZPGY => format!("{},Y", label(byte as u16, true)),
+        }
+    }
+
+    pub fn operand(&self) -> String {
+        self.operand_with(hex_address)
+    }
+}
+
+// Default rendering of an address operand
+pub fn hex_address(address: u16, zero_page: bool) -> String {
+    match zero_page {
+        true => format!("${:02X}", address),
+        false => format!("${:04X}", address),
+    }
+}
+
+/* Find an address 'count' instructions before 'address'. Code can't be decoded backwards, so
+this tries starting points further and further back and keeps the furthest one that decodes into
+'address' within that many instructions. */
+pub fn start_before(address: u16, count: usize, peek: impl Fn(u16) -> u8) -> u16 {
+    let mut best = (0, address);
+    for back in 1..=(count as u16 * 3) {
+        let start = address.wrapping_sub(back);
+        let mut at = start;
+        let mut lines = 0;
+        while at.wrapping_sub(start) < back {
+            at = Instruction::fetch(at, &peek).next();
+            lines += 1;
+        }
+        if at == address && lines <= count && lines >= best.0 {
+            best = (lines, start);
+        }
+    }
+    best.1
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = self.operand();
+        match operand.is_empty() {
+            true => write!(f, "{}", self.mnemonic().to_uppercase()),
+            false => write!(f, "{} {}", self.mnemonic().to_uppercase(), operand),
+        }
+    }
+}
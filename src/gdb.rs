@@ -0,0 +1,556 @@
+use crate::debugger::*;
+use crate::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/* A stub for the GDB remote serial protocol, so debuggers and front ends that speak it can
+attach to a CPU over TCP. It supports reading and writing registers and memory, breakpoints
+(software and hardware ones are the same thing here), write/read/access watchpoints, single steps
+and continuing, which can be interrupted with Ctrl-C from the debugger. When the CPU records
+execution history (see the history module) reverse-stepi and reverse-continue work as well.
+
+Registers are numbered a, x, y, sp, p, pc, the first five 8 bits wide and pc 16 bits (little
+endian like everything else in the protocol). Debuggers that ask get this as a target
+description, see TARGET_XML.
+
+GdbStub::handle() answers a single packet, serve() listens on a socket and handles a whole
+session until the debugger detaches or goes away, even while the CPU runs:
+
+    rust_6502::gdb::serve(&mut debugger, "127.0.0.1:6502")
+*/
+
+const SUPPORTED: &str =
+    "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+";
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rust6502.core">
+    <flags id="p_flags" size="1">
+      <field name="C" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="D" start="3" end="3"/>
+      <field name="B" start="4" end="4"/>
+      <field name="U" start="5" end="5"/>
+      <field name="V" start="6" end="6"/>
+      <field name="N" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="p_flags"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub<'d, 'a> {
+    pub debugger: &'d mut Debugger<'a>,
+
+    // Breakpoints set by the debugger, by packet type, address and length
+    inserted: HashMap<(u8, u16, u16), BreakpointId>,
+    last_stop: String,
+    no_ack: bool,
+    done: bool,
+}
+
+impl<'d, 'a> GdbStub<'d, 'a> {
+    pub fn new(debugger: &'d mut Debugger<'a>) -> Self {
+        GdbStub {
+            debugger,
+            inserted: HashMap::new(),
+            last_stop: format!("S{:02x}", SIGTRAP),
+            no_ack: false,
+            done: false,
+        }
+    }
+
+    // The debugger detached or killed the session
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // Whether packets still need to be acknowledged with '+'
+    pub fn acks(&self) -> bool {
+        !self.no_ack
+    }
+
+    /* Answer a packet (the part between '$' and '#'). Continuing asks 'interrupted' every so
+    often whether the debugger wants to break in. None means no reply is sent at all. */
+    pub fn handle(&mut self, packet: &str, interrupted: impl FnMut() -> bool) -> Option<String> {
+        // The command is one character, which after unescaping isn't necessarily one byte
+        let mut chars = packet.chars();
+        let command = chars.next();
+        let args = chars.as_str();
+        let reply = match command {
+            Some('?') => self.last_stop.clone(),
+            Some('g') => self.read_registers(),
+            Some('G') => self.write_registers(args),
+            Some('p') => self.read_register(args),
+            Some('P') => self.write_register(args),
+            Some('m') => self.read_memory(args),
+            Some('M') => self.write_memory(args),
+            Some('Z') => self.insert(args),
+            Some('z') => self.remove(args),
+            Some('s') => self.resume(args, true, interrupted),
+            Some('c') => self.resume(args, false, interrupted),
+            Some('b') => self.reverse(args),
+            Some('v') => self.v_packet(args, interrupted),
+            Some('q') => self.query(args),
+            Some('Q') if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            Some('H') => "OK".to_string(),
+            Some('D') => {
+                self.detach();
+                "OK".to_string()
+            }
+            Some('k') => {
+                self.detach();
+                return None;
+            }
+            _ => String::new(), // Not supported
+        };
+        Some(reply)
+    }
+
+    fn detach(&mut self) {
+        for id in self.inserted.drain().map(|(_, id)| id) {
+            self.debugger.remove(id);
+        }
+        self.done = true;
+    }
+
+    fn registers(&self) -> [u8; 7] {
+        let r = &self.debugger.cpu.registers;
+        let pc = r.pc.to_le_bytes();
+        [r.a, r.x, r.y, r.s, r.p.bits(), pc[0], pc[1]]
+    }
+
+    fn read_registers(&self) -> String {
+        hex(&self.registers())
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match unhex(args) {
+            Some(bytes) if bytes.len() == 7 => {
+                let r = &mut self.debugger.cpu.registers;
+                r.a = bytes[0];
+                r.x = bytes[1];
+                r.y = bytes[2];
+                r.s = bytes[3];
+                r.p = StatusFlags::from_bits_retain(bytes[4]);
+                r.pc = u16::from_le_bytes([bytes[5], bytes[6]]);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let registers = self.registers();
+        match usize::from_str_radix(args, 16) {
+            Ok(n @ 0..=4) => hex(&registers[n..=n]),
+            Ok(5) => hex(&registers[5..]),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((n, value)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+        let (Ok(n), Some(value)) = (usize::from_str_radix(n, 16), unhex(value)) else {
+            return "E01".to_string();
+        };
+
+        let r = &mut self.debugger.cpu.registers;
+        match (n, value.as_slice()) {
+            (0, [v]) => r.a = *v,
+            (1, [v]) => r.x = *v,
+            (2, [v]) => r.y = *v,
+            (3, [v]) => r.s = *v,
+            (4, [v]) => r.p = StatusFlags::from_bits_retain(*v),
+            (5, [lo, hi]) => r.pc = u16::from_le_bytes([*lo, *hi]),
+            _ => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let Some((address, len)) = address_length(args) else {
+            return "E01".to_string();
+        };
+
+        let cpu = &self.debugger.cpu;
+        let bytes: Option<Vec<u8>> = (0..len)
+            .map(|i| cpu.peek(address.wrapping_add(i) as usize))
+            .collect();
+        match bytes {
+            Some(bytes) => hex(&bytes),
+            None => "E02".to_string(), // The CPU has no peek callback
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let (Some((address, len)), Some(bytes)) = (address_length(range), unhex(data)) else {
+            return "E01".to_string();
+        };
+        if bytes.len() != len as usize {
+            return "E01".to_string();
+        }
+
+        for (i, byte) in bytes.into_iter().enumerate() {
+            let address = address.wrapping_add(i as u16);
+            self.debugger.cpu.poke(address as usize, byte);
+        }
+        "OK".to_string()
+    }
+
+    // Z0/Z1 are breakpoints, Z2/Z3/Z4 write/read/access watchpoints
+    fn insert(&mut self, args: &str) -> String {
+        let Some((key, kind)) = breakpoint_kind(args) else {
+            return String::new();
+        };
+        if !self.inserted.contains_key(&key) {
+            let id = self.debugger.add(kind);
+            self.inserted.insert(key, id);
+        }
+        "OK".to_string()
+    }
+
+    fn remove(&mut self, args: &str) -> String {
+        let Some((key, _)) = breakpoint_kind(args) else {
+            return String::new();
+        };
+        if let Some(id) = self.inserted.remove(&key) {
+            self.debugger.remove(id);
+        }
+        "OK".to_string()
+    }
+
+    // s/c with an optional address to resume from
+    fn resume(&mut self, args: &str, step: bool, interrupted: impl FnMut() -> bool) -> String {
+        if !args.is_empty() {
+            match u16::from_str_radix(args, 16) {
+                Ok(address) => self.debugger.cpu.registers.pc = address,
+                Err(_) => return "E01".to_string(),
+            }
+        }
+
+        let stop = match step {
+            true => self.debugger.step(),
+            false => self.debugger.cont_interruptible(None, interrupted),
+        };
+        self.last_stop = self.stop_reply(stop);
+        self.last_stop.clone()
+    }
+
+    // Reverse step (bs) and continue (bc), which need execution history on the CPU
+    fn reverse(&mut self, args: &str) -> String {
+        let stop = match args {
+            "s" => self.debugger.step_back(),
+            "c" => self.debugger.reverse_cont(),
+            _ => return String::new(),
+        };
+        self.last_stop = self.stop_reply(stop);
+        self.last_stop.clone()
+    }
+
+    fn v_packet(&mut self, args: &str, interrupted: impl FnMut() -> bool) -> String {
+        if args == "Cont?" {
+            return "vCont;c;C;s;S".to_string();
+        }
+
+        // Only one thread, so the first action is the one that applies
+        match args.strip_prefix("Cont;") {
+            Some(actions) => match actions.as_bytes().first() {
+                Some(b's' | b'S') => self.resume("", true, interrupted),
+                Some(b'c' | b'C') => self.resume("", false, interrupted),
+                _ => "E01".to_string(),
+            },
+            None => String::new(),
+        }
+    }
+
+    fn query(&self, args: &str) -> String {
+        let (name, rest) = args.split_once(':').unwrap_or((args, ""));
+        match name {
+            "Supported" => SUPPORTED.to_string(),
+            "Xfer" => match rest.strip_prefix("features:read:target.xml:") {
+                Some(range) => xfer(TARGET_XML, range),
+                None => String::new(),
+            },
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Watchpoint { id, access } => {
+                let kind = self.debugger.breakpoint(id).map(|b| b.kind);
+                let name = match kind {
+                    Some(BreakKind::Watch {
+                        kind: WatchKind::Read,
+                        ..
+                    }) => "rwatch",
+                    Some(BreakKind::Watch {
+                        kind: WatchKind::Access,
+                        ..
+                    }) => "awatch",
+                    _ => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, name, access.address)
+            }
+            Stop::Halted => format!("S{:02x}", SIGILL),
+            Stop::Interrupted => format!("S{:02x}", SIGINT),
+            Stop::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+            _ => format!("S{:02x}", SIGTRAP),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "addr,length" in hex
+fn address_length(text: &str) -> Option<(u16, u16)> {
+    let (address, len) = text.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    match address <= 0xFFFF && len <= 0xFFFF && address + len <= 0x10000 {
+        true => Some((address as u16, len as u16)),
+        false => None,
+    }
+}
+
+// The breakpoint for "type,addr,kind", with a key to find it again when it's removed
+fn breakpoint_kind(args: &str) -> Option<((u8, u16, u16), BreakKind)> {
+    let (kind, rest) = args.split_once(',')?;
+    let (address, len) = address_length(rest)?;
+    let end = address.wrapping_add(len.max(1) - 1);
+    let watch = |kind| BreakKind::Watch {
+        start: address,
+        end,
+        kind,
+    };
+
+    let breakpoint = match kind {
+        "0" | "1" => BreakKind::Exec(address),
+        "2" => watch(WatchKind::Write),
+        "3" => watch(WatchKind::Read),
+        "4" => watch(WatchKind::Access),
+        _ => return None,
+    };
+    Some(((kind.as_bytes()[0], address, len), breakpoint))
+}
+
+// A chunk of a qXfer object for "offset,length"
+fn xfer(object: &str, range: &str) -> String {
+    let Some((offset, len)) = range.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Ok(offset), Ok(len)) = (
+        usize::from_str_radix(offset, 16),
+        usize::from_str_radix(len, 16),
+    ) else {
+        return "E01".to_string();
+    };
+
+    let start = offset.min(object.len());
+    let end = (start + len).min(object.len());
+    match end == object.len() {
+        true => format!("l{}", &object[start..end]),
+        false => format!("m{}", &object[start..end]),
+    }
+}
+
+// Wait for a debugger to connect, then serve it until it detaches
+pub fn serve(debugger: &mut Debugger, address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    serve_stream(debugger, stream)
+}
+
+pub fn serve_stream(debugger: &mut Debugger, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub::new(debugger);
+    let mut connection = Connection {
+        stream,
+        pending: VecDeque::new(),
+        closed: false,
+    };
+
+    while !stub.is_done() {
+        let Some(packet) = read_packet(&mut connection, stub.acks())? else {
+            break; // Connection closed
+        };
+
+        // A Ctrl-C while stopped just gets the stop reason again
+        let packet = match packet {
+            Packet::Interrupt => "?".to_string(),
+            Packet::Data(packet) => packet,
+        };
+
+        let reply = stub.handle(&packet, || connection.break_requested());
+        if connection.closed {
+            break; // Went away while the CPU was running
+        }
+        if let Some(reply) = reply {
+            write_packet(&mut connection.stream, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+// The socket, with whatever came in while looking for a Ctrl-C kept for the packet reader
+struct Connection {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /* Whether a Ctrl-C (0x03) came in, without waiting for one. The debugger going away stops
+    the CPU as well. */
+    fn break_requested(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let stop = match self.stream.read(&mut byte) {
+            Ok(0) => {
+                self.closed = true;
+                true
+            }
+            Ok(_) if byte[0] == 0x03 => true,
+            Ok(_) => {
+                self.pending.push_back(byte[0]);
+                false
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+            Err(_) => {
+                self.closed = true;
+                true
+            }
+        };
+        let _ = self.stream.set_nonblocking(false);
+        stop
+    }
+}
+
+enum Packet {
+    Interrupt,
+    Data(String),
+}
+
+// Read "$data#cs", acknowledging it unless acks were turned off
+fn read_packet(connection: &mut Connection, acks: bool) -> io::Result<Option<Packet>> {
+    loop {
+        match connection.read_byte()? {
+            None => return Ok(None),
+            Some(0x03) => return Ok(Some(Packet::Interrupt)),
+            Some(b'$') => {}
+            Some(_) => continue, // Acks and line noise
+        }
+
+        let mut data = Vec::new();
+        let mut sum: u8 = 0;
+        loop {
+            let Some(byte) = connection.read_byte()? else {
+                return Ok(None);
+            };
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            data.push(byte);
+        }
+
+        let (Some(high), Some(low)) = (connection.read_byte()?, connection.read_byte()?) else {
+            return Ok(None);
+        };
+        let checksum = [high, low];
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|cs| u8::from_str_radix(cs, 16).ok())
+            == Some(sum);
+
+        if acks {
+            connection.stream.write_all(match valid {
+                true => b"+",
+                false => b"-",
+            })?;
+        }
+        if valid {
+            return Ok(Some(Packet::Data(unescape(&data))));
+        }
+    }
+}
+
+// '}' escapes the next byte, which is xored with 0x20
+fn unescape(data: &[u8]) -> String {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn write_packet(stream: &mut TcpStream, reply: &str) -> io::Result<()> {
+    let mut data = Vec::with_capacity(reply.len());
+    for byte in reply.bytes() {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => data.extend([b'}', byte ^ 0x20]),
+            _ => data.push(byte),
+        }
+    }
+    let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+    let mut packet = vec![b'$'];
+    packet.extend(data);
+    packet.extend(format!("#{:02x}", sum).bytes());
+    stream.write_all(&packet)
+}
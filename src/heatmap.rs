@@ -0,0 +1,161 @@
+use super::*;
+
+/* Counts of how many times every address was read, written and executed, for spotting zero page
+hot spots and I/O being accessed where it shouldn't. Executing counts every byte of the
+instruction, and like with coverage the instruction's own bytes don't count as reads.
+
+The counts export as CSV, or as 256x256 images with one pixel per address: the low byte of the
+address across and the high byte down, so the zero page is the top row and the stack the one
+below. Images are PGM (one channel, grayscale) or PPM (writes red, reads green, executing blue),
+which about anything can open or convert. Brightness goes by the log of the count, otherwise a
+busy loop would leave everything else black.
+
+    cpu.set_heatmap(Some(Heatmap::new()));
+    ...
+    fs::write("heat.ppm", cpu.heatmap().unwrap().ppm()); */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Read,
+    Write,
+    Execute,
+}
+
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    executions: Vec<u64>,
+    pc: u16, // The instruction running, whose bytes don't count as read
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap {
+            reads: vec![0; memory::MEM_SIZE],
+            writes: vec![0; memory::MEM_SIZE],
+            executions: vec![0; memory::MEM_SIZE],
+            pc: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Heatmap::new();
+    }
+
+    pub fn count(&self, channel: Channel, address: u16) -> u64 {
+        self.channel(channel)[address as usize]
+    }
+
+    // The highest count in a channel
+    pub fn max(&self, channel: Channel) -> u64 {
+        self.channel(channel).iter().copied().max().unwrap_or(0)
+    }
+
+    // One channel as a binary PGM image
+    pub fn pgm(&self, channel: Channel) -> Vec<u8> {
+        let mut image = b"P5\n256 256\n255\n".to_vec();
+        image.extend(self.pixels(channel));
+        image
+    }
+
+    // All three channels as a binary PPM image, writes in red, reads in green, executing in blue
+    pub fn ppm(&self) -> Vec<u8> {
+        let mut image = b"P6\n256 256\n255\n".to_vec();
+        let (red, green, blue) = (
+            self.pixels(Channel::Write),
+            self.pixels(Channel::Read),
+            self.pixels(Channel::Execute),
+        );
+        for i in 0..memory::MEM_SIZE {
+            image.extend([red[i], green[i], blue[i]]);
+        }
+        image
+    }
+
+    // A line for every address that was accessed at all
+    pub fn csv(&self) -> String {
+        let mut out = "address,reads,writes,executions\n".to_string();
+        for i in 0..memory::MEM_SIZE {
+            let (reads, writes, executions) = (self.reads[i], self.writes[i], self.executions[i]);
+            if reads + writes + executions > 0 {
+                out += &format!("{:04X},{},{},{}\n", i, reads, writes, executions);
+            }
+        }
+        out
+    }
+
+    fn channel(&self, channel: Channel) -> &[u64] {
+        match channel {
+            Channel::Read => &self.reads,
+            Channel::Write => &self.writes,
+            Channel::Execute => &self.executions,
+        }
+    }
+
+    // Brightness of every address, anything accessed at all getting at least 1
+    fn pixels(&self, channel: Channel) -> Vec<u8> {
+        let max = ((self.max(channel) + 1) as f64).ln();
+        self.channel(channel)
+            .iter()
+            .map(|&count| match count {
+                0 => 0,
+                count => ((((count + 1) as f64).ln() / max * 255.0).round() as u8).max(1),
+            })
+            .collect()
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    // Start (or with None stop) counting accesses
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        self.heatmap = heatmap;
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heatmap.as_mut()
+    }
+
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.take()
+    }
+
+    // Called by tick() ahead of an instruction or interrupt
+    pub(crate) fn heat_start(&mut self) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.pc = self.registers.pc;
+        }
+    }
+
+    // Called by tick() after an instruction
+    pub(crate) fn heat_instruction(&mut self, opcode: u8, pc: u16) {
+        if let Some(heatmap) = &mut self.heatmap {
+            for i in 0..disasm::instr_len(opcode) {
+                heatmap.executions[pc.wrapping_add(i as u16) as usize] += 1;
+            }
+        }
+    }
+
+    pub(crate) fn heat_read(&mut self, address: usize) {
+        if let Some(heatmap) = &mut self.heatmap {
+            if (address as u16).wrapping_sub(heatmap.pc) >= 3 {
+                heatmap.reads[address] += 1;
+            }
+        }
+    }
+
+    pub(crate) fn heat_write(&mut self, address: usize) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.writes[address] += 1;
+        }
+    }
+}
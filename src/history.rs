@@ -0,0 +1,380 @@
+use super::*;
+use callstack::CallStack;
+use std::collections::VecDeque;
+
+/* Execution history, so a debugger can go back in time. While it's enabled every tick() leaves
+behind the CPU state it started from and the memory it wrote over (an undo log), and every so
+often a snapshot of the whole CPU and memory is taken. Stepping back undoes the last instruction,
+seeking to a cycle far away restores the closest snapshot and replays the log from there. The
+log holds what was written, so replaying never touches the read callback and I/O doesn't need to
+behave the same the second time around.
+
+    cpu.set_history(Some(History::default()));
+    ...
+    cpu.reverse_to_write(0x0200); // Back to the instruction that last wrote $0200
+
+Recording needs the peek callback (see Cpu6502::set_mem_peek()) to know what a write replaced.
+Memory gets restored through the write callback, so devices mapped there see those writes.
+Executing an instruction while back in the past throws away the history after it. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteRecord {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+// Everything about the CPU besides memory and the call stack
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CpuState {
+    registers: Registers,
+    total_cycles: u64,
+    halted: bool,
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+    last_event: TickEvent,
+}
+
+struct Snapshot {
+    step: u64, // Number of the step it was taken in front of
+    cycle: u64,
+    call_stack: Option<CallStack>,
+    memory: Vec<u8>,
+}
+
+struct Step {
+    before: CpuState,
+
+    /* The call stack before the step, only kept when the previous step changed it. Every change
+    to the call stack moves the stack pointer, so that's what's checked. */
+    call_stack: Option<CallStack>,
+
+    writes: Vec<WriteRecord>,
+}
+
+pub struct History {
+    interval: u64,
+    max_snapshots: usize,
+    snapshots: VecDeque<Snapshot>,
+    steps: VecDeque<Step>,
+    first: u64, // Number of the oldest step kept
+
+    // Index of the step the CPU is in front of, steps.len() when it's in the present
+    position: usize,
+
+    // The state to come back to when going forward to the present again
+    present: Option<(CpuState, Option<CallStack>)>,
+}
+
+impl History {
+    /* A snapshot every 'interval' cycles, keeping at most 'max_snapshots' of them. History goes
+    back as far as the oldest snapshot, older steps are dropped along with it. */
+    pub fn new(interval: u64, max_snapshots: usize) -> Self {
+        History {
+            interval: interval.max(1),
+            max_snapshots: max_snapshots.max(1),
+            snapshots: VecDeque::new(),
+            steps: VecDeque::new(),
+            first: 0,
+            position: 0,
+            present: None,
+        }
+    }
+
+    // Instructions (and interrupts) that can be stepped back over from where the CPU is now
+    pub fn steps_back(&self) -> usize {
+        self.position
+    }
+
+    pub fn steps_forward(&self) -> usize {
+        self.steps.len() - self.position
+    }
+
+    // Cycle count of the oldest point that can be gone back to
+    pub fn oldest_cycle(&self) -> Option<u64> {
+        self.steps.front().map(|step| step.before.total_cycles)
+    }
+
+    // What the instruction that step_back() would undo wrote
+    pub fn previous_writes(&self) -> Option<&[WriteRecord]> {
+        match self.position {
+            0 => None,
+            position => Some(&self.steps[position - 1].writes),
+        }
+    }
+
+    fn is_present(&self) -> bool {
+        self.position == self.steps.len()
+    }
+
+    // Index of the last step starting at or before 'cycle'
+    fn step_at(&self, cycle: u64) -> Option<usize> {
+        let after = self
+            .steps
+            .partition_point(|step| step.before.total_cycles <= cycle);
+        match after {
+            0 => None,
+            after => Some(after - 1),
+        }
+    }
+}
+
+impl Default for History {
+    // A snapshot every 100000 cycles, going back about 3 million cycles
+    fn default() -> Self {
+        History::new(100_000, 32)
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    // Start (or with None stop) recording execution history
+    pub fn set_history(&mut self, history: Option<History>) {
+        self.history = history;
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    // Undo the last instruction (or interrupt), false if there's no history left
+    pub fn step_back(&mut self) -> bool {
+        let Some(mut history) = self.history.take() else {
+            return false;
+        };
+        let moved = history.position > 0;
+        if moved {
+            let position = history.position - 1;
+            self.go_back(&mut history, position);
+        }
+        self.history = Some(history);
+        moved
+    }
+
+    /* Go to where the CPU was at 'cycle', or rather in front of the instruction running at that
+    point, returning the cycle count it ended up at. Going forward is fine too, up to the present.
+    None if 'cycle' is older than the history goes back. */
+    pub fn seek(&mut self, cycle: u64) -> Option<u64> {
+        let mut history = self.history.take()?;
+        let newest = match &history.present {
+            Some((present, _)) => present.total_cycles,
+            None => self.total_cycles,
+        };
+        let target = match cycle >= newest {
+            true => Some(history.steps.len()),
+            false => history.step_at(cycle),
+        };
+
+        if let Some(target) = target {
+            self.go_to(&mut history, target);
+        }
+        self.history = Some(history);
+        target.map(|_| self.total_cycles)
+    }
+
+    /* Go back to the last instruction that wrote 'address', stopping in front of it so the PC
+    is on it. Returns its cycle count, or None (staying put) if it isn't in the history. */
+    pub fn reverse_to_write(&mut self, address: u16) -> Option<u64> {
+        let mut history = self.history.take()?;
+        let target = history
+            .steps
+            .range(..history.position)
+            .rposition(|step| step.writes.iter().any(|w| w.address == address));
+
+        if let Some(target) = target {
+            self.go_to(&mut history, target);
+        }
+        self.history = Some(history);
+        target.map(|_| self.total_cycles)
+    }
+
+    // Called by tick() ahead of an instruction or interrupt
+    pub(crate) fn record_step(&mut self) {
+        let Some(mut history) = self.history.take() else {
+            return;
+        };
+        if self.mem_peek.is_none() {
+            self.history = Some(history);
+            return;
+        }
+
+        // Running from the past starts a new future
+        if !history.is_present() {
+            history.steps.truncate(history.position);
+            let end = history.first + history.position as u64;
+            history.snapshots.retain(|s| s.step <= end);
+            history.present = None;
+        }
+
+        let step = history.first + history.steps.len() as u64;
+        let due = match history.snapshots.back() {
+            Some(last) => self.total_cycles >= last.cycle + history.interval,
+            None => true,
+        };
+        if due {
+            history.snapshots.push_back(Snapshot {
+                step,
+                cycle: self.total_cycles,
+                call_stack: self.call_stack.clone(),
+                memory: (0..memory::MEM_SIZE)
+                    .map(|a| self.peek(a).unwrap())
+                    .collect(),
+            });
+        }
+
+        // The previous step moved the stack pointer, so the call stack may have changed
+        let changed = match history.steps.back() {
+            Some(last) => last.before.registers.s != self.registers.s,
+            None => true,
+        };
+        history.steps.push_back(Step {
+            before: self.state(),
+            call_stack: match changed {
+                true => self.call_stack.clone(),
+                false => None,
+            },
+            writes: Vec::new(),
+        });
+
+        // Drop the oldest snapshot and everything up to the next one
+        if history.snapshots.len() > history.max_snapshots {
+            history.snapshots.pop_front();
+            let oldest = &history.snapshots[0];
+            let dropped = (oldest.step - history.first) as usize;
+            history.steps.drain(..dropped);
+            history.steps[0].call_stack = oldest.call_stack.clone();
+            history.first = oldest.step;
+        }
+
+        history.position = history.steps.len();
+        self.history = Some(history);
+    }
+
+    // Called by write() ahead of the write
+    pub(crate) fn record_write(&mut self, address: usize, value: u8) {
+        if self.history.is_none() {
+            return;
+        }
+        let Some(old) = self.peek(address) else {
+            return;
+        };
+        if let Some(step) = self.history.as_mut().and_then(|h| h.steps.back_mut()) {
+            step.writes.push(WriteRecord {
+                address: address as u16,
+                old,
+                new: value,
+            });
+        }
+    }
+
+    // Move to step 'target' (steps.len() being the present), whichever way is shortest
+    fn go_to(&mut self, history: &mut History, target: usize) {
+        // Replaying from a snapshot in front of the target beats undoing a lot more steps
+        if target < history.position {
+            let snapshot = history
+                .snapshots
+                .iter()
+                .rposition(|s| s.step <= history.first + target as u64);
+            if let Some(i) = snapshot {
+                let start = (history.snapshots[i].step - history.first) as usize;
+                if target - start < history.position - target {
+                    self.restore_snapshot(history, i);
+                }
+            }
+        }
+
+        match target < history.position {
+            true => self.go_back(history, target),
+            false => {
+                while history.position < target {
+                    self.redo(history);
+                }
+            }
+        }
+    }
+
+    fn leave_present(&mut self, history: &mut History) {
+        if history.is_present() {
+            history.present = Some((self.state(), self.call_stack.clone()));
+        }
+    }
+
+    // Undo steps until in front of step 'target'
+    fn go_back(&mut self, history: &mut History, target: usize) {
+        self.leave_present(history);
+        while history.position > target {
+            history.position -= 1;
+            let step = &history.steps[history.position];
+            for write in step.writes.iter().rev() {
+                self.poke(write.address as usize, write.old);
+            }
+        }
+        self.restore(history, history.position);
+    }
+
+    fn redo(&mut self, history: &mut History) {
+        let step = &history.steps[history.position];
+        for write in &step.writes {
+            self.poke(write.address as usize, write.new);
+        }
+        history.position += 1;
+        self.restore(history, history.position);
+    }
+
+    fn restore_snapshot(&mut self, history: &mut History, index: usize) {
+        self.leave_present(history);
+        let snapshot = &history.snapshots[index];
+        for (address, value) in snapshot.memory.iter().enumerate() {
+            if self.peek(address) != Some(*value) {
+                self.poke(address, *value);
+            }
+        }
+        history.position = (snapshot.step - history.first) as usize;
+        self.restore(history, history.position);
+    }
+
+    // Registers and the like as they were in front of step 'index'
+    fn restore(&mut self, history: &History, index: usize) {
+        self.accesses.clear();
+        if index == history.steps.len() {
+            let (state, call_stack) = history.present.as_ref().expect("kept while in the past");
+            self.set_state(*state);
+            self.call_stack = call_stack.clone();
+            return;
+        }
+
+        self.set_state(history.steps[index].before);
+        if self.call_stack.is_some() {
+            let saved = history
+                .steps
+                .range(..=index)
+                .rev()
+                .find_map(|step| step.call_stack.as_ref());
+            if let Some(call_stack) = saved {
+                self.call_stack = Some(call_stack.clone());
+            }
+        }
+    }
+
+    pub(crate) fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            total_cycles: self.total_cycles,
+            halted: self.halted,
+            irq: self.irq,
+            nmi: self.nmi,
+            nmi_pending: self.nmi_pending,
+            last_event: self.last_event,
+        }
+    }
+
+    pub(crate) fn set_state(&mut self, state: CpuState) {
+        self.registers = state.registers;
+        self.total_cycles = state.total_cycles;
+        self.halted = state.halted;
+        self.irq = state.irq;
+        self.nmi = state.nmi;
+        self.nmi_pending = state.nmi_pending;
+        self.last_event = state.last_event;
+    }
+}
@@ -4,13 +4,40 @@ use bitflags::bitflags;
 
 type MemReadCallback<'a> = Box<dyn FnMut(usize) -> u8 + 'a>;
 type MemWriteCallback<'a> = Box<dyn FnMut(usize, u8) + 'a>;
+type MemPeekCallback<'a> = Box<dyn Fn(usize) -> u8 + 'a>;
+
+pub mod asm;
+pub mod callstack;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod debuginfo;
+pub mod disasm;
+pub mod gdb;
+pub mod heatmap;
+pub mod history;
+pub mod memory;
+pub mod monitor;
+pub mod opstats;
+pub mod pins;
+pub mod profile;
+pub mod replay;
+pub mod run;
+pub mod symbols;
+pub mod testsuite;
+pub mod trace;
+pub mod tui;
+pub mod vcd;
+pub mod vice;
 
 const STACK_OFFSET: usize = 0x0100;
+const NMI_VECTOR: usize = 0xFFFA;
 const RESET_VECTOR: usize = 0xFFFC;
 const INTR_VECTOR: usize = 0xFFFE;
 
 #[allow(clippy::upper_case_acronyms)]
-enum AddrMode {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddrMode {
     ACM0, // Accumulator
     ABS0, // Absolute
     ABSX, // Absolute Indexed with X
@@ -28,6 +55,7 @@ enum AddrMode {
 
 struct Opcode {
     instr: fn(&mut Cpu6502, &Opcode, &[u8]),
+    name: &'static str,
     mode: AddrMode,
     bytes: u8,
 }
@@ -36,1302 +64,1559 @@ static OPCODES: [Opcode; 0x100] = [
     // $00-$0F
     Opcode {
         instr: instructions::brk,
+        name: "brk",
         mode: AddrMode::IMP0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::asl,
+        name: "asl",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::php,
+        name: "php",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::asl,
+        name: "asl",
         mode: AddrMode::ACM0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::anc,
+        name: "anc",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::asl,
+        name: "asl",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $10 - $1F
     Opcode {
         instr: instructions::bpl,
+        name: "bpl",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::asl,
+        name: "asl",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::clc,
+        name: "clc",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ora,
+        name: "ora",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::asl,
+        name: "asl",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::slo,
+        name: "slo",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     // $20 - $2F
     Opcode {
         instr: instructions::jsr,
+        name: "jsr",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::bit,
+        name: "bit",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rol,
+        name: "rol",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::plp,
+        name: "plp",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rol,
+        name: "rol",
         mode: AddrMode::ACM0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::anc,
+        name: "anc",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::bit,
+        name: "bit",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rol,
+        name: "rol",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $30 - $3F
     Opcode {
         instr: instructions::bmi,
+        name: "bmi",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rol,
+        name: "rol",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sec,
+        name: "sec",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::and,
+        name: "and",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rol,
+        name: "rol",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rla,
+        name: "rla",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     // $40 - $4F
     Opcode {
         instr: instructions::rti,
+        name: "rti",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lsr,
+        name: "lsr",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::pha,
+        name: "pha",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lsr,
+        name: "lsr",
         mode: AddrMode::ACM0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::alr,
+        name: "alr",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jmp,
+        name: "jmp",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lsr,
+        name: "lsr",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $50 - $5F
     Opcode {
         instr: instructions::bvc,
+        name: "bvc",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lsr,
+        name: "lsr",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cli,
+        name: "cli",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::eor,
+        name: "eor",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lsr,
+        name: "lsr",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sre,
+        name: "sre",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     // $60 - $6F
     Opcode {
         instr: instructions::rts,
+        name: "rts",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ror,
+        name: "ror",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::pla,
+        name: "pla",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ror,
+        name: "ror",
         mode: AddrMode::ACM0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::arr,
+        name: "arr",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jmp,
+        name: "jmp",
         mode: AddrMode::IND0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ror,
+        name: "ror",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $70 - $7F
     Opcode {
         instr: instructions::bvs,
+        name: "bvs",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ror,
+        name: "ror",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sei,
+        name: "sei",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::adc,
+        name: "adc",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ror,
+        name: "ror",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::rra,
+        name: "rra",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     // $80 - $8F
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sax,
+        name: "sax",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sty,
+        name: "sty",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::stx,
+        name: "stx",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sax,
+        name: "sax",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dey,
+        name: "dey",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::txa,
+        name: "txa",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::ane,
+        name: "ane",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sty,
+        name: "sty",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::stx,
+        name: "stx",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sax,
+        name: "sax",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $90 - $9F
     Opcode {
         instr: instructions::bcc,
+        name: "bcc",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sha,
+        name: "sha",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sty,
+        name: "sty",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::stx,
+        name: "stx",
         mode: AddrMode::ZPGY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sax,
+        name: "sax",
         mode: AddrMode::ZPGY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::tya,
+        name: "tya",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::txs,
+        name: "txs",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::tas,
+        name: "tas",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::shy,
+        name: "shy",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sta,
+        name: "sta",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::shx,
+        name: "shx",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sha,
+        name: "sha",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     // $A0 - $AF
     Opcode {
         instr: instructions::ldy,
+        name: "ldy",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldx,
+        name: "ldx",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldy,
+        name: "ldy",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldx,
+        name: "ldx",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::tay,
+        name: "tay",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::tax,
+        name: "tax",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::lxa,
+        name: "lxa",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldy,
+        name: "ldy",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ldx,
+        name: "ldx",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $B0 - $BF
     Opcode {
         instr: instructions::bcs,
+        name: "bcs",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldy,
+        name: "ldy",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::ldx,
+        name: "ldx",
         mode: AddrMode::ZPGY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::ZPGY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::clv,
+        name: "clv",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::tsx,
+        name: "tsx",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::las,
+        name: "las",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ldy,
+        name: "ldy",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lda,
+        name: "lda",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::ldx,
+        name: "ldx",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::lax,
+        name: "lax",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     // $C0 - $CF
     Opcode {
         instr: instructions::cpy,
+        name: "cpy",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cpy,
+        name: "cpy",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dec,
+        name: "dec",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::iny,
+        name: "iny",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dex,
+        name: "dex",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sbx,
+        name: "sbx",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cpy,
+        name: "cpy",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::dec,
+        name: "dec",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $D0 - $DF
     Opcode {
         instr: instructions::bne,
+        name: "bne",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dec,
+        name: "dec",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cld,
+        name: "cld",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::cmp,
+        name: "cmp",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::dec,
+        name: "dec",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::dcp,
+        name: "dcp",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     // $E0 - $EF
     Opcode {
         instr: instructions::cpx,
+        name: "cpx",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::INDX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cpx,
+        name: "cpx",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::inc,
+        name: "inc",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::ZPG0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::inx,
+        name: "inx",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::usb,
+        name: "usb",
         mode: AddrMode::IMM0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::cpx,
+        name: "cpx",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::inc,
+        name: "inc",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::ABS0,
         bytes: 3,
     },
     // $F0 - $FF
     Opcode {
         instr: instructions::beq,
+        name: "beq",
         mode: AddrMode::REL0,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::jam,
+        name: "jam",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::INDY,
         bytes: 2,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::inc,
+        name: "inc",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::ZPGX,
         bytes: 2,
     },
     Opcode {
         instr: instructions::sed,
+        name: "sed",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::IMP0,
         bytes: 1,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::ABSY,
         bytes: 3,
     },
     Opcode {
         instr: instructions::nop,
+        name: "nop",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::sbc,
+        name: "sbc",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::inc,
+        name: "inc",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
     Opcode {
         instr: instructions::isc,
+        name: "isc",
         mode: AddrMode::ABSX,
         bytes: 3,
     },
 ];
 
 bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct StatusFlags: u8 {
         const N = 1 << 7;   // Negative
         const V = 1 << 6;   // Overflow
@@ -1344,6 +1629,31 @@ bitflags! {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Fetch, // Opcode fetch (the cycle SYNC is high on the real chip)
+    Read,
+    Write,
+}
+
+// A single bus cycle, as recorded by the access log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+// What the last call to tick() did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickEvent {
+    Instruction { pc: u16, opcode: u8 },
+    Interrupt { pc: u16, vector: u16 }, // pc is where execution will resume
+    Stalled, // RDY was low, so a cycle went by without anything happening
+    Halted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Registers {
     pub pc: u16,        // Program counter
     pub s: u8,          // Stack pointer
@@ -1357,8 +1667,29 @@ pub struct Cpu6502<'a> {
     pub registers: Registers,
     mem_read: MemReadCallback<'a>,
     mem_write: MemWriteCallback<'a>,
+    mem_peek: Option<MemPeekCallback<'a>>,
+    tracer: Option<trace::Tracer<'a>>,
     cycles: u8,
+    total_cycles: u64,
+    write_count: u64,
     halted: bool,
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+    rdy: bool,
+    last_event: TickEvent,
+    log_accesses: bool,
+    accesses: Vec<BusAccess>,
+    call_stack: Option<callstack::CallStack>,
+    history: Option<history::History>,
+    profiler: Option<profile::Profiler>,
+    coverage: Option<coverage::Coverage>,
+    heatmap: Option<heatmap::Heatmap>,
+    vcd: Option<vcd::VcdWriter<'a>>,
+    opcode_histogram: Option<opstats::OpcodeHistogram>,
+    page_crossed: bool,
+    recorder: Option<replay::Recorder>,
+    replayer: Option<replay::Replayer>,
 }
 
 impl<'a> Cpu6502<'a> {
@@ -1396,8 +1727,29 @@ impl<'a> Cpu6502<'a> {
 
             mem_read,
             mem_write,
+            mem_peek: None,
+            tracer: None,
             cycles: 0,
+            total_cycles: 0,
+            write_count: 0,
             halted: false,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            rdy: true,
+            last_event: TickEvent::Halted,
+            log_accesses: false,
+            accesses: Vec::new(),
+            call_stack: None,
+            history: None,
+            profiler: None,
+            coverage: None,
+            heatmap: None,
+            vcd: None,
+            opcode_histogram: None,
+            page_crossed: false,
+            recorder: None,
+            replayer: None,
         }
     }
 
@@ -1410,18 +1762,170 @@ impl<'a> Cpu6502<'a> {
         // Disable interrupts flag and extension bit should be set
         self.registers.p = StatusFlags::E | StatusFlags::I;
 
+        // The reset sequence spends 5 more cycles on internal operations before the vector fetch
+        self.total_cycles += 5;
+
         self.halted = false;
+        self.nmi_pending = false;
+
+        if let Some(call_stack) = &mut self.call_stack {
+            call_stack.clear();
+        }
+    }
+
+    /* Drive the interrupt lines, true meaning asserted (the real pins are active low). IRQ is
+    level triggered and serviced before the next instruction for as long as it's asserted and
+    the I flag is clear. NMI is edge triggered, so only asserting it after it was released
+    causes another interrupt. */
+    pub fn set_irq(&mut self, active: bool) {
+        if active != self.irq {
+            self.record_line(replay::Line::Irq, active);
+        }
+        self.irq = active;
+    }
+
+    pub fn set_nmi(&mut self, active: bool) {
+        if active != self.nmi {
+            self.record_line(replay::Line::Nmi, active);
+        }
+        if active && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = active;
+    }
+
+    /* The RDY input, true meaning ready. While it's pulled low every tick() spends a single cycle
+    waiting (the real chip waits on its next read, which between instructions is the opcode
+    fetch), the way DMA on some machines keeps the CPU off the bus. */
+    pub fn set_rdy(&mut self, ready: bool) {
+        if ready != self.rdy {
+            self.record_line(replay::Line::Rdy, ready);
+        }
+        self.rdy = ready;
+    }
+
+    pub fn rdy_line(&self) -> bool {
+        self.rdy
+    }
+
+    // True if the next tick() will service an interrupt rather than execute an instruction
+    pub fn interrupt_pending(&self) -> bool {
+        !self.halted && (self.nmi_pending || self.irq && !self.registers.p.contains(StatusFlags::I))
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.irq
+    }
+
+    pub fn nmi_line(&self) -> bool {
+        self.nmi
+    }
+
+    /* Optionally provide a way to look at memory without side effects (no I/O register reads,
+    no cycles spent). Debugging features such as the tracer use this to resolve operand values. */
+    pub fn set_mem_peek(&mut self, mem_peek: MemPeekCallback<'a>) {
+        self.mem_peek = Some(mem_peek);
+    }
+
+    pub fn peek(&self, address: usize) -> Option<u8> {
+        self.mem_peek.as_ref().map(|peek| peek(address))
+    }
+
+    // Write memory from outside the CPU (a debugger, say) without it counting as a bus cycle
+    pub fn poke(&mut self, address: usize, value: u8) {
+        (self.mem_write)(address, value);
+    }
+
+    // Log every instruction executed by tick() (see the trace module)
+    pub fn set_tracer(&mut self, tracer: trace::Tracer<'a>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<trace::Tracer<'a>> {
+        self.tracer.take()
+    }
+
+    // Number of cycles executed since the CPU was created
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn last_event(&self) -> TickEvent {
+        self.last_event
+    }
+
+    /* When enabled, every bus cycle of the last tick() is kept so debugging tools can see what
+    memory an instruction touched without hooking the memory callbacks. */
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.log_accesses = enabled;
+        self.accesses.clear();
+    }
+
+    pub fn last_accesses(&self) -> &[BusAccess] {
+        &self.accesses
+    }
+
+    // Keep a shadow call stack of subroutine calls and interrupts (see the callstack module)
+    pub fn set_call_tracking(&mut self, enabled: bool) {
+        self.call_stack = match enabled {
+            true => Some(self.call_stack.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    pub fn call_stack(&self) -> Option<&callstack::CallStack> {
+        self.call_stack.as_ref()
     }
 
     pub fn tick(&mut self) -> u8 {
         self.cycles = 0;
+        self.accesses.clear();
+        self.replay_lines();
 
         if self.halted {
+            self.last_event = TickEvent::Halted;
             return 0;
         } // Do nothing if halted, typically after encountering a 'jam'
 
+        if !self.rdy {
+            self.cycles = 1;
+            self.total_cycles += 1;
+            self.last_event = TickEvent::Stalled;
+            self.vcd_stall();
+            return self.cycles;
+        }
+
+        self.record_step();
+        self.checkpoint();
+        self.cover_start();
+        self.heat_start();
+
+        // Interrupts are checked between instructions, NMI taking priority
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI_VECTOR);
+            return self.cycles;
+        }
+        if self.irq && !self.registers.p.contains(StatusFlags::I) {
+            self.interrupt(INTR_VECTOR);
+            return self.cycles;
+        }
+
+        let (pc, s, cycle) = (self.registers.pc, self.registers.s, self.total_cycles);
+        self.vcd_fetch();
         let fetch = self.read(self.registers.pc as usize) as usize;
         let opcode = &OPCODES[fetch];
+        if let Some(access) = self.accesses.last_mut() {
+            access.kind = AccessKind::Fetch;
+        }
+        self.last_event = TickEvent::Instruction {
+            pc: self.registers.pc,
+            opcode: fetch as u8,
+        };
 
         // Find more Rusty way to handle this...
         let mut operands = [0, 0];
@@ -1438,21 +1942,91 @@ impl<'a> Cpu6502<'a> {
             }
         }
 
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.log(self, fetch as u8, &operands);
+            self.tracer = Some(tracer);
+        }
+
         self.registers.pc = self.registers.pc.wrapping_add(opcode.bytes as u16);
+        self.page_crossed = false;
         (opcode.instr)(self, opcode, &operands);
 
+        if let Some(call_stack) = &mut self.call_stack {
+            call_stack.instruction(fetch as u8, pc, s, &self.registers, cycle);
+        }
+        self.profile_instruction(fetch as u8, pc, s, cycle);
+        self.cover_instruction(fetch as u8, pc);
+        self.heat_instruction(fetch as u8, pc);
+        self.count_opcode(fetch as u8);
+
         self.cycles
     }
 
+    // Hardware interrupt sequence, same as BRK except the B flag isn't pushed
+    fn interrupt(&mut self, vector: usize) {
+        let (pc, s, cycle) = (self.registers.pc, self.registers.s, self.total_cycles);
+
+        // Opcode fetch and operand read are performed but discarded
+        self.vcd_fetch();
+        self.read(self.registers.pc as usize);
+        self.read(self.registers.pc as usize);
+
+        instructions::stack_push16(self, self.registers.pc);
+        let psw = (self.registers.p - StatusFlags::B) | StatusFlags::E;
+        instructions::stack_push(self, psw.bits());
+
+        self.registers.p |= StatusFlags::I;
+
+        let lsb = self.read(vector) as u16;
+        let msb = self.read(vector + 1) as u16;
+        self.last_event = TickEvent::Interrupt {
+            pc,
+            vector: vector as u16,
+        };
+        self.registers.pc = msb << 8 | lsb;
+
+        if let Some(call_stack) = &mut self.call_stack {
+            call_stack.interrupt(pc, s, vector as u16, self.registers.pc, cycle);
+        }
+        self.profile_interrupt(pc, s, vector as u16, cycle);
+    }
+
     fn read(&mut self, address: usize) -> u8 {
         self.cycles += 1;
-        (self.mem_read)(address)
+        self.total_cycles += 1;
+        let value = match self.replay_read(address) {
+            Some(value) => value,
+            None => (self.mem_read)(address),
+        };
+        self.record_read(address, value);
+        self.cover_read(address);
+        self.heat_read(address);
+        self.vcd_cycle(address, value, true);
+        self.log_access(address, value, AccessKind::Read);
+        value
     }
 
     fn write(&mut self, address: usize, value: u8) {
         self.cycles += 1;
+        self.total_cycles += 1;
+        self.write_count += 1;
+        self.log_access(address, value, AccessKind::Write);
+        self.record_write(address, value);
+        self.cover_write(address);
+        self.heat_write(address);
+        self.vcd_cycle(address, value, false);
         (self.mem_write)(address, value)
     }
+
+    fn log_access(&mut self, address: usize, value: u8, kind: AccessKind) {
+        if self.log_accesses {
+            self.accesses.push(BusAccess {
+                address: address as u16,
+                value,
+                kind,
+            });
+        }
+    }
 }
 
 pub mod instructions {
@@ -1489,7 +2063,7 @@ pub mod instructions {
         read: bool,
         cond_read: bool,
     ) -> (usize, u8, bool) {
-        match mode {
+        let (addr, value, pgx) = match mode {
             AddrMode::ABS0 => {
                 let addr = (operands[1] as usize) << 8 | operands[0] as usize;
                 let value = check_read(cpu, addr, 0, true, read, cond_read);
@@ -1598,7 +2172,10 @@ pub mod instructions {
             AddrMode::ACM0 => (0, cpu.registers.a, false),
             AddrMode::IMM0 => (0, operands[0], false),
             AddrMode::IMP0 => (0, 0, false),
-        }
+        };
+
+        cpu.page_crossed = pgx;
+        (addr, value, pgx)
     }
 
     // Commonly performed by quite a few instructions
@@ -1612,7 +2189,7 @@ pub mod instructions {
     }
 
     // For easy stack manipulation
-    fn stack_push(cpu: &mut Cpu6502, value: u8) {
+    pub(super) fn stack_push(cpu: &mut Cpu6502, value: u8) {
         cpu.write(STACK_OFFSET + cpu.registers.s as usize, value);
         cpu.registers.s = cpu.registers.s.wrapping_sub(1);
     }
@@ -1620,7 +2197,7 @@ pub mod instructions {
         cpu.registers.s = cpu.registers.s.wrapping_add(1);
         cpu.read(STACK_OFFSET + cpu.registers.s as usize)
     }
-    fn stack_push16(cpu: &mut Cpu6502, value: u16) {
+    pub(super) fn stack_push16(cpu: &mut Cpu6502, value: u16) {
         stack_push(cpu, (value >> 8) as u8);
         stack_push(cpu, (value & 0xFF) as u8);
     }
@@ -2036,12 +2613,12 @@ pub mod instructions {
         //cpu.cycles -= 2;
 
         // Reset the PC to just after opcode fetch (which was originally incremented during tick())
-        cpu.registers.pc -= (opcode.bytes - 1) as u16;
+        cpu.registers.pc = cpu.registers.pc.wrapping_sub((opcode.bytes - 1) as u16);
 
         // Fetch the low byte of jump address, then increment pc
         //let adl: u16 = cpu.ram[cpu.registers.pc as usize] as u16;
         let adl: u16 = cpu.read(cpu.registers.pc as usize) as u16;
-        cpu.registers.pc += 1;
+        cpu.registers.pc = cpu.registers.pc.wrapping_add(1);
 
         // Strange dummy read (sometimes things are just magic ya know?)
         cpu.read(STACK_OFFSET + cpu.registers.s as usize);
@@ -2056,8 +2633,8 @@ pub mod instructions {
     }
     pub(super) fn rts(cpu: &mut Cpu6502, opcode: &Opcode, operands: &[u8]) {
         cpu.read(STACK_OFFSET + cpu.registers.s as usize); // Dummy read
-        cpu.registers.pc = stack_pop16(cpu) + 1;
-        cpu.read((cpu.registers.pc - 1) as usize); // Another dummy read
+        cpu.registers.pc = stack_pop16(cpu).wrapping_add(1);
+        cpu.read(cpu.registers.pc.wrapping_sub(1) as usize); // Another dummy read
     }
 
     // Branch Operations
@@ -2068,6 +2645,9 @@ pub mod instructions {
         let branch_set = set && (cpu.registers.p.bits() & flag.bits()) != 0;
         let branch_clr = !set && (cpu.registers.p.bits() & flag.bits()) == 0;
 
+        // The target is only on another page if the branch is actually taken
+        cpu.page_crossed = pgx && (branch_set || branch_clr);
+
         if branch_set || branch_clr {
             cpu.read(cpu.registers.pc as usize); // Dummy read if branch
 
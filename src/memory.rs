@@ -0,0 +1,64 @@
+use super::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const MEM_SIZE: usize = 0x10000;
+
+/* A flat 64K of RAM for when the CPU doesn't need any memory mapped devices. The memory is
+shared, so a clone of the handle can be kept around to inspect or modify memory while the CPU
+owns the callbacks:
+
+    let ram = Ram::new();
+    ram.load(0x0400, &program);
+    let mut cpu = ram.cpu();
+*/
+#[derive(Clone)]
+pub struct Ram(Rc<RefCell<Vec<u8>>>);
+
+impl Ram {
+    pub fn new() -> Self {
+        Ram(Rc::new(RefCell::new(vec![0; MEM_SIZE])))
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.0.borrow()[address as usize]
+    }
+
+    pub fn write(&self, address: u16, value: u8) {
+        self.0.borrow_mut()[address as usize] = value;
+    }
+
+    // Copy data into memory starting at address, wrapping around at the end of memory
+    pub fn load(&self, address: u16, data: &[u8]) {
+        let mut ram = self.0.borrow_mut();
+        for (i, b) in data.iter().enumerate() {
+            ram[address.wrapping_add(i as u16) as usize] = *b;
+        }
+    }
+
+    pub fn dump(&self, address: u16, len: usize) -> Vec<u8> {
+        let ram = self.0.borrow();
+        (0..len)
+            .map(|i| ram[address.wrapping_add(i as u16) as usize])
+            .collect()
+    }
+
+    // A CPU whose read, write and peek callbacks all go to this memory
+    pub fn cpu<'a>(&self) -> Cpu6502<'a> {
+        let (read, write, peek) = (self.clone(), self.clone(), self.clone());
+
+        let mut cpu = Cpu6502::new(
+            Box::new(move |address: usize| read.read(address as u16)),
+            Box::new(move |address: usize, value: u8| write.write(address as u16, value)),
+        );
+        cpu.set_mem_peek(Box::new(move |address: usize| peek.read(address as u16)));
+        cpu
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Ram::new()
+    }
+}
@@ -0,0 +1,779 @@
+use crate::asm;
+use crate::debugger::expr::Expr;
+use crate::debugger::*;
+use crate::debuginfo::DebugInfo;
+use crate::disasm::Instruction;
+use crate::history::History;
+use crate::memory::Ram;
+use crate::run::StopHandle;
+use crate::trace::flag_string;
+use crate::*;
+use std::fmt;
+use std::fs;
+
+/* A machine language monitor in the style of the classic C64/Apple ones, working on a CPU with
+64K of RAM. Commands are executed one line at a time and return the text to show, so the same
+monitor can sit behind a terminal (see src/bin/monitor.rs) or be scripted.
+
+Addresses and values are hex by default (d 400), anything that isn't plain hex is evaluated as
+an expression (d pc, m word[$FE], b main+3). With debug info loaded an address can also be a
+source line (b main.s:42). See HELP for the commands. */
+
+const HELP: &str = "\
+r [reg=val ...]          show or set registers (a x y sp pc p)
+m [start [end]]          hex dump memory
+> addr byte ...          write bytes to memory
+f start end byte         fill memory
+d [start [end]]          disassemble
+a addr [instr]           assemble, continuing on the next lines until an empty one
+l file [addr]            load a binary (a PRG with its load address if none is given)
+s file start end         save memory to a file
+z [count]                step instructions
+n                        step over a subroutine call
+ret                      run until the current subroutine returns
+t [count]                trace, stepping and showing every instruction
+g [addr]                 go, optionally from a new address
+u addr                   run until the PC reaches an address
+hist [on|off]            show, start or stop recording execution history
+bz [count]               step back through the history
+bg                       go back until a breakpoint or a write to a watched address
+bw addr                  go back to the instruction that last wrote an address
+seek cycle               go to a cycle count within the history
+b addr [if cond]         set a breakpoint
+w [r|w] start [end] [if cond]  set a watchpoint
+op (byte|ill)            break on an opcode, or on any undocumented one
+bl                       list breakpoints
+del (id|all)             delete breakpoints
+en id / dis id           enable or disable a breakpoint
+cond id [expr]           set or clear a breakpoint condition
+p expr                   evaluate an expression
+bt                       show the call stack
+sym [name addr]          list or add symbols
+dbg file                 load symbols and lines from ld65 debug info or a VICE label file
+gdb [port]               wait for a GDB remote protocol debugger on localhost (default 6502)
+vice [port]              wait for a VICE binary monitor client on localhost (default 6502)
+reset                    reset the CPU through the reset vector
+x                        exit
+";
+
+// Number of lines shown by d and m when no end is given, m showing 16 bytes a line
+const DEFAULT_LINES: u16 = 16;
+const DEFAULT_DUMP_LINES: u16 = 8;
+
+// Cycles run between looking at whether to stop, see interrupt_handle()
+const SLICE_CYCLES: u64 = 100_000;
+
+// Port the gdb and vice commands listen on by default (VICE's own default)
+const SERVER_PORT: u16 = 6502;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandError(pub String);
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn error<T>(message: impl Into<String>) -> Result<T, CommandError> {
+    Err(CommandError(message.into()))
+}
+
+pub struct Monitor {
+    pub debugger: Debugger<'static>,
+    pub ram: Ram,
+
+    // Where m and d continue when given no address
+    dump_next: u16,
+    disasm_next: u16,
+
+    // Address of the next line to assemble while in assembly mode
+    assembling: Option<u16>,
+    done: bool,
+
+    interrupt: StopHandle,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor::with_ram(Ram::new())
+    }
+
+    pub fn with_ram(ram: Ram) -> Self {
+        let mut debugger = Debugger::new(ram.cpu());
+        debugger.cpu.registers.s = 0xFF;
+        debugger.cpu.registers.p = StatusFlags::E | StatusFlags::I;
+
+        Monitor {
+            debugger,
+            ram,
+            dump_next: 0,
+            disasm_next: 0,
+            assembling: None,
+            done: false,
+            interrupt: StopHandle::new(),
+        }
+    }
+
+    // Set once the exit command has been given
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /* Stopping through this breaks into g, n, ret and u, which otherwise run for as long as the
+    program does. The monitor binary stops it on Ctrl-C. */
+    pub fn interrupt_handle(&self) -> StopHandle {
+        self.interrupt.clone()
+    }
+
+    // Run in slices until the goal is reached, something stops the CPU or we're interrupted
+    fn run(&mut self, mut goal: Goal) -> Stop {
+        self.interrupt.reset();
+        let mut resuming = true;
+        loop {
+            let slice = self
+                .debugger
+                .run_slice_to(&mut goal, SLICE_CYCLES, resuming);
+            resuming = false;
+            match slice {
+                Stop::CycleLimit if self.interrupt.is_stopped() => return Stop::Interrupted,
+                Stop::CycleLimit => {}
+                stop => return stop,
+            }
+        }
+    }
+
+    /* What g, n, ret and u run until, None for other commands and for an n that's just a step.
+    Front ends that run the CPU in slices themselves use this instead of execute() for those. */
+    pub fn run_goal(&mut self, line: &str) -> Result<Option<Goal>, CommandError> {
+        if self.assembling.is_some() {
+            return Ok(None);
+        }
+
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default().to_ascii_lowercase();
+        let args: Vec<&str> = words.collect();
+        match command.as_str() {
+            "n" => Ok(self.debugger.step_over_goal()),
+            "ret" => Ok(Some(self.debugger.step_out_goal())),
+            "g" => {
+                if let Some(address) = args.first() {
+                    self.debugger.cpu.registers.pc = self.value(address)?;
+                }
+                Ok(Some(Goal::Continue))
+            }
+            "u" => Ok(Some(Goal::Address(self.arg(&args, 0)?))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        match self.assembling {
+            Some(address) => format!("A {:04X} ", address),
+            None => ". ".to_string(),
+        }
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<String, CommandError> {
+        if let Some(address) = self.assembling {
+            if line.trim().is_empty() {
+                self.assembling = None;
+                return Ok(String::new());
+            }
+            return self.assemble(address, line);
+        }
+
+        // A condition is everything after " if "
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(parse_expr(condition)?)),
+            None => (line, None),
+        };
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(String::new());
+        };
+        let args: Vec<&str> = words.collect();
+
+        // Allow the address to stick to the edit command (">0400 01 02")
+        let (command, args) = match command.strip_prefix('>') {
+            Some(rest) if !rest.is_empty() => (">", [&[rest], &args[..]].concat()),
+            _ => (command, args),
+        };
+
+        let command = command.to_ascii_lowercase();
+        match command.as_str() {
+            "help" | "h" => Ok(HELP.to_string()),
+            "r" => self.registers(&args),
+            "m" => self.dump(&args),
+            ">" => self.edit(&args),
+            "f" => self.fill(&args),
+            "d" => self.disassemble(&args),
+            "a" => match args.split_first() {
+                Some((address, instr)) => {
+                    let address = self.value(address)?;
+                    if instr.is_empty() {
+                        self.assembling = Some(address);
+                        return Ok(String::new());
+                    }
+
+                    // Keep assembling after the instruction once it went in fine
+                    let text = self.assemble(address, &instr.join(" "))?;
+                    self.assembling = Some(self.disasm_next);
+                    Ok(text)
+                }
+                None => error("Missing address"),
+            },
+            "l" => self.load(&args),
+            "s" => self.save(&args),
+            "z" => self.step(&args, false),
+            "t" => self.step(&args, true),
+            "n" | "ret" | "g" | "u" => {
+                let stop = match self.run_goal(line)? {
+                    Some(goal) => self.run(goal),
+                    None => self.debugger.step(), // An n that isn't over a JSR
+                };
+                Ok(self.stopped(stop))
+            }
+            "hist" => self.history(&args),
+            "bz" => {
+                let count = match args.first() {
+                    Some(count) => self.value(count)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if self.debugger.step_back() == Stop::HistoryStart {
+                        return Ok(self.stopped(Stop::HistoryStart));
+                    }
+                }
+                Ok(self.current())
+            }
+            "bg" => {
+                let stop = self.debugger.reverse_cont();
+                Ok(self.stopped(stop))
+            }
+            "bw" => {
+                let address = self.arg(&args, 0)?;
+                match self.debugger.cpu.reverse_to_write(address) {
+                    Some(_) => Ok(self.current()),
+                    None => error(format!("No write to ${:04X} in the history", address)),
+                }
+            }
+            "seek" => {
+                let Some(Ok(cycle)) = args.first().map(|c| c.parse()) else {
+                    return error("Expected a cycle count");
+                };
+                match self.debugger.cpu.seek(cycle) {
+                    Some(_) => Ok(self.current()),
+                    None => error(format!("Cycle {} is before the history starts", cycle)),
+                }
+            }
+            "b" => {
+                let address = self.arg(&args, 0)?;
+                Ok(self.add_breakpoint(BreakKind::Exec(address), condition))
+            }
+            "w" => self.watch(&args, condition),
+            "op" => {
+                let what = match args.first() {
+                    Some(arg) if arg.eq_ignore_ascii_case("ill") => OpcodeMatch::Undocumented,
+                    Some(arg) => OpcodeMatch::Opcode(self.byte(arg)?),
+                    None => return error("Missing opcode"),
+                };
+                Ok(self.add_breakpoint(BreakKind::Opcode(what), condition))
+            }
+            "bl" => Ok(self.list_breakpoints()),
+            "del" => match args.first() {
+                Some(&"all") => {
+                    self.debugger.clear();
+                    Ok(String::new())
+                }
+                _ => {
+                    let id = self.id(&args)?;
+                    self.debugger.remove(id);
+                    Ok(String::new())
+                }
+            },
+            "en" | "dis" => {
+                let id = self.id(&args)?;
+                self.debugger.enable(id, command == "en");
+                Ok(String::new())
+            }
+            "cond" => {
+                let id = self.id(&args)?;
+                let condition = match args.len() > 1 {
+                    true => Some(parse_expr(&args[1..].join(" "))?),
+                    false => None,
+                };
+                self.debugger.set_condition(id, condition);
+                Ok(String::new())
+            }
+            "p" => {
+                let expr = parse_expr(&args.join(" "))?;
+                match self.debugger.eval(&expr) {
+                    Ok(value) => Ok(format!("${:X} {} %{:b}\n", value, value, value)),
+                    Err(e) => error(e.to_string()),
+                }
+            }
+            "bt" => Ok(self.debugger.backtrace()),
+            "sym" => self.symbols(&args),
+            "dbg" => self.load_debug_info(&args),
+            "gdb" | "vice" => {
+                let port = match args.first() {
+                    Some(port) => port.parse().or_else(|_| error("Bad port"))?,
+                    None => SERVER_PORT,
+                };
+                let address = ("127.0.0.1", port);
+                let result = match command.as_str() {
+                    "gdb" => gdb::serve(&mut self.debugger, address),
+                    _ => vice::serve(&mut self.debugger, address),
+                };
+                match result {
+                    Ok(()) => Ok(self.current()),
+                    Err(e) => error(e.to_string()),
+                }
+            }
+            "reset" => {
+                self.debugger.cpu.reset();
+                Ok(self.current())
+            }
+            "x" | "q" | "exit" | "quit" => {
+                self.done = true;
+                Ok(String::new())
+            }
+            other => error(format!("Unknown command '{}'", other)),
+        }
+    }
+
+    // Plain hex, a source line, or else an expression
+    fn value(&self, text: &str) -> Result<u16, CommandError> {
+        if let Some((file, line)) = text.rsplit_once(':') {
+            if let (false, Ok(line)) = (file.is_empty(), line.parse()) {
+                return match self.debugger.lines.address(file, line) {
+                    Some((address, _)) => Ok(address),
+                    None => error(format!("No code for {}", text)),
+                };
+            }
+        }
+
+        if !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit()) {
+            return match u16::from_str_radix(text, 16) {
+                Ok(value) => Ok(value),
+                Err(_) => error(format!("'{}' is out of range", text)),
+            };
+        }
+
+        let expr = parse_expr(text)?;
+        match self.debugger.eval(&expr) {
+            Ok(value) => Ok(value as u16),
+            Err(e) => error(e.to_string()),
+        }
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, CommandError> {
+        match self.value(text)? {
+            value if value > 0xFF => error(format!("'{}' is more than a byte", text)),
+            value => Ok(value as u8),
+        }
+    }
+
+    fn arg(&self, args: &[&str], index: usize) -> Result<u16, CommandError> {
+        match args.get(index) {
+            Some(arg) => self.value(arg),
+            None => error("Missing address"),
+        }
+    }
+
+    fn id(&self, args: &[&str]) -> Result<BreakpointId, CommandError> {
+        match args.first().map(|id| id.parse()) {
+            Some(Ok(id)) => Ok(id),
+            _ => error("Missing breakpoint number"),
+        }
+    }
+
+    // An optional start and end, start defaulting to where the last listing left off
+    fn range(&self, args: &[&str], next: u16) -> Result<(u16, Option<u16>), CommandError> {
+        let start = match args.first() {
+            Some(arg) => self.value(arg)?,
+            None => next,
+        };
+        let end = match args.get(1) {
+            Some(arg) => Some(self.value(arg)?),
+            None => None,
+        };
+        Ok((start, end))
+    }
+
+    fn registers(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        for arg in args {
+            let Some((reg, value)) = arg.split_once('=') else {
+                return error(format!("Expected reg=value, got '{}'", arg));
+            };
+            let reg = reg.to_ascii_lowercase();
+            if reg == "pc" {
+                self.debugger.cpu.registers.pc = self.value(value)?;
+                continue;
+            }
+
+            let value = self.byte(value)?;
+            let r = &mut self.debugger.cpu.registers;
+            match reg.as_str() {
+                "a" => r.a = value,
+                "x" => r.x = value,
+                "y" => r.y = value,
+                "sp" | "s" => r.s = value,
+                "p" => r.p = StatusFlags::from_bits_retain(value),
+                _ => return error(format!("Unknown register '{}'", reg)),
+            }
+        }
+        Ok(self.register_line())
+    }
+
+    fn register_line(&self) -> String {
+        let r = &self.debugger.cpu.registers;
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}\n",
+            r.pc,
+            r.a,
+            r.x,
+            r.y,
+            r.s,
+            r.p.bits(),
+            flag_string(r.p),
+            self.debugger.cpu.total_cycles()
+        )
+    }
+
+    fn dump(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let (start, end) = self.range(args, self.dump_next)?;
+        let end = end.unwrap_or(start.wrapping_add(DEFAULT_DUMP_LINES * 16 - 1));
+        let len = end.wrapping_sub(start) as usize + 1;
+
+        let mut out = String::new();
+        for offset in (0..len).step_by(16) {
+            let address = start.wrapping_add(offset as u16);
+            let bytes = self.ram.dump(address, (len - offset).min(16));
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|b| match b.is_ascii_graphic() || *b == b' ' {
+                    true => *b as char,
+                    false => '.',
+                })
+                .collect();
+            out += &format!("{:04X}  {:<48} {}\n", address, hex.join(" "), text);
+        }
+
+        self.dump_next = end.wrapping_add(1);
+        Ok(out)
+    }
+
+    fn edit(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let address = self.arg(args, 0)?;
+        let bytes = args[1..]
+            .iter()
+            .map(|b| self.byte(b))
+            .collect::<Result<Vec<u8>, _>>()?;
+        self.ram.load(address, &bytes);
+        Ok(String::new())
+    }
+
+    fn fill(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let (start, end) = (self.arg(args, 0)?, self.arg(args, 1)?);
+        let Some(value) = args.get(2) else {
+            return error("Missing fill byte");
+        };
+        let value = self.byte(value)?;
+
+        let len = end.wrapping_sub(start) as usize + 1;
+        self.ram.load(start, &vec![value; len]);
+        Ok(String::new())
+    }
+
+    fn disassemble(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let (start, end) = self.range(args, self.disasm_next)?;
+
+        let mut out = String::new();
+        let mut address = start;
+        for line in 0.. {
+            let done = match end {
+                Some(end) => address.wrapping_sub(start) > end.wrapping_sub(start),
+                None => line == DEFAULT_LINES,
+            };
+            if done {
+                break;
+            }
+
+            let (text, next) = self.disasm_line(address);
+            out += &text;
+            if next < address {
+                break; // Wrapped around the end of memory
+            }
+            address = next;
+        }
+
+        self.disasm_next = address;
+        Ok(out)
+    }
+
+    // One disassembled instruction, marked with '>' at the PC and '*' on breakpoints
+    pub fn disasm_line(&self, address: u16) -> (String, u16) {
+        let instr = Instruction::fetch(address, |a| self.ram.read(a));
+        let bytes: Vec<String> = instr.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+
+        let breakpoint = self
+            .debugger
+            .breakpoints()
+            .iter()
+            .any(|b| b.enabled && b.kind == BreakKind::Exec(address));
+        let marker = match (address == self.debugger.cpu.registers.pc, breakpoint) {
+            (true, _) => '>',
+            (false, true) => '*',
+            (false, false) => ' ',
+        };
+
+        let mut text = String::new();
+        if let Some(label) = self.debugger.symbols.name(address) {
+            text += &format!("{}:\n", label);
+        }
+        let line = format!(
+            "{}{:04X}  {:<9} {}",
+            marker,
+            address,
+            bytes.join(" "),
+            self.debugger.disassemble(&instr)
+        );
+
+        // The source line, on the first instruction assembled from it
+        let location = self
+            .debugger
+            .lines
+            .line_at(address)
+            .and(self.debugger.lines.describe(address));
+        text += &match location {
+            Some(location) => format!("{:<32} ; {}\n", line, location),
+            None => line + "\n",
+        };
+        (text, instr.next())
+    }
+
+    fn assemble(&mut self, address: u16, instr: &str) -> Result<String, CommandError> {
+        let bytes = match asm::assemble(instr, address, Some(&self.debugger.symbols)) {
+            Ok(bytes) => bytes,
+            Err(e) => return error(e.to_string()),
+        };
+        self.ram.load(address, &bytes);
+
+        let (text, next) = self.disasm_line(address);
+        if self.assembling.is_some() {
+            self.assembling = Some(next);
+        }
+        self.disasm_next = next;
+        Ok(text)
+    }
+
+    fn load(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let Some(file) = args.first() else {
+            return error("Missing file name");
+        };
+        let file = file.trim_matches('"');
+        let data = match fs::read(file) {
+            Ok(data) => data,
+            Err(e) => return error(format!("Can't read {}: {}", file, e)),
+        };
+
+        // Without an address it's a PRG, which starts with its load address
+        let (address, data) = match args.get(1) {
+            Some(address) => (self.value(address)?, &data[..]),
+            None if data.len() >= 2 => (u16::from_le_bytes([data[0], data[1]]), &data[2..]),
+            None => return error("Missing load address"),
+        };
+        if data.is_empty() {
+            return error(format!("{} is empty", file));
+        }
+        if data.len() > memory::MEM_SIZE {
+            return error(format!("{} is bigger than 64K", file));
+        }
+
+        self.ram.load(address, data);
+        let end = address.wrapping_add((data.len() - 1) as u16);
+        self.disasm_next = address;
+        self.dump_next = address;
+        Ok(format!("Loaded ${:04X}-${:04X}\n", address, end))
+    }
+
+    fn save(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let Some(file) = args.first() else {
+            return error("Missing file name");
+        };
+        let file = file.trim_matches('"');
+        let (start, end) = (self.arg(args, 1)?, self.arg(args, 2)?);
+
+        let len = end.wrapping_sub(start) as usize + 1;
+        match fs::write(file, self.ram.dump(start, len)) {
+            Ok(_) => Ok(format!("Saved ${:04X}-${:04X}\n", start, end)),
+            Err(e) => error(format!("Can't write {}: {}", file, e)),
+        }
+    }
+
+    fn step(&mut self, args: &[&str], trace: bool) -> Result<String, CommandError> {
+        let count = match args.first() {
+            Some(count) => self.value(count)?,
+            None => 1,
+        };
+
+        let mut out = String::new();
+        for _ in 0..count {
+            if trace {
+                out += &self.disasm_line(self.debugger.cpu.registers.pc).0;
+            }
+            match self.debugger.step() {
+                Stop::Step => {}
+                stop => return Ok(out + &self.stopped(stop)),
+            }
+        }
+        Ok(out + &self.current())
+    }
+
+    // Report why the CPU stopped, followed by where it is now
+    fn stopped(&mut self, stop: Stop) -> String {
+        let mut out = match stop {
+            Stop::Step => String::new(),
+            _ => format!("{}\n", stop),
+        };
+        out += &self.current();
+        if stop == Stop::Halted {
+            out += &self.debugger.backtrace();
+        }
+        out
+    }
+
+    fn current(&mut self) -> String {
+        let pc = self.debugger.cpu.registers.pc;
+        self.disasm_next = pc;
+        self.disasm_line(pc).0 + &self.register_line()
+    }
+
+    fn add_breakpoint(&mut self, kind: BreakKind, condition: Option<Expr>) -> String {
+        let id = self.debugger.add(kind);
+        self.debugger.set_condition(id, condition);
+        format!("Breakpoint {}\n", id)
+    }
+
+    fn watch(&mut self, args: &[&str], condition: Option<Expr>) -> Result<String, CommandError> {
+        let (kind, args) = match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+            Some("r") => (WatchKind::Read, &args[1..]),
+            Some("w") => (WatchKind::Write, &args[1..]),
+            Some("rw") => (WatchKind::Access, &args[1..]),
+            _ => (WatchKind::Access, args),
+        };
+        let start = self.arg(args, 0)?;
+        let end = match args.get(1) {
+            Some(end) => self.value(end)?,
+            None => start,
+        };
+        Ok(self.add_breakpoint(BreakKind::Watch { start, end, kind }, condition))
+    }
+
+    fn list_breakpoints(&self) -> String {
+        let mut out = String::new();
+        for b in self.debugger.breakpoints() {
+            let what = match b.kind {
+                BreakKind::Exec(address) => match self.debugger.describe(address) {
+                    Some(place) => format!("exec  ${:04X} {}", address, place),
+                    None => format!("exec  ${:04X}", address),
+                },
+                BreakKind::ExecRange { start, end } => {
+                    format!("exec  ${:04X}-${:04X}", start, end)
+                }
+                BreakKind::Watch { start, end, kind } => {
+                    let kind = match kind {
+                        WatchKind::Read => "read ",
+                        WatchKind::Write => "write",
+                        WatchKind::Access => "rw   ",
+                    };
+                    match start == end {
+                        true => format!("{} ${:04X}", kind, start),
+                        false => format!("{} ${:04X}-${:04X}", kind, start, end),
+                    }
+                }
+                BreakKind::Opcode(OpcodeMatch::Opcode(op)) => format!("op    ${:02X}", op),
+                BreakKind::Opcode(OpcodeMatch::Undocumented) => "op    undocumented".to_string(),
+            };
+
+            out += &format!("{:>3}  {}", b.id, what);
+            if !b.enabled {
+                out += "  (disabled)";
+            }
+            if let Some(condition) = &b.condition {
+                out += &format!("  if {}", condition);
+            }
+            out += &format!("  hits {}\n", b.hits);
+        }
+        out
+    }
+
+    fn symbols(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        match args {
+            [] => Ok(self
+                .debugger
+                .symbols
+                .iter()
+                .map(|(name, address)| format!("${:04X}  {}\n", address, name))
+                .collect()),
+            [name, address] => {
+                let address = self.value(address)?;
+                self.debugger.symbols.insert(name, address);
+                Ok(String::new())
+            }
+            _ => error("Expected a name and an address"),
+        }
+    }
+
+    fn history(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let cpu = &mut self.debugger.cpu;
+        match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+            Some("on") if cpu.history().is_none() => cpu.set_history(Some(History::default())),
+            Some("on") => {}
+            Some("off") => cpu.set_history(None),
+            Some(other) => return error(format!("Expected on or off, got '{}'", other)),
+            None => {}
+        }
+
+        Ok(match cpu.history() {
+            Some(history) => format!(
+                "History from cycle {}: {} steps back, {} forward\n",
+                history.oldest_cycle().unwrap_or(cpu.total_cycles()),
+                history.steps_back(),
+                history.steps_forward()
+            ),
+            None => "History is off\n".to_string(),
+        })
+    }
+
+    fn load_debug_info(&mut self, args: &[&str]) -> Result<String, CommandError> {
+        let Some(file) = args.first() else {
+            return error("Missing file name");
+        };
+        match DebugInfo::load(file.trim_matches('"')) {
+            Ok(info) => {
+                self.debugger.add_debug_info(&info);
+                Ok(format!(
+                    "Loaded {} symbols and lines from {} files\n",
+                    info.symbols.len(),
+                    info.lines.files().len()
+                ))
+            }
+            Err(e) => error(e.to_string()),
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+fn parse_expr(text: &str) -> Result<Expr, CommandError> {
+    Expr::parse(text).map_err(|e| CommandError(e.to_string()))
+}
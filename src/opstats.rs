@@ -0,0 +1,194 @@
+use super::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/* Instruction set coverage: which of the 256 opcodes and which addressing modes a program
+actually used, how often, how many cycles they took and how often their effective address (or a
+taken branch) crossed a page. Mostly useful for finding out what undocumented opcodes some
+software relies on, since those do something else entirely on a 65C02.
+
+    cpu.set_opcode_histogram(Some(OpcodeHistogram::new()));
+    ...
+    print!("{}", cpu.opcode_histogram().unwrap().report()); */
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpcodeStats {
+    pub count: u64,
+    pub cycles: u64,
+    pub page_crossings: u64,
+}
+
+impl OpcodeStats {
+    fn add(&mut self, other: &OpcodeStats) {
+        self.count += other.count;
+        self.cycles += other.cycles;
+        self.page_crossings += other.page_crossings;
+    }
+}
+
+pub struct OpcodeHistogram {
+    opcodes: [OpcodeStats; 0x100],
+}
+
+impl OpcodeHistogram {
+    pub fn new() -> Self {
+        OpcodeHistogram {
+            opcodes: [OpcodeStats::default(); 0x100],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = OpcodeHistogram::new();
+    }
+
+    pub fn opcode(&self, opcode: u8) -> OpcodeStats {
+        self.opcodes[opcode as usize]
+    }
+
+    // The opcodes that executed, the most used first
+    pub fn used(&self) -> Vec<(u8, OpcodeStats)> {
+        let mut used: Vec<(u8, OpcodeStats)> = (0..=u8::MAX)
+            .map(|opcode| (opcode, self.opcode(opcode)))
+            .filter(|(_, stats)| stats.count > 0)
+            .collect();
+        used.sort_by_key(|(opcode, stats)| (Reverse(stats.count), *opcode));
+        used
+    }
+
+    // The undocumented opcodes that executed, the ones that won't work on a 65C02
+    pub fn illegal(&self) -> Vec<(u8, OpcodeStats)> {
+        self.used()
+            .into_iter()
+            .filter(|(opcode, _)| disasm::is_illegal(*opcode))
+            .collect()
+    }
+
+    // The addressing modes that were used, the most used first
+    pub fn modes(&self) -> Vec<(AddrMode, OpcodeStats)> {
+        let mut modes: HashMap<AddrMode, OpcodeStats> = HashMap::new();
+        for (opcode, stats) in self.used() {
+            modes
+                .entry(OPCODES[opcode as usize].mode)
+                .or_default()
+                .add(&stats);
+        }
+        let mut modes: Vec<(AddrMode, OpcodeStats)> = modes.into_iter().collect();
+        modes.sort_by_key(|(mode, stats)| (Reverse(stats.count), format!("{:?}", mode)));
+        modes
+    }
+
+    pub fn total(&self) -> OpcodeStats {
+        let mut total = OpcodeStats::default();
+        for stats in &self.opcodes {
+            total.add(stats);
+        }
+        total
+    }
+
+    /* A table of the opcodes used, then one of the addressing modes, each with their share of
+    the instructions and of the cycles. Undocumented opcodes are marked with a '*':
+
+        Opcode  Name  Mode       Count       %     Cycles       %  Crossed
+        $BD     LDA   ABSX          64  12.50%        320  14.81%       16
+        $A7    *LAX   ZPG0           8   1.56%         24   1.11%        0
+    */
+    pub fn report(&self) -> String {
+        let total = self.total();
+        let mut out = format!(
+            "Opcode  Name  Mode  {:>10} {:>7} {:>10} {:>7} {:>8}\n",
+            "Count", "%", "Cycles", "%", "Crossed"
+        );
+        for (opcode, stats) in self.used() {
+            let name = OPCODES[opcode as usize].name.to_uppercase();
+            let marker = match disasm::is_illegal(opcode) {
+                true => '*',
+                false => ' ',
+            };
+            let mode = format!("{:?}", OPCODES[opcode as usize].mode);
+            out += &format!(
+                "${:02X}    {}{:<4}  {:<4}  {}\n",
+                opcode,
+                marker,
+                name,
+                mode,
+                row(&stats, &total)
+            );
+        }
+
+        out += &format!(
+            "\nMode  {:>10} {:>7} {:>10} {:>7} {:>8}\n",
+            "Count", "%", "Cycles", "%", "Crossed"
+        );
+        for (mode, stats) in self.modes() {
+            out += &format!("{:<4}  {}\n", format!("{:?}", mode), row(&stats, &total));
+        }
+
+        let illegal = self.illegal();
+        out += &format!(
+            "\n{} of 256 opcodes used, {} of them undocumented",
+            self.used().len(),
+            illegal.len()
+        );
+        match illegal.is_empty() {
+            true => out += "\n",
+            false => {
+                let opcodes: Vec<String> = illegal
+                    .iter()
+                    .map(|(opcode, _)| format!("${:02X}", opcode))
+                    .collect();
+                out += &format!(" ({})\n", opcodes.join(" "));
+            }
+        }
+        out
+    }
+
+    fn instruction(&mut self, opcode: u8, cycles: u64, page_crossed: bool) {
+        let stats = &mut self.opcodes[opcode as usize];
+        stats.count += 1;
+        stats.cycles += cycles;
+        stats.page_crossings += page_crossed as u64;
+    }
+}
+
+impl Default for OpcodeHistogram {
+    fn default() -> Self {
+        OpcodeHistogram::new()
+    }
+}
+
+fn row(stats: &OpcodeStats, total: &OpcodeStats) -> String {
+    let percent = |part: u64, total: u64| match total {
+        0 => 0.0,
+        total => part as f64 * 100.0 / total as f64,
+    };
+    format!(
+        "{:>10} {:>6.2}% {:>10} {:>6.2}% {:>8}",
+        stats.count,
+        percent(stats.count, total.count),
+        stats.cycles,
+        percent(stats.cycles, total.cycles),
+        stats.page_crossings
+    )
+}
+
+impl<'a> Cpu6502<'a> {
+    // Start (or with None stop) counting opcodes
+    pub fn set_opcode_histogram(&mut self, histogram: Option<OpcodeHistogram>) {
+        self.opcode_histogram = histogram;
+    }
+
+    pub fn opcode_histogram(&self) -> Option<&OpcodeHistogram> {
+        self.opcode_histogram.as_ref()
+    }
+
+    pub fn opcode_histogram_mut(&mut self) -> Option<&mut OpcodeHistogram> {
+        self.opcode_histogram.as_mut()
+    }
+
+    // Called by tick() after an instruction
+    pub(crate) fn count_opcode(&mut self, opcode: u8) {
+        if let Some(histogram) = &mut self.opcode_histogram {
+            histogram.instruction(opcode, self.cycles as u64, self.page_crossed);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use super::*;
+use history::CpuState;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/* The CPU driven like the real chip, for co-simulation with an FPGA or hardware in the loop.
+There are no memory callbacks: every half-cycle the inputs (data bus, RDY, IRQ, NMI, RES and SO)
+go in and the outputs (address bus, R/W, SYNC and the data bus on a write) come out.
+
+    let mut cpu = PinCpu::new();
+    let mut inputs = PinInputs::default();
+    loop {
+        // PHI2 low, the address goes out
+        let out = cpu.half_cycle(&inputs);
+        inputs.data = memory[out.address as usize];
+
+        // PHI2 high, a read takes the data bus as it is at the end, a write drives it
+        let out = cpu.half_cycle(&inputs);
+        if let Some(value) = out.data {
+            memory[out.address as usize] = value;
+        }
+    }
+
+A new PinCpu starts with the reset sequence, as if RES was just released. Everything but RES is
+only looked at between instructions: IRQ and NMI by the next instruction (or interrupt), and SO
+sets the V flag in front of it. RDY held low while PHI2 is high makes the read happen again the
+next cycle, with the same address, and like on the NMOS chip doesn't stop a write.
+
+The core runs a whole instruction at a time, so to get at the cycles one by one it runs the
+instruction in progress again from the start every cycle, with what was read so far, until it
+gets to a cycle that hasn't happened yet. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinInputs {
+    pub data: u8,  // D0-D7, taken at the end of PHI2 on a read
+    pub rdy: bool, // true meaning ready
+    pub irq: bool, // true meaning asserted, for these the real pins are active low
+    pub nmi: bool,
+    pub res: bool,
+    pub so: bool,
+}
+
+impl Default for PinInputs {
+    fn default() -> Self {
+        PinInputs {
+            data: 0,
+            rdy: true,
+            irq: false,
+            nmi: false,
+            res: false,
+            so: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinOutputs {
+    pub phi2: bool, // Which half of the cycle these are for
+    pub address: u16,
+    pub data: Option<u8>, // Driven by the CPU while PHI2 is high on a write
+    pub read: bool,       // RWB, high for a read
+    pub sync: bool,       // High while an opcode is fetched
+}
+
+// A bus cycle, with what was read or written
+#[derive(Clone, Copy, Debug)]
+struct Cycle {
+    address: u16,
+    value: u8,
+    read: bool,
+}
+
+// What the memory callbacks of the CPU inside go to
+#[derive(Default)]
+struct Bus {
+    done: Vec<Cycle>,    // The cycles of the instruction in progress so far
+    position: usize,     // How far the run of the instruction got
+    next: Option<Cycle>, // The first cycle the run got to that hasn't happened yet
+}
+
+impl Bus {
+    fn access(&mut self, address: usize, value: u8, read: bool) -> u8 {
+        let value = match self.done.get(self.position) {
+            Some(cycle) => cycle.value,
+            None => {
+                if self.next.is_none() {
+                    self.next = Some(Cycle {
+                        address: address as u16,
+                        value,
+                        read,
+                    });
+                }
+                value
+            }
+        };
+        self.position += 1;
+        value
+    }
+}
+
+pub struct PinCpu {
+    cpu: Cpu6502<'static>,
+    bus: Rc<RefCell<Bus>>,
+    start: CpuState,                // The CPU in front of the instruction in progress
+    resetting: bool,                // The reset sequence is in progress rather than an instruction
+    held: bool,                     // In reset for as long as RES is asserted
+    current: Option<(Cycle, bool)>, // The cycle on the bus and whether SYNC is high for it
+    phi2: bool,                     // The next half-cycle is with PHI2 high
+    cycles: u64,
+    irq: bool,
+    nmi: bool,
+    so: bool,
+    nmi_edge: bool, // NMI and SO were asserted, for the next instruction
+    so_edge: bool,
+}
+
+impl PinCpu {
+    pub fn new() -> Self {
+        let bus = Rc::new(RefCell::new(Bus::default()));
+        let (read, write) = (bus.clone(), bus.clone());
+        let cpu = Cpu6502::new(
+            Box::new(move |address: usize| read.borrow_mut().access(address, 0xFF, true)),
+            Box::new(move |address: usize, value: u8| {
+                write.borrow_mut().access(address, value, false);
+            }),
+        );
+
+        PinCpu {
+            start: cpu.state(),
+            cpu,
+            bus,
+            resetting: true,
+            held: false,
+            current: None,
+            phi2: false,
+            cycles: 0,
+            irq: false,
+            nmi: false,
+            so: false,
+            nmi_edge: false,
+            so_edge: false,
+        }
+    }
+
+    // The next half of the cycle, starting with PHI2 low
+    pub fn half_cycle(&mut self, inputs: &PinInputs) -> PinOutputs {
+        match self.phi2 {
+            false => self.phi2_low(inputs),
+            true => self.phi2_high(inputs),
+        }
+    }
+
+    // As they were in front of the instruction in progress
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.registers
+    }
+
+    // Full cycles since the CPU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cpu.halted
+    }
+
+    fn phi2_low(&mut self, inputs: &PinInputs) -> PinOutputs {
+        self.phi2 = true;
+        self.nmi_edge |= inputs.nmi && !self.nmi;
+        self.so_edge |= inputs.so && !self.so;
+        (self.irq, self.nmi, self.so) = (inputs.irq, inputs.nmi, inputs.so);
+
+        // Whatever was going on is dropped, and the reset sequence starts once RES is released
+        if inputs.res {
+            if !self.held {
+                self.held = true;
+                self.cpu.set_state(self.start);
+            }
+            self.current = None;
+            return self.outputs(false);
+        }
+        if self.held {
+            self.held = false;
+            self.resetting = true;
+            self.bus.borrow_mut().done.clear();
+            self.start = self.cpu.state();
+        }
+
+        if self.current.is_none() {
+            self.current = Some(self.next_cycle());
+        }
+        self.outputs(false)
+    }
+
+    fn phi2_high(&mut self, inputs: &PinInputs) -> PinOutputs {
+        self.phi2 = false;
+        self.cycles += 1;
+        let outputs = self.outputs(true);
+
+        if let Some((cycle, _)) = self.current {
+            match cycle.read {
+                true if !inputs.rdy => return outputs, // Not ready, the read happens again
+                true => self.bus.borrow_mut().done.push(Cycle {
+                    value: inputs.data,
+                    ..cycle
+                }),
+                false => self.bus.borrow_mut().done.push(cycle),
+            }
+            self.current = None;
+        }
+        outputs
+    }
+
+    fn outputs(&self, phi2: bool) -> PinOutputs {
+        match self.current {
+            Some((cycle, sync)) => PinOutputs {
+                phi2,
+                address: cycle.address,
+                data: match phi2 && !cycle.read {
+                    true => Some(cycle.value),
+                    false => None,
+                },
+                read: cycle.read,
+                sync,
+            },
+
+            // Held in reset, just reading
+            None => PinOutputs {
+                phi2,
+                address: self.cpu.registers.pc,
+                data: None,
+                read: true,
+                sync: false,
+            },
+        }
+    }
+
+    // The cycle the instruction in progress is at, moving on to the next one if it's done
+    fn next_cycle(&mut self) -> (Cycle, bool) {
+        loop {
+            if let Some(cycle) = self.run() {
+                let sync = !self.resetting && self.bus.borrow().done.is_empty();
+                return (cycle, sync);
+            }
+            self.next_instruction();
+
+            // A jam leaves the bus stuck until a reset
+            if self.cpu.halted {
+                let cycle = Cycle {
+                    address: 0xFFFF,
+                    value: 0xFF,
+                    read: true,
+                };
+                return (cycle, false);
+            }
+        }
+    }
+
+    // Run the instruction in progress from the start, None meaning it's finished
+    fn run(&mut self) -> Option<Cycle> {
+        self.cpu.set_state(self.start);
+        {
+            let mut bus = self.bus.borrow_mut();
+            bus.position = 0;
+            bus.next = None;
+        }
+
+        match self.resetting {
+            true => self.reset_sequence(),
+            false => {
+                self.cpu.tick();
+            }
+        }
+
+        let next = self.bus.borrow_mut().next.take();
+        if next.is_some() {
+            self.cpu.set_state(self.start);
+        }
+        next
+    }
+
+    // Take in the inputs that are only looked at between instructions
+    fn next_instruction(&mut self) {
+        self.bus.borrow_mut().done.clear();
+        self.resetting = false;
+
+        self.cpu.irq = self.irq;
+        self.cpu.nmi = self.nmi;
+        self.cpu.nmi_pending |= self.nmi_edge;
+        if self.so_edge {
+            self.cpu.registers.p |= StatusFlags::V;
+        }
+        (self.nmi_edge, self.so_edge) = (false, false);
+
+        self.start = self.cpu.state();
+    }
+
+    /* Unlike Cpu6502::reset() the cycles in front of the vector fetch are on the bus: two reads
+    of the PC and three of the stack, which is how the stack pointer ends up 3 lower */
+    fn reset_sequence(&mut self) {
+        let (pc, s) = (self.cpu.registers.pc as usize, self.cpu.registers.s);
+        self.cpu.read(pc);
+        self.cpu.read(pc);
+        for i in 0..3 {
+            self.cpu.read(STACK_OFFSET + s.wrapping_sub(i) as usize);
+        }
+        self.cpu.registers.s = s.wrapping_sub(3);
+
+        let lsb = self.cpu.read(RESET_VECTOR) as u16;
+        let msb = self.cpu.read(RESET_VECTOR + 1) as u16;
+        self.cpu.registers.pc = msb << 8 | lsb;
+        self.cpu.registers.p = StatusFlags::E | StatusFlags::I;
+        self.cpu.halted = false;
+        self.cpu.nmi_pending = false;
+    }
+}
+
+impl Default for PinCpu {
+    fn default() -> Self {
+        PinCpu::new()
+    }
+}
@@ -0,0 +1,450 @@
+use super::*;
+use callstack::{CallStack, Frame, FrameKind};
+use disasm::Instruction;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use symbols::SymbolTable;
+
+/* An execution profiler. While it's attached every instruction adds to the counters of the
+address it ran from: how many times it executed and how many cycles that took. The cycles are
+whatever the instruction spent on the bus, so page crossings and taken branches are included.
+
+Cycles are also added up per subroutine with a call stack of its own (see the callstack module),
+both the cycles spent in the subroutine itself and the total including whatever it called. The
+JSR is counted in the caller and the RTS in the subroutine. Interrupt handlers count as
+subroutines, entered at the address from the vector, with the 7 cycles taken to get there kept
+apart since there's no instruction to put them on.
+
+The cycles are also kept per call stack, which folded_stacks() turns into the input flamegraph
+tools take. With a timeline (see with_timeline()) every call becomes a span from the JSR up to
+and including the RTS, and chrome_trace() exports those for timeline viewers like Perfetto or
+chrome://tracing, one cycle showing as one microsecond (about right for a 1 MHz 6502).
+
+    cpu.set_profiler(Some(Profiler::new()));
+    ...
+    print!("{}", cpu.profiler().unwrap().listing(|a| ram.read(a), None)); */
+
+// Root of every folded stack, where the cycles outside of any subroutine go
+const TOP_LEVEL: &str = "[top]";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub address: u16, // Where it was called (or the interrupt vector pointed)
+    pub calls: u64,
+    pub self_cycles: u64,
+    pub total_cycles: u64, // Including the subroutines it called
+}
+
+// A single call, from the cycle the JSR (or interrupt) started to the one the return finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub kind: FrameKind,
+    pub address: u16,
+    pub caller: u16,
+    pub depth: usize, // Number of calls it's nested in
+    pub start: u64,
+    pub end: u64,
+}
+
+struct Timeline {
+    spans: Vec<Span>,
+    max_spans: usize,
+    dropped: u64,
+}
+
+pub struct Profiler {
+    addresses: Vec<AddressStats>,
+    subroutines: HashMap<u16, SubroutineStats>,
+    call_stack: CallStack,
+    outside_cycles: u64,
+    interrupt_cycles: u64,
+
+    // Frames as of the last instruction, with the addresses they called as the key into stacks
+    frames: Vec<Frame>,
+    stack: Vec<u16>,
+    stacks: HashMap<Vec<u16>, u64>,
+
+    timeline: Option<Timeline>,
+    cycle: u64, // Cycle count after the last thing profiled
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            addresses: vec![AddressStats::default(); memory::MEM_SIZE],
+            subroutines: HashMap::new(),
+            call_stack: CallStack::new(),
+            outside_cycles: 0,
+            interrupt_cycles: 0,
+            frames: Vec::new(),
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            timeline: None,
+            cycle: 0,
+        }
+    }
+
+    /* Also keep a span for every call, up to 'max_spans' of them. Calls still going when the
+    timeline is exported end at the last cycle profiled. */
+    pub fn with_timeline(mut self, max_spans: usize) -> Self {
+        self.timeline = Some(Timeline {
+            spans: Vec::new(),
+            max_spans,
+            dropped: 0,
+        });
+        self
+    }
+
+    pub fn clear(&mut self) {
+        let timeline = self.timeline.take().map(|t| Timeline {
+            spans: Vec::new(),
+            max_spans: t.max_spans,
+            dropped: 0,
+        });
+        *self = Profiler {
+            timeline,
+            ..Profiler::new()
+        };
+    }
+
+    pub fn address(&self, address: u16) -> AddressStats {
+        self.addresses[address as usize]
+    }
+
+    // Every address that executed, the most cycles first
+    pub fn hottest(&self) -> Vec<(u16, AddressStats)> {
+        let mut hot: Vec<(u16, AddressStats)> = (0..=u16::MAX)
+            .map(|address| (address, self.address(address)))
+            .filter(|(_, stats)| stats.count > 0)
+            .collect();
+        hot.sort_by_key(|(_, stats)| (Reverse(stats.cycles), Reverse(stats.count)));
+        hot
+    }
+
+    pub fn subroutine(&self, address: u16) -> Option<SubroutineStats> {
+        self.subroutines.get(&address).copied()
+    }
+
+    // Every subroutine that was called, the most cycles in total first
+    pub fn subroutines(&self) -> Vec<SubroutineStats> {
+        let mut subroutines: Vec<SubroutineStats> = self.subroutines.values().copied().collect();
+        subroutines.sort_by_key(|s| (Reverse(s.total_cycles), Reverse(s.self_cycles), s.address));
+        subroutines
+    }
+
+    // Cycles spent by instructions that weren't in any subroutine that's known about
+    pub fn outside_cycles(&self) -> u64 {
+        self.outside_cycles
+    }
+
+    // Cycles spent on the interrupt sequence itself, not in the handlers
+    pub fn interrupt_cycles(&self) -> u64 {
+        self.interrupt_cycles
+    }
+
+    // Everything that was profiled
+    pub fn total_cycles(&self) -> u64 {
+        self.addresses.iter().map(|stats| stats.cycles).sum::<u64>() + self.interrupt_cycles
+    }
+
+    // Every call in the timeline in the order they were made, empty without a timeline
+    pub fn spans(&self) -> Vec<Span> {
+        let Some(timeline) = &self.timeline else {
+            return Vec::new();
+        };
+        let mut spans = timeline.spans.clone();
+        for (depth, frame) in self.frames.iter().enumerate() {
+            spans.push(span(frame, depth, self.cycle));
+        }
+        spans.sort_by_key(|span| (span.start, span.depth));
+        spans
+    }
+
+    // Calls left out of the timeline because it was full
+    pub fn dropped_spans(&self) -> u64 {
+        self.timeline.as_ref().map_or(0, |t| t.dropped)
+    }
+
+    /* The cycles spent in each call stack, one line per stack in the folded format flamegraph
+    tools read. Stacks start at the top level, with subroutines named by 'symbols':
+
+        [top];main_loop;draw;plot 4096
+    */
+    pub fn folded_stacks(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let mut names = vec![TOP_LEVEL.to_string()];
+                names.extend(stack.iter().map(|a| name(symbols, *a)));
+                format!("{} {}\n", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    /* The timeline as Chrome trace event JSON, each call a complete ("X") event with the
+    subroutine's name, timestamps in cycles. */
+    pub fn chrome_trace(&self, symbols: Option<&SymbolTable>) -> String {
+        let events: Vec<_> = self
+            .spans()
+            .iter()
+            .map(|span| {
+                json!({
+                    "name": name(symbols, span.address),
+                    "cat": match span.kind {
+                        FrameKind::Call => "call",
+                        FrameKind::Break => "brk",
+                        FrameKind::Interrupt { .. } => "interrupt",
+                    },
+                    "ph": "X",
+                    "ts": span.start,
+                    "dur": span.end - span.start,
+                    "pid": 1,
+                    "tid": 1,
+                    "args": {
+                        "address": format!("${:04X}", span.address),
+                        "caller": format!("${:04X}", span.caller),
+                    },
+                })
+            })
+            .collect();
+        json!({"traceEvents": events, "displayTimeUnit": "ns"}).to_string()
+    }
+
+    /* The instructions that executed as a disassembly listing, the hottest first, with their
+    share of the total cycles:
+
+          Cycles       %      Count  Address
+            4096  62.14%       1024  $0402  main+2            E8        INX
+    */
+    pub fn listing(&self, peek: impl Fn(u16) -> u8, symbols: Option<&SymbolTable>) -> String {
+        let total = self.total_cycles();
+        let mut out = format!("{:>10} {:>7} {:>10}  Address\n", "Cycles", "%", "Count");
+        for (address, stats) in self.hottest() {
+            let instr = Instruction::fetch(address, &peek);
+            let bytes: Vec<String> = instr.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+            let line = format!(
+                "{:>10} {:>6.2}% {:>10}  ${:04X}  {:<16}  {:<9} {}",
+                stats.cycles,
+                percent(stats.cycles, total),
+                stats.count,
+                address,
+                label(symbols, address),
+                bytes.join(" "),
+                disassemble(&instr, symbols)
+            );
+            out += line.trim_end();
+            out += "\n";
+        }
+        out
+    }
+
+    /* The subroutines, the most cycles in total first:
+
+           Total       %       Self       %    Calls  Subroutine
+            6144  93.20%       2048  31.07%        1  $0420  draw
+    */
+    pub fn subroutine_report(&self, symbols: Option<&SymbolTable>) -> String {
+        let total = self.total_cycles();
+        let mut out = format!(
+            "{:>10} {:>7} {:>10} {:>7} {:>8}  Subroutine\n",
+            "Total", "%", "Self", "%", "Calls"
+        );
+        for s in self.subroutines() {
+            let line = format!(
+                "{:>10} {:>6.2}% {:>10} {:>6.2}% {:>8}  ${:04X}  {}",
+                s.total_cycles,
+                percent(s.total_cycles, total),
+                s.self_cycles,
+                percent(s.self_cycles, total),
+                s.calls,
+                s.address,
+                label(symbols, s.address)
+            );
+            out += line.trim_end();
+            out += "\n";
+        }
+        out
+    }
+
+    fn instruction(&mut self, opcode: u8, pc: u16, s: u8, r: &Registers, cycles: u64, cycle: u64) {
+        let stats = &mut self.addresses[pc as usize];
+        stats.count += 1;
+        stats.cycles += cycles;
+        self.attribute(cycles);
+        match self.stacks.get_mut(&self.stack) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles);
+            }
+        }
+
+        self.call_stack.instruction(opcode, pc, s, r, cycle);
+        if opcode == 0x20 || opcode == 0x00 {
+            self.called();
+        }
+        self.cycle = cycle + cycles;
+        self.sync();
+    }
+
+    fn interrupt(&mut self, pc: u16, s: u8, vector: u16, target: u16, cycles: u64, cycle: u64) {
+        self.interrupt_cycles += cycles;
+        self.call_stack.interrupt(pc, s, vector, target, cycle);
+        self.called();
+        self.cycle = cycle + cycles;
+        self.sync();
+    }
+
+    // Catch up with the call stack, ending the spans of calls that are gone
+    fn sync(&mut self) {
+        let frames = self.call_stack.frames();
+        let kept = self
+            .frames
+            .iter()
+            .zip(frames)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if kept == self.frames.len() && kept == frames.len() {
+            return;
+        }
+
+        while self.frames.len() > kept {
+            let frame = self.frames.pop().unwrap();
+            if let Some(timeline) = &mut self.timeline {
+                match timeline.spans.len() < timeline.max_spans {
+                    true => timeline
+                        .spans
+                        .push(span(&frame, self.frames.len(), self.cycle)),
+                    false => timeline.dropped += 1,
+                }
+            }
+        }
+        self.frames.extend_from_slice(&frames[kept..]);
+        self.stack = self.frames.iter().map(|f| f.target).collect();
+    }
+
+    // The innermost frame was just pushed
+    fn called(&mut self) {
+        let target = self.call_stack.frames().last().unwrap().target;
+        let stats = self.subroutines.entry(target).or_default();
+        stats.address = target;
+        stats.calls += 1;
+    }
+
+    // Cycles go to the innermost subroutine, and to each one further out once (it may recurse)
+    fn attribute(&mut self, cycles: u64) {
+        let frames = self.call_stack.frames();
+        let Some(innermost) = frames.last() else {
+            self.outside_cycles += cycles;
+            return;
+        };
+
+        for (i, frame) in frames.iter().enumerate() {
+            if frames[..i].iter().any(|f| f.target == frame.target) {
+                continue;
+            }
+            let stats = self.subroutines.entry(frame.target).or_default();
+            stats.address = frame.target;
+            stats.total_cycles += cycles;
+            if frame.target == innermost.target {
+                stats.self_cycles += cycles;
+            }
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+fn span(frame: &Frame, depth: usize, end: u64) -> Span {
+    Span {
+        kind: frame.kind,
+        address: frame.target,
+        caller: frame.caller,
+        depth,
+        start: frame.cycle,
+        end,
+    }
+}
+
+// A subroutine's label, or its address when it has none
+fn name(symbols: Option<&SymbolTable>, address: u16) -> String {
+    match symbols.and_then(|s| s.name(address)) {
+        Some(name) => name.to_string(),
+        None => format!("${:04X}", address),
+    }
+}
+
+fn percent(cycles: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => cycles as f64 * 100.0 / total as f64,
+    }
+}
+
+fn label(symbols: Option<&SymbolTable>, address: u16) -> String {
+    symbols
+        .and_then(|s| s.describe(address))
+        .unwrap_or_default()
+}
+
+fn disassemble(instr: &Instruction, symbols: Option<&SymbolTable>) -> String {
+    let operand =
+        instr.operand_with(
+            |address, zero_page| match symbols.and_then(|s| s.name(address)) {
+                Some(name) => name.to_string(),
+                None => disasm::hex_address(address, zero_page),
+            },
+        );
+    match operand.is_empty() {
+        true => instr.mnemonic().to_uppercase(),
+        false => format!("{} {}", instr.mnemonic().to_uppercase(), operand),
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    // Attach a profiler (or with None detach it), counting from then on
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    // Called by tick() after an instruction, with the PC and stack pointer from before it ran
+    pub(crate) fn profile_instruction(&mut self, opcode: u8, pc: u16, s: u8, cycle: u64) {
+        if let Some(profiler) = &mut self.profiler {
+            let cycles = self.cycles as u64;
+            profiler.instruction(opcode, pc, s, &self.registers, cycles, cycle);
+        }
+    }
+
+    // Called after the interrupt sequence
+    pub(crate) fn profile_interrupt(&mut self, pc: u16, s: u8, vector: u16, cycle: u64) {
+        if let Some(profiler) = &mut self.profiler {
+            let cycles = self.cycles as u64;
+            profiler.interrupt(pc, s, vector, self.registers.pc, cycles, cycle);
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use super::*;
+use memory::{Ram, MEM_SIZE};
+use std::fmt;
+
+/* Deterministic record and replay. While recording, the CPU keeps every value it read from
+I/O (the address ranges it's told are devices rather than memory), every change of the IRQ,
+NMI and RDY lines and, every so often, the PC as a checkpoint, all stamped with the cycle count.
+Along with the registers and memory at the start that's enough to run the same thing again
+without any of the devices:
+
+    cpu.start_recording(&[(0xD000, 0xDFFF)], 1000)?;
+    ... run with the real devices ...
+    fs::write("bug.rec", cpu.stop_recording().unwrap().to_bytes())?;
+
+    let recording = Recording::from_bytes(&fs::read("bug.rec")?)?;
+    let (ram, mut cpu) = recording.replay();
+    while !cpu.replay_finished() { cpu.tick(); }
+
+Replay checks it's still doing the same as the recording at every checkpoint and I/O read, see
+replay_desync(). Recording needs the peek callback to take the starting memory.
+
+The file starts with "R6502" and a version byte. Cycle stamps are stored as the difference from
+the previous one in a variable length encoding and memory is run length encoded, so most
+recordings end up small. */
+
+const MAGIC: &[u8] = b"R6502";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Line {
+    Irq,
+    Nmi,
+    Rdy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineChange {
+    pub cycle: u64,
+    pub line: Line,
+    pub level: bool, // true for asserted (IRQ, NMI) or ready (RDY)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+    // Inclusive address ranges whose reads come from the recording instead of memory
+    pub io: Vec<(u16, u16)>,
+    pub checkpoint_interval: u32,
+
+    pub registers: Registers,
+    pub start_cycle: u64,
+    pub end_cycle: u64,
+    pub lines: [bool; 3], // IRQ, NMI and RDY at the start
+    pub memory: Vec<u8>,
+
+    pub reads: Vec<(u64, u8)>,
+    pub line_changes: Vec<LineChange>,
+    pub checkpoints: Vec<(u64, u16)>, // Cycle and PC in front of every so many instructions
+}
+
+// How a replay went out of step with its recording
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Desync {
+    Checkpoint {
+        cycle: u64,
+        expected_pc: u16,
+        pc: u16,
+    },
+    Read {
+        cycle: u64,
+        expected_cycle: u64,
+        address: u16,
+    },
+    OutOfReads {
+        cycle: u64,
+        address: u16,
+    },
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Desync::Checkpoint {
+                cycle,
+                expected_pc,
+                pc,
+            } => write!(
+                f,
+                "PC ${:04X} at cycle {} where the recording had ${:04X}",
+                pc, cycle, expected_pc
+            ),
+            Desync::Read {
+                cycle,
+                expected_cycle,
+                address,
+            } => write!(
+                f,
+                "Read ${:04X} at cycle {} where the recording read at cycle {}",
+                address, cycle, expected_cycle
+            ),
+            Desync::OutOfReads { cycle, address } => write!(
+                f,
+                "Read ${:04X} at cycle {} after the recorded reads ran out",
+                address, cycle
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordingError {
+    pub message: String,
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn error<T>(message: impl Into<String>) -> Result<T, RecordingError> {
+    Err(RecordingError {
+        message: message.into(),
+    })
+}
+
+pub struct Recorder {
+    recording: Recording,
+    count: u32, // Instructions until the next checkpoint
+}
+
+pub struct Replayer {
+    recording: Recording,
+    count: u32,
+    next_read: usize,
+    next_line: usize,
+    next_checkpoint: usize,
+    desync: Option<Desync>,
+}
+
+impl Recording {
+    /* A CPU set up the way the recording started, with plain RAM for memory. The CPU has a peek
+    callback, so the debugger and the like work on it too. */
+    pub fn replay<'a>(self) -> (Ram, Cpu6502<'a>) {
+        let ram = Ram::new();
+        ram.load(0, &self.memory);
+        let mut cpu = ram.cpu();
+        cpu.start_replay(self);
+        (ram, cpu)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        let r = &self.registers;
+        out.extend(r.pc.to_le_bytes());
+        out.extend([r.s, r.a, r.x, r.y, r.p.bits()]);
+        let lines = self
+            .lines
+            .iter()
+            .rev()
+            .fold(0, |bits, &l| bits << 1 | l as u8);
+        out.push(lines);
+        put_varint(&mut out, self.start_cycle);
+        put_varint(&mut out, self.end_cycle - self.start_cycle);
+        put_varint(&mut out, self.checkpoint_interval as u64);
+
+        put_varint(&mut out, self.io.len() as u64);
+        for (start, end) in &self.io {
+            out.extend(start.to_le_bytes());
+            out.extend(end.to_le_bytes());
+        }
+
+        // Runs of the same byte
+        let mut i = 0;
+        while i < self.memory.len() {
+            let run = self.memory[i..]
+                .iter()
+                .take_while(|&&b| b == self.memory[i])
+                .count();
+            put_varint(&mut out, run as u64);
+            out.push(self.memory[i]);
+            i += run;
+        }
+
+        // Each list of cycle stamps counts up from the start
+        let start = self.start_cycle;
+        put_varint(&mut out, self.reads.len() as u64);
+        let mut cycle = start;
+        for &(at, value) in &self.reads {
+            put_varint(&mut out, at - std::mem::replace(&mut cycle, at));
+            out.push(value);
+        }
+
+        put_varint(&mut out, self.line_changes.len() as u64);
+        let mut cycle = start;
+        for change in &self.line_changes {
+            put_varint(
+                &mut out,
+                change.cycle - std::mem::replace(&mut cycle, change.cycle),
+            );
+            out.push(change.line as u8 | (change.level as u8) << 7);
+        }
+
+        put_varint(&mut out, self.checkpoints.len() as u64);
+        let mut cycle = start;
+        for &(at, pc) in &self.checkpoints {
+            put_varint(&mut out, at - std::mem::replace(&mut cycle, at));
+            out.extend(pc.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RecordingError> {
+        let Some(bytes) = bytes.strip_prefix(MAGIC) else {
+            return error("Not a recording");
+        };
+        let mut input = Input { bytes, pos: 0 };
+        match input.byte()? {
+            VERSION => {}
+            version => return error(format!("Unsupported recording version {}", version)),
+        }
+
+        let pc = input.u16()?;
+        let [s, a, x, y, p] = [0; 5].map(|_| input.byte());
+        let registers = Registers {
+            pc,
+            s: s?,
+            a: a?,
+            x: x?,
+            y: y?,
+            p: StatusFlags::from_bits_retain(p?),
+        };
+        let lines = input.byte()?;
+        let start_cycle = input.varint()?;
+        let mut end_cycle = start_cycle;
+        input.stamp(&mut end_cycle)?;
+        let checkpoint_interval = match u32::try_from(input.varint()?) {
+            Ok(interval) if interval > 0 => interval,
+            _ => return error("Bad checkpoint interval"),
+        };
+
+        let io = (0..input.count()?)
+            .map(|_| Ok((input.u16()?, input.u16()?)))
+            .collect::<Result<_, _>>()?;
+
+        let mut memory = Vec::with_capacity(MEM_SIZE);
+        while memory.len() < MEM_SIZE {
+            let run = input.varint()? as usize;
+            if run == 0 || memory.len() + run > MEM_SIZE {
+                return error("Bad memory image");
+            }
+            memory.resize(memory.len() + run, input.byte()?);
+        }
+
+        let mut cycle = start_cycle;
+        let reads = (0..input.count()?)
+            .map(|_| Ok((input.stamp(&mut cycle)?, input.byte()?)))
+            .collect::<Result<_, _>>()?;
+
+        let mut cycle = start_cycle;
+        let line_changes = (0..input.count()?)
+            .map(|_| {
+                let cycle = input.stamp(&mut cycle)?;
+                let byte = input.byte()?;
+                let line = match byte & 0x7F {
+                    0 => Line::Irq,
+                    1 => Line::Nmi,
+                    2 => Line::Rdy,
+                    other => return error(format!("Unknown line {}", other)),
+                };
+                Ok(LineChange {
+                    cycle,
+                    line,
+                    level: byte & 0x80 != 0,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut cycle = start_cycle;
+        let checkpoints = (0..input.count()?)
+            .map(|_| Ok((input.stamp(&mut cycle)?, input.u16()?)))
+            .collect::<Result<_, _>>()?;
+
+        if input.pos != input.bytes.len() {
+            return error("Trailing data after the recording");
+        }
+
+        Ok(Recording {
+            io,
+            checkpoint_interval,
+            registers,
+            start_cycle,
+            end_cycle,
+            lines: [lines & 1 != 0, lines & 2 != 0, lines & 4 != 0],
+            memory,
+            reads,
+            line_changes,
+            checkpoints,
+        })
+    }
+
+    fn is_io(&self, address: usize) -> bool {
+        self.io
+            .iter()
+            .any(|&(start, end)| (start as usize..=end as usize).contains(&address))
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Input<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl Input<'_> {
+    fn byte(&mut self) -> Result<u8, RecordingError> {
+        let Some(&byte) = self.bytes.get(self.pos) else {
+            return error("Recording is cut short");
+        };
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, RecordingError> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn varint(&mut self) -> Result<u64, RecordingError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        error("Bad number")
+    }
+
+    // The next cycle stamp, stored as the difference from the previous one
+    fn stamp(&mut self, cycle: &mut u64) -> Result<u64, RecordingError> {
+        *cycle = match cycle.checked_add(self.varint()?) {
+            Some(next) => next,
+            None => return error("Bad cycle count"),
+        };
+        Ok(*cycle)
+    }
+
+    // A number of items, which can't be more than there are bytes left
+    fn count(&mut self) -> Result<usize, RecordingError> {
+        let count = self.varint()?;
+        match count <= (self.bytes.len() - self.pos) as u64 {
+            true => Ok(count as usize),
+            false => error("Recording is cut short"),
+        }
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    /* Start recording, reads from the 'io' address ranges (inclusive) being kept. A checkpoint
+    is taken every 'checkpoint_interval' instructions. Without the peek callback there's no
+    way to take the starting memory, so that's an error. */
+    pub fn start_recording(
+        &mut self,
+        io: &[(u16, u16)],
+        checkpoint_interval: u32,
+    ) -> Result<(), RecordingError> {
+        if self.mem_peek.is_none() {
+            return error("Recording needs the peek callback");
+        }
+
+        let recording = Recording {
+            io: io.to_vec(),
+            checkpoint_interval: checkpoint_interval.max(1),
+            registers: self.registers,
+            start_cycle: self.total_cycles,
+            end_cycle: self.total_cycles,
+            lines: [self.irq, self.nmi, self.rdy],
+            memory: (0..MEM_SIZE).map(|a| self.peek(a).unwrap_or(0)).collect(),
+            reads: Vec::new(),
+            line_changes: Vec::new(),
+            checkpoints: Vec::new(),
+        };
+        self.recorder = Some(Recorder {
+            recording,
+            count: 0,
+        });
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let mut recording = self.recorder.take()?.recording;
+        recording.end_cycle = self.total_cycles;
+        Some(recording)
+    }
+
+    // Run from the recording from now on, see Recording::replay() for a CPU that's all set up
+    pub fn start_replay(&mut self, mut recording: Recording) {
+        recording.checkpoint_interval = recording.checkpoint_interval.max(1);
+        self.registers = recording.registers;
+        self.total_cycles = recording.start_cycle;
+        [self.irq, self.nmi, self.rdy] = recording.lines;
+        self.nmi_pending = false;
+        self.halted = false;
+        self.replayer = Some(Replayer {
+            recording,
+            count: 0,
+            next_read: 0,
+            next_line: 0,
+            next_checkpoint: 0,
+            desync: None,
+        });
+    }
+
+    pub fn stop_replay(&mut self) -> Option<Recording> {
+        self.replayer.take().map(|r| r.recording)
+    }
+
+    // Whether the replay got as far as the recording went
+    pub fn replay_finished(&self) -> bool {
+        match &self.replayer {
+            Some(replayer) => self.total_cycles >= replayer.recording.end_cycle,
+            None => true,
+        }
+    }
+
+    // The first place the replay stopped matching the recording
+    pub fn replay_desync(&self) -> Option<Desync> {
+        self.replayer.as_ref().and_then(|r| r.desync)
+    }
+
+    pub(crate) fn record_line(&mut self, line: Line, level: bool) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.recording.line_changes.push(LineChange {
+                cycle: self.total_cycles,
+                line,
+                level,
+            });
+        }
+    }
+
+    pub(crate) fn record_read(&mut self, address: usize, value: u8) {
+        if let Some(recorder) = &mut self.recorder {
+            if recorder.recording.is_io(address) {
+                recorder.recording.reads.push((self.total_cycles, value));
+            }
+        }
+    }
+
+    // Every so many instructions, take a checkpoint or check the one taken while recording
+    pub(crate) fn checkpoint(&mut self) {
+        let (cycle, pc) = (self.total_cycles, self.registers.pc);
+        if let Some(recorder) = &mut self.recorder {
+            if recorder.count == 0 {
+                recorder.recording.checkpoints.push((cycle, pc));
+                recorder.count = recorder.recording.checkpoint_interval;
+            }
+            recorder.count -= 1;
+        }
+
+        let Some(replayer) = &mut self.replayer else {
+            return;
+        };
+        if replayer.count == 0 {
+            let expected = replayer.recording.checkpoints.get(replayer.next_checkpoint);
+            if let Some(&(expected_cycle, expected_pc)) = expected {
+                replayer.next_checkpoint += 1;
+                if (expected_cycle, expected_pc) != (cycle, pc) && replayer.desync.is_none() {
+                    replayer.desync = Some(Desync::Checkpoint {
+                        cycle,
+                        expected_pc,
+                        pc,
+                    });
+                }
+            }
+            replayer.count = replayer.recording.checkpoint_interval;
+        }
+        replayer.count -= 1;
+    }
+
+    // The recorded value for a read from I/O, None for memory
+    pub(crate) fn replay_read(&mut self, address: usize) -> Option<u8> {
+        let replayer = self.replayer.as_mut()?;
+        if !replayer.recording.is_io(address) {
+            return None;
+        }
+
+        let cycle = self.total_cycles;
+        let Some(&(expected_cycle, value)) = replayer.recording.reads.get(replayer.next_read)
+        else {
+            replayer.desync.get_or_insert(Desync::OutOfReads {
+                cycle,
+                address: address as u16,
+            });
+            return Some(0xFF);
+        };
+        replayer.next_read += 1;
+        if expected_cycle != cycle {
+            replayer.desync.get_or_insert(Desync::Read {
+                cycle,
+                expected_cycle,
+                address: address as u16,
+            });
+        }
+        Some(value)
+    }
+
+    // Change the interrupt and RDY lines the way they were changed at this point in the recording
+    pub(crate) fn replay_lines(&mut self) {
+        let Some(replayer) = &mut self.replayer else {
+            return;
+        };
+        let changes = &replayer.recording.line_changes;
+        while let Some(&change) = changes.get(replayer.next_line) {
+            if change.cycle > self.total_cycles {
+                break;
+            }
+            replayer.next_line += 1;
+            match change.line {
+                Line::Irq => self.irq = change.level,
+                Line::Nmi => {
+                    if change.level && !self.nmi {
+                        self.nmi_pending = true;
+                    }
+                    self.nmi = change.level;
+                }
+                Line::Rdy => self.rdy = change.level,
+            }
+        }
+    }
+}
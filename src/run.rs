@@ -0,0 +1,161 @@
+use super::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/* Helpers for running programs headlessly. Most test programs signal that they are done by
+jumping (or branching) to themselves, so rather than polling the PC around tick() a run stops
+as soon as such a trap is hit:
+
+    let result = cpu.run(&RunOptions::default());
+    println!("Trapped at ${:04X} after {} cycles", result.pc, result.cycles);
+
+A run on a worker thread can be stopped from another one with a StopHandle, which is checked in
+front of every instruction:
+
+    let stop = StopHandle::new();
+    let options = RunOptions {
+        stop: Some(stop.clone()),
+        ..Default::default()
+    };
+    let worker = thread::spawn(move || {
+        let mut cpu = ...; // The CPU and its memory live on the worker thread
+        cpu.run(&options)
+    });
+    stop.stop();
+    assert_eq!(worker.join().unwrap().reason, StopReason::Stopped);
+*/
+
+// Instructions run between looking at the clock for a timeout
+const TIME_CHECK: u64 = 1000;
+
+// Clones share the same flag, so any of them (on any thread) can stop the run
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn new() -> Self {
+        StopHandle::default()
+    }
+
+    // Ask the run to stop in front of the next instruction
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Runs stop right away until it's reset
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct RunOptions {
+    // Stop after this many cycles (counted from the start of the run)
+    pub max_cycles: Option<u64>,
+
+    /* Also detect loops of up to this many instructions that come back to the exact same
+    registers without writing memory in between. Polling loops waiting on I/O look the same,
+    so this is off (0) by default. */
+    pub loop_window: usize,
+
+    // Stop when asked to through the handle
+    pub stop: Option<StopHandle>,
+
+    // Stop once this much time has gone by (checked every thousand instructions)
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Trap,        // Jumped or branched to itself
+    Loop(usize), // Looped over this many instructions without changing anything
+    Halted,      // Executed a JAM opcode
+    CycleLimit,  // Ran out of cycles
+    Timeout,     // Ran out of time
+    Stopped,     // Asked to stop through a StopHandle
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunResult {
+    pub reason: StopReason,
+
+    // Address of the trap (or where the CPU was when it stopped for any other reason)
+    pub pc: u16,
+
+    // Cycles and instructions executed during this run
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+impl<'a> Cpu6502<'a> {
+    pub fn run(&mut self, options: &RunOptions) -> RunResult {
+        self.run_with(options, |_| {})
+    }
+
+    /* Same as run(), but calls 'before_instr' ahead of every instruction. That's the place to
+    drive interrupt lines or anything else that lives outside of memory. */
+    pub fn run_with(
+        &mut self,
+        options: &RunOptions,
+        mut before_instr: impl FnMut(&mut Cpu6502<'a>),
+    ) -> RunResult {
+        let start_cycles = self.total_cycles;
+        let start_time = Instant::now();
+        let mut instructions = 0;
+
+        // Registers after each of the last few instructions, along with the write count
+        let mut history: VecDeque<(Registers, u64)> = VecDeque::new();
+
+        let reason = loop {
+            if options.stop.as_ref().is_some_and(|stop| stop.is_stopped()) {
+                break StopReason::Stopped;
+            }
+            before_instr(self);
+
+            let pc = self.registers.pc;
+            self.tick();
+            instructions += 1;
+
+            if self.halted {
+                break StopReason::Halted;
+            }
+            if self.registers.pc == pc && self.last_event != TickEvent::Stalled {
+                break StopReason::Trap;
+            }
+            if options.loop_window != 0 {
+                let state = (self.registers, self.write_count);
+                if let Some(i) = history.iter().rposition(|s| *s == state) {
+                    break StopReason::Loop(history.len() - i);
+                }
+
+                history.push_back(state);
+                if history.len() > options.loop_window {
+                    history.pop_front();
+                }
+            }
+            if let Some(max) = options.max_cycles {
+                if self.total_cycles - start_cycles >= max {
+                    break StopReason::CycleLimit;
+                }
+            }
+            if let Some(timeout) = options.timeout {
+                if instructions % TIME_CHECK == 0 && start_time.elapsed() >= timeout {
+                    break StopReason::Timeout;
+                }
+            }
+        };
+
+        RunResult {
+            reason,
+            pc: self.registers.pc,
+            cycles: self.total_cycles - start_cycles,
+            instructions,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+
+/* Labels for addresses, used to make backtraces, disassembly and the like readable. An address
+can have several labels, the first one added is the one shown. */
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, Vec<String>>,
+    addresses: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn insert(&mut self, name: &str, address: u16) {
+        if let Some(old) = self.addresses.insert(name.to_string(), address) {
+            if let Some(names) = self.names.get_mut(&old) {
+                names.retain(|n| n != name);
+            }
+        }
+        self.names
+            .entry(address)
+            .or_default()
+            .push(name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    // The label for exactly this address
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names
+            .get(&address)
+            .and_then(|names| names.first())
+            .map(String::as_str)
+    }
+
+    // The closest label at or below the address, along with the offset from it
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.names
+            .range(..=address)
+            .rev()
+            .find_map(|(addr, names)| names.first().map(|n| (n.as_str(), address - addr)))
+    }
+
+    // "label" or "label+offset", None if there's no label at or below the address
+    pub fn describe(&self, address: u16) -> Option<String> {
+        self.nearest(address).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            _ => format!("{}+{}", name, offset),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.names
+            .iter()
+            .flat_map(|(addr, names)| names.iter().map(move |n| (n.as_str(), *addr)))
+    }
+}
+
+/* Which source file and line each range of addresses was assembled from, for debuggers that
+show source. Files are kept by the name the debug info used. */
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    spans: BTreeMap<u16, Span>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    file: usize,
+    line: u32,
+    end: u16, // Inclusive
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        LineTable::default()
+    }
+
+    // The code for a line takes 'len' bytes from 'address' on
+    pub fn insert(&mut self, file: &str, line: u32, address: u16, len: u16) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(i) => i,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            }
+        };
+        let end = address.saturating_add(len.max(1) - 1);
+        self.spans.insert(address, Span { file, line, end });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    // The file and line an address was assembled from
+    pub fn location(&self, address: u16) -> Option<(&str, u32)> {
+        let (_, span) = self.spans.range(..=address).next_back()?;
+        match address <= span.end {
+            true => Some((self.files[span.file].as_str(), span.line)),
+            false => None,
+        }
+    }
+
+    /* The first address of a line, or of the closest line after it that has code if it has none
+    itself, along with the line that was picked. The file may be given as a longer or shorter
+    path than the one in the table. */
+    pub fn address(&self, file: &str, line: u32) -> Option<(u16, u32)> {
+        let file = self.files.iter().position(|f| same_file(f, file))?;
+        self.spans
+            .iter()
+            .filter(|(_, span)| span.file == file && span.line >= line)
+            .map(|(address, span)| (span.line, *address))
+            .min()
+            .map(|(line, address)| (address, line))
+    }
+
+    // The line whose code starts right at the address, for marking lines in disassembly
+    pub fn line_at(&self, address: u16) -> Option<(&str, u32)> {
+        let span = self.spans.get(&address)?;
+        Some((self.files[span.file].as_str(), span.line))
+    }
+
+    // "main.s:42", with just the file's name and not its directory
+    pub fn describe(&self, address: u16) -> Option<String> {
+        let (file, line) = self.location(address)?;
+        let name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+        Some(format!("{}:{}", name, line))
+    }
+
+    // Every line with code as (file, line, first address, last address), in address order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32, u16, u16)> {
+        self.spans.iter().map(|(address, span)| {
+            (
+                self.files[span.file].as_str(),
+                span.line,
+                *address,
+                span.end,
+            )
+        })
+    }
+
+    // Adds another table's lines, which win where the two overlap
+    pub fn extend(&mut self, other: &LineTable) {
+        for (&address, span) in &other.spans {
+            let len = (span.end - address).saturating_add(1);
+            self.insert(&other.files[span.file], span.line, address, len);
+        }
+    }
+}
+
+// Whether two paths name the same file, one possibly being relative to some directory
+pub fn same_file(a: &str, b: &str) -> bool {
+    let a = a.replace('\\', "/");
+    let b = b.replace('\\', "/");
+    let (short, long) = match a.len() <= b.len() {
+        true => (a.trim_start_matches("./"), b.as_str()),
+        false => (b.trim_start_matches("./"), a.as_str()),
+    };
+    long == short || long.ends_with(&format!("/{}", short))
+}
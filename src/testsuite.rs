@@ -0,0 +1,3 @@
+// Runners for the well known 6502 test programs
+pub mod decimal;
+pub mod klaus;
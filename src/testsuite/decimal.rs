@@ -0,0 +1,244 @@
+use crate::memory::Ram;
+use crate::*;
+use std::fmt;
+
+/* Exhaustive check of decimal mode ADC/SBC in the spirit of Bruce Clark's decimal mode test
+(http://www.6502.org/tutorials/decimal_mode.html). Rather than running the test program itself,
+every accumulator/operand/carry combination is executed on the CPU and compared against the
+reference sequences from appendix B of the tutorial. The CPU only implements NMOS behaviour, so
+that's what is modelled here; the 65C02 differs in the flags (valid N/Z, extra cycle) and would
+need its own reference sequences once there's a core to check them against. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Adc,
+    Sbc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inputs {
+    All,      // Every byte value, including invalid BCD like $1F or $A0
+    ValidBcd, // Only $00-$99 with both nibbles 0-9
+}
+
+// Accumulator and the N, V, Z and C flags after the operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecimalResult {
+    pub a: u8,
+    pub flags: StatusFlags,
+}
+
+const CHECKED_FLAGS: StatusFlags = StatusFlags::N
+    .union(StatusFlags::V)
+    .union(StatusFlags::Z)
+    .union(StatusFlags::C);
+
+pub fn is_valid_bcd(value: u8) -> bool {
+    value & 0x0F <= 0x09 && value >> 4 <= 0x09
+}
+
+// What an NMOS 6502 produces for the operation in decimal mode
+pub fn reference(op: Operation, a: u8, b: u8, carry: bool) -> DecimalResult {
+    let c = carry as i32;
+    let (ai, bi) = (a as i32, b as i32);
+    let mut flags = StatusFlags::empty();
+
+    match op {
+        Operation::Adc => {
+            // Sequence 1: accumulator and carry
+            let mut al = (ai & 0x0F) + (bi & 0x0F) + c;
+            if al >= 0x0A {
+                al = ((al + 0x06) & 0x0F) + 0x10;
+            }
+            let mut sum = (ai & 0xF0) + (bi & 0xF0) + al;
+            if sum >= 0xA0 {
+                sum += 0x60;
+            }
+            flags.set(StatusFlags::C, sum >= 0x100);
+
+            // Sequence 2: N and V come from the intermediate result, using signed arithmetic
+            let signed = (a & 0xF0) as i8 as i32 + (b & 0xF0) as i8 as i32 + al;
+            flags.set(StatusFlags::N, signed & 0x80 != 0);
+            flags.set(StatusFlags::V, !(-128..=127).contains(&signed));
+
+            // Z is based on the binary sum
+            flags.set(StatusFlags::Z, (ai + bi + c) & 0xFF == 0);
+
+            DecimalResult {
+                a: sum as u8,
+                flags,
+            }
+        }
+        Operation::Sbc => {
+            // Sequence 3: accumulator
+            let mut al = (ai & 0x0F) - (bi & 0x0F) + c - 1;
+            if al < 0 {
+                al = ((al - 0x06) & 0x0F) - 0x10;
+            }
+            let mut diff = (ai & 0xF0) - (bi & 0xF0) + al;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+
+            // All flags are the same as for a binary subtraction
+            let binary = ai - bi + c - 1;
+            flags.set(StatusFlags::C, binary >= 0);
+            flags.set(StatusFlags::N, binary & 0x80 != 0);
+            flags.set(StatusFlags::Z, binary & 0xFF == 0);
+            let signed = a as i8 as i32 - b as i8 as i32 + c - 1;
+            flags.set(StatusFlags::V, !(-128..=127).contains(&signed));
+
+            DecimalResult {
+                a: diff as u8,
+                flags,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Mismatch {
+    pub op: Operation,
+    pub a: u8,
+    pub operand: u8,
+    pub carry: bool,
+    pub expected: DecimalResult,
+    pub actual: DecimalResult,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, sign) = match self.op {
+            Operation::Adc => ("ADC", '+'),
+            Operation::Sbc => ("SBC", '-'),
+        };
+        let valid = match is_valid_bcd(self.a) && is_valid_bcd(self.operand) {
+            true => "valid",
+            false => "invalid",
+        };
+        write!(
+            f,
+            "{} ${:02X} {} ${:02X} with C={} ({} BCD): expected A=${:02X} {}, got A=${:02X} {}",
+            name,
+            self.a,
+            sign,
+            self.operand,
+            self.carry as u8,
+            valid,
+            self.expected.a,
+            nvzc(self.expected.flags),
+            self.actual.a,
+            nvzc(self.actual.flags)
+        )
+    }
+}
+
+fn nvzc(flags: StatusFlags) -> String {
+    [
+        (StatusFlags::N, 'N'),
+        (StatusFlags::V, 'V'),
+        (StatusFlags::Z, 'Z'),
+        (StatusFlags::C, 'C'),
+    ]
+    .iter()
+    .map(|(flag, c)| match flags.contains(*flag) {
+        true => *c,
+        false => c.to_ascii_lowercase(),
+    })
+    .collect()
+}
+
+pub struct DecimalReport {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl DecimalReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for DecimalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} combinations checked, {} mismatches",
+            self.checked,
+            self.mismatches.len()
+        )?;
+        for m in &self.mismatches {
+            writeln!(f, "{}", m)?;
+        }
+        Ok(())
+    }
+}
+
+// Execute one immediate mode ADC/SBC with the D flag set
+fn execute(
+    cpu: &mut Cpu6502,
+    ram: &Ram,
+    op: Operation,
+    a: u8,
+    b: u8,
+    carry: bool,
+) -> DecimalResult {
+    let opcode = match op {
+        Operation::Adc => 0x69,
+        Operation::Sbc => 0xE9,
+    };
+    ram.load(0x0200, &[opcode, b]);
+
+    cpu.registers.pc = 0x0200;
+    cpu.registers.a = a;
+    cpu.registers.p = StatusFlags::E | StatusFlags::D;
+    cpu.registers.p.set(StatusFlags::C, carry);
+    cpu.tick();
+
+    DecimalResult {
+        a: cpu.registers.a,
+        flags: cpu.registers.p & CHECKED_FLAGS,
+    }
+}
+
+/* Run every combination and collect the ones that disagree with the reference, stopping once
+max_mismatches have been found. */
+pub fn run(inputs: Inputs, max_mismatches: usize) -> DecimalReport {
+    let ram = Ram::new();
+    let mut cpu = ram.cpu();
+
+    let values: Vec<u8> = (0..=255)
+        .filter(|v| inputs == Inputs::All || is_valid_bcd(*v))
+        .collect();
+
+    let mut report = DecimalReport {
+        checked: 0,
+        mismatches: Vec::new(),
+    };
+    for op in [Operation::Adc, Operation::Sbc] {
+        for carry in [false, true] {
+            for &a in &values {
+                for &b in &values {
+                    let expected = reference(op, a, b, carry);
+                    let actual = execute(&mut cpu, &ram, op, a, b, carry);
+                    report.checked += 1;
+
+                    if expected != actual {
+                        report.mismatches.push(Mismatch {
+                            op,
+                            a,
+                            operand: b,
+                            carry,
+                            expected,
+                            actual,
+                        });
+                        if report.mismatches.len() >= max_mismatches {
+                            return report;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    report
+}
@@ -0,0 +1,202 @@
+use crate::memory::Ram;
+use crate::run::*;
+use crate::*;
+use std::fmt;
+
+/* Runner for Klaus Dormann's 6502 test suite (https://github.com/Klaus2m5/6502_65C02_functional_tests).
+The binaries aren't distributed with this crate: either download the prebuilt ones or assemble
+them from source, then pass the image in. Every test ends up in a "jmp *" (or branch to itself)
+trap loop, and only the success trap means the test passed. The success address depends on how
+the test was assembled, so check the listing for it. */
+
+// Lets the interrupt test raise IRQ/NMI by writing to a memory mapped port
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptPort {
+    pub address: u16,
+    pub irq_bit: u8,
+    pub nmi_bit: Option<u8>,
+}
+
+impl Default for InterruptPort {
+    // Matches the default configuration of 6502_interrupt_test.a65
+    fn default() -> Self {
+        InterruptPort {
+            address: 0xBFFC,
+            irq_bit: 0,
+            nmi_bit: Some(1),
+        }
+    }
+}
+
+pub struct TestRom {
+    pub image: Vec<u8>,
+    pub load_address: u16,
+    pub start: u16,
+
+    // Address of the success trap, None if any trap counts (see error_flag)
+    pub success: Option<u16>,
+
+    // Where the test keeps the number of the test case currently running
+    pub test_case: Option<u16>,
+
+    // Byte that's left at zero when the test passed
+    pub error_flag: Option<u16>,
+
+    pub interrupt_port: Option<InterruptPort>,
+
+    // Give up after this many cycles in case the test never reaches a trap
+    pub max_cycles: u64,
+}
+
+impl TestRom {
+    // 6502_functional_test.bin, a full 64K image starting at $0000 with code at $0400
+    pub fn functional(image: Vec<u8>, success: u16) -> Self {
+        TestRom {
+            image,
+            load_address: 0x0000,
+            start: 0x0400,
+            success: Some(success),
+            test_case: Some(0x0200),
+            error_flag: None,
+            interrupt_port: None,
+            max_cycles: 200_000_000,
+        }
+    }
+
+    // 6502_decimal_test.bin, assembled at $0200 with its ERROR byte at $0B
+    pub fn decimal(image: Vec<u8>) -> Self {
+        TestRom {
+            image,
+            load_address: 0x0200,
+            start: 0x0200,
+            success: None,
+            test_case: None,
+            error_flag: Some(0x000B),
+            interrupt_port: None,
+            max_cycles: 200_000_000,
+        }
+    }
+
+    // 6502_interrupt_test.bin, a full 64K image using the feedback port at $BFFC
+    pub fn interrupt(image: Vec<u8>, success: u16) -> Self {
+        TestRom {
+            image,
+            load_address: 0x0000,
+            start: 0x0400,
+            success: Some(success),
+            test_case: Some(0x0200),
+            error_flag: None,
+            interrupt_port: Some(InterruptPort::default()),
+            max_cycles: 10_000_000,
+        }
+    }
+
+    pub fn run(&self) -> TestReport {
+        let ram = Ram::new();
+        ram.load(self.load_address, &self.image);
+
+        let mut cpu = ram.cpu();
+        cpu.registers.pc = self.start;
+        cpu.registers.s = 0xFF;
+        cpu.registers.p = StatusFlags::E | StatusFlags::I;
+
+        let options = RunOptions {
+            max_cycles: Some(self.max_cycles),
+            ..Default::default()
+        };
+        let result = cpu.run_with(&options, |cpu| {
+            if let Some(port) = &self.interrupt_port {
+                let value = ram.read(port.address);
+                cpu.set_irq(value & (1 << port.irq_bit) != 0);
+                if let Some(bit) = port.nmi_bit {
+                    cpu.set_nmi(value & (1 << bit) != 0);
+                }
+            }
+        });
+
+        let error = self.error_flag.map(|addr| ram.read(addr));
+        let outcome = match result.reason {
+            StopReason::Trap => {
+                match self.success.unwrap_or(result.pc) == result.pc && error.unwrap_or(0) == 0 {
+                    true => Outcome::Passed,
+                    false => Outcome::Failed,
+                }
+            }
+            StopReason::Halted => Outcome::Halted,
+            StopReason::CycleLimit
+            | StopReason::Timeout
+            | StopReason::Stopped
+            | StopReason::Loop(_) => Outcome::Timeout,
+        };
+
+        TestReport {
+            outcome,
+            pc: result.pc,
+            registers: cpu.registers,
+            cycles: result.cycles,
+            instructions: result.instructions,
+            test_case: self.test_case.map(|addr| ram.read(addr)),
+            error,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,  // Trapped somewhere other than the success address
+    Halted,  // Executed a JAM opcode
+    Timeout, // Hit max_cycles without reaching a trap
+}
+
+pub struct TestReport {
+    pub outcome: Outcome,
+    pub pc: u16,
+    pub registers: Registers,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub test_case: Option<u8>,
+    pub error: Option<u8>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.outcome {
+            Outcome::Passed => "Passed",
+            Outcome::Failed => "FAILED, trapped",
+            Outcome::Halted => "FAILED, halted",
+            Outcome::Timeout => "FAILED, timed out",
+        };
+        write!(
+            f,
+            "{} at ${:04X} after {} instructions ({} cycles)",
+            what, self.pc, self.instructions, self.cycles
+        )?;
+
+        if self.outcome != Outcome::Passed {
+            if let Some(test_case) = self.test_case {
+                write!(f, ", test case ${:02X}", test_case)?;
+            }
+            if let Some(error) = self.error {
+                write!(f, ", error flag ${:02X}", error)?;
+            }
+            let r = &self.registers;
+            write!(
+                f,
+                "\nA:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                r.a,
+                r.x,
+                r.y,
+                r.p.bits(),
+                r.s
+            )?;
+        }
+        Ok(())
+    }
+}
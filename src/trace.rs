@@ -0,0 +1,250 @@
+use super::debuginfo::DebugInfo;
+use super::disasm::Instruction;
+use super::*;
+
+pub mod diff;
+
+type TraceOutput<'a> = Box<dyn FnMut(&str) + 'a>;
+
+/* Placeholders understood by TraceFormat::Custom:
+{pc} {bytes} {instr} {mnemonic} {operand} {a} {x} {y} {p} {sp} {flags} {cyc}
+and, when the tracer has debug info, {label} (like draw_sprite+3) and {source} (like main.s:42) */
+pub enum TraceFormat {
+    // The Nintendulator/nestest.log line format (minus the PPU columns)
+    Nestest,
+    Custom(String),
+}
+
+// Everything known about an instruction just before it executes
+pub struct TraceRecord {
+    pub instr: Instruction,
+    pub registers: Registers,
+    pub cycles: u64,
+
+    // The operand with effective addresses and memory values resolved (if memory can be peeked)
+    pub operand: String,
+
+    // Where the instruction is by symbol and source line, empty when unknown
+    pub label: String,
+    pub source: String,
+}
+
+impl TraceRecord {
+    pub fn new(cpu: &Cpu6502, opcode: u8, operands: &[u8]) -> Self {
+        let pc = cpu.registers.pc;
+        let mut instr = Instruction::decode(pc, &[opcode, operands[0], operands[1]]);
+
+        // JSR reads its own operands during execution, so they haven't been fetched yet
+        if opcode == 0x20 {
+            instr.operands[0] = cpu.peek(pc.wrapping_add(1) as usize).unwrap_or(0);
+            instr.operands[1] = cpu.peek(pc.wrapping_add(2) as usize).unwrap_or(0);
+        }
+
+        TraceRecord {
+            instr,
+            registers: cpu.registers,
+            cycles: cpu.total_cycles - cpu.cycles as u64,
+            operand: resolve_operand(cpu, &instr),
+            label: String::new(),
+            source: String::new(),
+        }
+    }
+
+    pub fn format(&self, format: &TraceFormat) -> String {
+        match format {
+            TraceFormat::Nestest => self.nestest(),
+            TraceFormat::Custom(template) => self.custom(template),
+        }
+    }
+
+    pub fn hex_bytes(&self) -> String {
+        self.instr
+            .bytes()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn disassembly(&self, mnemonic: &str) -> String {
+        match self.operand.is_empty() {
+            true => mnemonic.to_string(),
+            false => format!("{} {}", mnemonic, self.operand),
+        }
+    }
+
+    fn nestest(&self) -> String {
+        // nestest.log uses a few different names for the illegal opcodes
+        let mnemonic = match self.instr.mnemonic() {
+            "isc" => "ISB".to_string(),
+            "usb" => "SBC".to_string(),
+            name => name.to_uppercase(),
+        };
+        let marker = match self.instr.is_illegal() {
+            true => '*',
+            false => ' ',
+        };
+
+        format!(
+            "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.instr.address,
+            self.hex_bytes(),
+            marker,
+            self.disassembly(&mnemonic),
+            self.registers.a,
+            self.registers.x,
+            self.registers.y,
+            self.registers.p.bits(),
+            self.registers.s,
+            self.cycles
+        )
+    }
+
+    fn custom(&self, template: &str) -> String {
+        let regs = &self.registers;
+        let mnemonic = self.instr.mnemonic().to_uppercase();
+        template
+            .replace("{pc}", &format!("{:04X}", self.instr.address))
+            .replace("{bytes}", &self.hex_bytes())
+            .replace("{instr}", &self.disassembly(&mnemonic))
+            .replace("{mnemonic}", &mnemonic)
+            .replace("{operand}", &self.operand)
+            .replace("{a}", &format!("{:02X}", regs.a))
+            .replace("{x}", &format!("{:02X}", regs.x))
+            .replace("{y}", &format!("{:02X}", regs.y))
+            .replace("{p}", &format!("{:02X}", regs.p.bits()))
+            .replace("{sp}", &format!("{:02X}", regs.s))
+            .replace("{flags}", &flag_string(regs.p))
+            .replace("{cyc}", &self.cycles.to_string())
+            .replace("{label}", &self.label)
+            .replace("{source}", &self.source)
+    }
+}
+
+// Status register as letters (NV-BDIZC), uppercase when set
+pub fn flag_string(p: StatusFlags) -> String {
+    "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match p.bits() & (1 << (7 - i)) != 0 {
+            true => c,
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+// Render the operand like nestest does: "$0300,X @ 0301 = 5A"
+fn resolve_operand(cpu: &Cpu6502, instr: &Instruction) -> String {
+    let operand = instr.operand();
+    let regs = &cpu.registers;
+    let peek = |addr: u16| cpu.peek(addr as usize);
+    let peek16 = |lo: u16, hi: u16| match (peek(lo), peek(hi)) {
+        (Some(lsb), Some(msb)) => Some((msb as u16) << 8 | lsb as u16),
+        _ => None,
+    };
+
+    // Without a way to peek at memory we can only show the plain operand
+    if cpu.mem_peek.is_none() {
+        return operand;
+    }
+
+    let byte = instr.operands[0];
+    let word = instr.word();
+    match instr.mode() {
+        AddrMode::ABS0 => match instr.opcode {
+            0x20 | 0x4C => operand, // JSR/JMP only use the address
+            _ => format!("{} = {:02X}", operand, peek(word).unwrap()),
+        },
+        AddrMode::ABSX | AddrMode::ABSY => {
+            let index = match instr.mode() {
+                AddrMode::ABSX => regs.x,
+                _ => regs.y,
+            };
+            let addr = word.wrapping_add(index as u16);
+            format!("{} @ {:04X} = {:02X}", operand, addr, peek(addr).unwrap())
+        }
+        AddrMode::IND0 => {
+            // Same page wrap bug as the real JMP
+            let hi = (word & 0xFF00) | (word as u8).wrapping_add(1) as u16;
+            format!("{} = {:04X}", operand, peek16(word, hi).unwrap())
+        }
+        AddrMode::INDX => {
+            let ptr = byte.wrapping_add(regs.x);
+            let addr = peek16(ptr as u16, ptr.wrapping_add(1) as u16).unwrap();
+            format!(
+                "{} @ {:02X} = {:04X} = {:02X}",
+                operand,
+                ptr,
+                addr,
+                peek(addr).unwrap()
+            )
+        }
+        AddrMode::INDY => {
+            let base = peek16(byte as u16, byte.wrapping_add(1) as u16).unwrap();
+            let addr = base.wrapping_add(regs.y as u16);
+            format!(
+                "{} = {:04X} @ {:04X} = {:02X}",
+                operand,
+                base,
+                addr,
+                peek(addr).unwrap()
+            )
+        }
+        AddrMode::ZPG0 => format!("{} = {:02X}", operand, peek(byte as u16).unwrap()),
+        AddrMode::ZPGX | AddrMode::ZPGY => {
+            let index = match instr.mode() {
+                AddrMode::ZPGX => regs.x,
+                _ => regs.y,
+            };
+            let addr = byte.wrapping_add(index);
+            format!(
+                "{} @ {:02X} = {:02X}",
+                operand,
+                addr,
+                peek(addr as u16).unwrap()
+            )
+        }
+        _ => operand,
+    }
+}
+
+/* Formats each instruction executed by Cpu6502::tick() and hands the line to an output closure.
+Writing the trace to a file would look like:
+
+    let mut log = File::create("trace.log").unwrap();
+    cpu.set_tracer(Tracer::new(
+        TraceFormat::Nestest,
+        Box::new(move |line| writeln!(log, "{}", line).unwrap()),
+    ));
+*/
+pub struct Tracer<'a> {
+    format: TraceFormat,
+    output: TraceOutput<'a>,
+    debug_info: Option<DebugInfo>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(format: TraceFormat, output: TraceOutput<'a>) -> Self {
+        Tracer {
+            format,
+            output,
+            debug_info: None,
+        }
+    }
+
+    // Fills in the {label} and {source} placeholders
+    pub fn with_debug_info(mut self, info: DebugInfo) -> Self {
+        self.debug_info = Some(info);
+        self
+    }
+
+    pub(crate) fn log(&mut self, cpu: &Cpu6502, opcode: u8, operands: &[u8]) {
+        let mut record = TraceRecord::new(cpu, opcode, operands);
+        if let Some(info) = &self.debug_info {
+            let pc = record.instr.address;
+            record.label = info.symbols.describe(pc).unwrap_or_default();
+            record.source = info.lines.describe(pc).unwrap_or_default();
+        }
+        (self.output)(&record.format(&self.format));
+    }
+}
@@ -0,0 +1,381 @@
+use super::flag_string;
+use crate::StatusFlags;
+use std::fmt;
+
+/* Compares two execution traces line by line. The parser is deliberately loose so that our own
+traces can be checked against nestest.log, Mesen or VICE traces: the PC is the first address on
+the line and registers are picked out of "A:xx" style fields wherever they appear. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Pc,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Cycles,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Field::Pc => "PC",
+            Field::A => "A",
+            Field::X => "X",
+            Field::Y => "Y",
+            Field::P => "P",
+            Field::Sp => "SP",
+            Field::Cycles => "CYC",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// One parsed trace line, fields missing from a format are left as None
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub line: usize,
+    pub text: String,
+    pub pc: u16,
+    pub a: Option<u8>,
+    pub x: Option<u8>,
+    pub y: Option<u8>,
+    pub p: Option<u8>,
+    pub sp: Option<u8>,
+    pub cycles: Option<u64>,
+}
+
+impl LogEntry {
+    // Returns None for lines without an instruction address (headers, blank lines, etc.)
+    pub fn parse(line: usize, text: &str) -> Option<Self> {
+        let mut tokens = text.split_whitespace().peekable();
+
+        // VICE prefixes the address with the memory space (".C:0400")
+        let first = tokens.next()?;
+        let first = first.rsplit(':').next().unwrap();
+        if first.len() != 4 {
+            return None;
+        }
+        let pc = u16::from_str_radix(first, 16).ok()?;
+
+        let mut entry = LogEntry {
+            line,
+            text: text.to_string(),
+            pc,
+            a: None,
+            x: None,
+            y: None,
+            p: None,
+            sp: None,
+            cycles: None,
+        };
+
+        while let Some(token) = tokens.next() {
+            let Some((key, mut value)) = token.split_once(':') else {
+                continue;
+            };
+
+            // Some formats pad the value after the colon ("CYC:  7")
+            if value.is_empty() {
+                match tokens.peek() {
+                    Some(next) if !next.contains(':') => value = tokens.next().unwrap(),
+                    _ => continue,
+                }
+            }
+
+            match key.to_ascii_uppercase().as_str() {
+                "A" => entry.a = parse_hex(value),
+                "X" => entry.x = parse_hex(value),
+                "Y" => entry.y = parse_hex(value),
+                "SP" | "S" => entry.sp = parse_hex(value),
+                "P" => entry.p = parse_hex(value).or_else(|| parse_flags(value)),
+                "CYC" => entry.cycles = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(entry)
+    }
+
+    fn get(&self, field: Field) -> Option<u64> {
+        match field {
+            Field::Pc => Some(self.pc as u64),
+            Field::A => self.a.map(u64::from),
+            Field::X => self.x.map(u64::from),
+            Field::Y => self.y.map(u64::from),
+            Field::P => self.p.map(u64::from),
+            Field::Sp => self.sp.map(u64::from),
+            Field::Cycles => self.cycles,
+        }
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u8> {
+    match value.len() {
+        2 => u8::from_str_radix(value, 16).ok(),
+        _ => None,
+    }
+}
+
+// Mesen style flags ("nvUbdIzc"), uppercase meaning set
+fn parse_flags(value: &str) -> Option<u8> {
+    if value.len() != 8 {
+        return None;
+    }
+
+    Some(
+        value
+            .chars()
+            .enumerate()
+            .fold(0, |p, (i, c)| match c.is_ascii_uppercase() {
+                true => p | 1 << (7 - i),
+                false => p,
+            }),
+    )
+}
+
+pub fn parse_log(log: &str) -> Vec<LogEntry> {
+    log.lines()
+        .enumerate()
+        .filter_map(|(i, text)| LogEntry::parse(i + 1, text))
+        .collect()
+}
+
+pub struct DiffOptions {
+    // Fields that are never compared (a field missing from either log is also skipped)
+    pub ignore: Vec<Field>,
+
+    // Status flag bits to compare, e.g. !0x30 to ignore the B and unused bits
+    pub p_mask: u8,
+
+    // Added to our cycle count before comparing it, for logs that start counting elsewhere
+    pub cycle_offset: i64,
+
+    // Skip leading lines in both logs until the first PC they have in common
+    pub sync_on_pc: bool,
+
+    // Number of matching instructions shown before the divergence
+    pub context: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            ignore: Vec::new(),
+            p_mask: 0xFF,
+            cycle_offset: 0,
+            sync_on_pc: true,
+            context: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldDelta {
+    pub field: Field,
+    pub ours: u64,
+    pub reference: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    // Index of the diverging instruction, counted from the point both logs were aligned
+    pub index: usize,
+    pub ours: LogEntry,
+    pub reference: LogEntry,
+    pub deltas: Vec<FieldDelta>,
+
+    // The matching instructions leading up to the divergence (from our log)
+    pub context: Vec<LogEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiffReport {
+    // Lines skipped in each log to line them up
+    pub ours_skipped: usize,
+    pub reference_skipped: usize,
+
+    pub compared: usize,
+    pub divergence: Option<Divergence>,
+
+    // Instructions left over once the shorter log ran out
+    pub ours_remaining: usize,
+    pub reference_remaining: usize,
+}
+
+impl DiffReport {
+    pub fn is_match(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+pub fn diff(ours: &str, reference: &str, options: &DiffOptions) -> DiffReport {
+    diff_entries(&parse_log(ours), &parse_log(reference), options)
+}
+
+pub fn diff_entries(
+    ours: &[LogEntry],
+    reference: &[LogEntry],
+    options: &DiffOptions,
+) -> DiffReport {
+    let (ours_skipped, reference_skipped) = match options.sync_on_pc {
+        true => align(ours, reference),
+        false => (0, 0),
+    };
+    let ours = &ours[ours_skipped..];
+    let reference = &reference[reference_skipped..];
+
+    let mut compared = 0;
+    let mut divergence = None;
+    for (index, (o, r)) in ours.iter().zip(reference).enumerate() {
+        let deltas = compare(o, r, options);
+        if !deltas.is_empty() {
+            divergence = Some(Divergence {
+                index,
+                ours: o.clone(),
+                reference: r.clone(),
+                deltas,
+                context: ours[index.saturating_sub(options.context)..index].to_vec(),
+            });
+            break;
+        }
+        compared += 1;
+    }
+
+    DiffReport {
+        ours_skipped,
+        reference_skipped,
+        compared,
+        divergence,
+        ours_remaining: ours.len() - compared.min(ours.len()),
+        reference_remaining: reference.len() - compared.min(reference.len()),
+    }
+}
+
+// Find the first PC of the reference log that also shows up in ours
+fn align(ours: &[LogEntry], reference: &[LogEntry]) -> (usize, usize) {
+    for (r, entry) in reference.iter().enumerate() {
+        if let Some(o) = ours.iter().position(|e| e.pc == entry.pc) {
+            return (o, r);
+        }
+    }
+    (0, 0)
+}
+
+fn compare(ours: &LogEntry, reference: &LogEntry, options: &DiffOptions) -> Vec<FieldDelta> {
+    let fields = [
+        Field::Pc,
+        Field::A,
+        Field::X,
+        Field::Y,
+        Field::P,
+        Field::Sp,
+        Field::Cycles,
+    ];
+
+    let mut deltas = Vec::new();
+    for field in fields.into_iter().filter(|f| !options.ignore.contains(f)) {
+        let (Some(mut o), Some(mut r)) = (ours.get(field), reference.get(field)) else {
+            continue;
+        };
+
+        match field {
+            Field::P => {
+                o &= options.p_mask as u64;
+                r &= options.p_mask as u64;
+            }
+            Field::Cycles => o = (o as i64 + options.cycle_offset) as u64,
+            _ => {}
+        }
+
+        if o != r {
+            deltas.push(FieldDelta {
+                field,
+                ours: o,
+                reference: r,
+            });
+        }
+    }
+    deltas
+}
+
+impl fmt::Display for FieldDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.field {
+            Field::Pc => write!(f, "PC: ${:04X} != ${:04X}", self.ours, self.reference),
+            Field::Cycles => write!(
+                f,
+                "CYC: {} != {} ({:+})",
+                self.ours,
+                self.reference,
+                self.ours as i64 - self.reference as i64
+            ),
+            Field::P => {
+                // Spell out which flags differ
+                let ours = StatusFlags::from_bits_retain(self.ours as u8);
+                let reference = StatusFlags::from_bits_retain(self.reference as u8);
+                let changed = flag_string(ours ^ reference)
+                    .chars()
+                    .filter(|c| c.is_ascii_uppercase())
+                    .collect::<String>();
+                write!(
+                    f,
+                    "P: ${:02X} ({}) != ${:02X} ({}), differs in {}",
+                    self.ours,
+                    flag_string(ours),
+                    self.reference,
+                    flag_string(reference),
+                    changed
+                )
+            }
+            _ => write!(
+                f,
+                "{}: ${:02X} != ${:02X}",
+                self.field, self.ours, self.reference
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ours_skipped != 0 || self.reference_skipped != 0 {
+            writeln!(
+                f,
+                "Aligned after skipping {} line(s) of ours and {} of the reference",
+                self.ours_skipped, self.reference_skipped
+            )?;
+        }
+
+        match &self.divergence {
+            None => {
+                write!(f, "{} instructions match", self.compared)?;
+                if self.ours_remaining != 0 || self.reference_remaining != 0 {
+                    write!(
+                        f,
+                        " ({} left over in ours, {} in the reference)",
+                        self.ours_remaining, self.reference_remaining
+                    )?;
+                }
+                writeln!(f)
+            }
+            Some(d) => {
+                writeln!(
+                    f,
+                    "Diverged at instruction {} (line {} of ours, line {} of the reference)",
+                    d.index, d.ours.line, d.reference.line
+                )?;
+                for entry in &d.context {
+                    writeln!(f, "    {}", entry.text)?;
+                }
+                writeln!(f, "  < {}", d.ours.text)?;
+                writeln!(f, "  > {}", d.reference.text)?;
+                for delta in &d.deltas {
+                    writeln!(f, "  {}", delta)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
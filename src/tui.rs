@@ -0,0 +1,403 @@
+use crate::debugger::*;
+use crate::disasm::Instruction;
+use crate::memory::MEM_SIZE;
+use crate::monitor::Monitor;
+use crate::*;
+
+#[cfg(feature = "tui")]
+pub mod term;
+
+/* A full screen debugger on top of the monitor: registers, disassembly around the PC, a memory
+view highlighting recent writes, the stack page and the call stack, driven by the keyboard. The
+contents of every pane are put together here independent of any terminal library, the drawing
+and key handling live in tui::term behind the "tui" feature:
+
+    cargo run --features tui --bin tui -- program.bin@0400
+*/
+
+// Writes are highlighted for this many steps (or slices of a continuous run)
+const RECENT_WRITES: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    Dim,
+    Title,
+    Pc,
+    Cursor,
+    Breakpoint,
+    Changed,
+    FlagSet,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+pub type Line = Vec<Span>;
+
+fn span(text: impl Into<String>, style: Style) -> Span {
+    Span {
+        text: text.into(),
+        style,
+    }
+}
+
+fn plain(text: impl Into<String>) -> Line {
+    vec![span(text, Style::Normal)]
+}
+
+pub struct Tui {
+    pub monitor: Monitor,
+
+    // Selected line of the disassembly, which follows the PC when None
+    pub cursor: Option<u16>,
+    pub memory_address: u16,
+
+    // Messages and command output, newest last
+    pub output: Vec<String>,
+
+    // A run starts by executing the instruction at the PC even if there's a breakpoint on it
+    running: bool,
+    resumed: bool,
+    goal: Goal, // Where the run stops, continuing as well as step over, step out and run to
+
+    generation: u32,
+    writes: Vec<u32>, // Generation of the last write to each address
+    snapshot: Vec<u8>,
+}
+
+impl Tui {
+    pub fn new(monitor: Monitor) -> Self {
+        let snapshot = monitor.ram.dump(0, MEM_SIZE);
+        Tui {
+            monitor,
+            cursor: None,
+            memory_address: 0,
+            output: Vec::new(),
+            running: false,
+            resumed: false,
+            goal: Goal::Continue,
+            generation: 1,
+            writes: vec![0; MEM_SIZE],
+            snapshot,
+        }
+    }
+
+    fn debugger(&self) -> &Debugger<'static> {
+        &self.monitor.debugger
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn message(&mut self, text: &str) {
+        self.output.extend(text.lines().map(str::to_string));
+        let len = self.output.len();
+        if len > 100 {
+            self.output.drain(..len - 100);
+        }
+    }
+
+    // Run a monitor command, as typed on the command line. g, n, ret and u run in slices.
+    pub fn command(&mut self, line: &str) {
+        self.message(&format!(": {}", line));
+        match self.monitor.run_goal(line) {
+            Ok(Some(goal)) => return self.start(goal),
+            Ok(None) => {}
+            Err(e) => return self.message(&format!("? {}", e)),
+        }
+        match self.monitor.execute(line) {
+            Ok(output) => self.message(&output),
+            Err(e) => self.message(&format!("? {}", e)),
+        }
+        self.track_writes();
+    }
+
+    pub fn step(&mut self) {
+        let stop = self.monitor.debugger.step();
+        self.stopped(stop);
+    }
+
+    // Anything but a single step runs in slices like continuing does, so it can be paused
+    pub fn step_over(&mut self) {
+        match self.debugger().step_over_goal() {
+            Some(goal) => self.start(goal),
+            None => self.step(),
+        }
+    }
+
+    pub fn step_out(&mut self) {
+        let goal = self.debugger().step_out_goal();
+        self.start(goal);
+    }
+
+    // Start running, the caller then keeps calling run_slice() for as long as is_running()
+    pub fn cont(&mut self) {
+        self.start(Goal::Continue);
+    }
+
+    fn start(&mut self, goal: Goal) {
+        self.running = true;
+        self.resumed = true;
+        self.goal = goal;
+        self.cursor = None;
+    }
+
+    pub fn pause(&mut self) {
+        if self.running {
+            self.running = false;
+            self.message("Paused");
+            self.track_writes();
+        }
+    }
+
+    pub fn run_slice(&mut self, cycles: u64) {
+        if !self.running {
+            return;
+        }
+
+        let resuming = std::mem::take(&mut self.resumed);
+        match self
+            .monitor
+            .debugger
+            .run_slice_to(&mut self.goal, cycles, resuming)
+        {
+            Stop::CycleLimit => self.track_writes(),
+            stop => self.stopped(stop),
+        }
+    }
+
+    pub fn run_to_cursor(&mut self) {
+        if let Some(address) = self.cursor {
+            self.start(Goal::Address(address));
+        }
+    }
+
+    pub fn toggle_breakpoint(&mut self) {
+        let address = self.cursor.unwrap_or(self.debugger().cpu.registers.pc);
+        let existing = self
+            .debugger()
+            .breakpoints()
+            .iter()
+            .find(|b| b.kind == BreakKind::Exec(address))
+            .map(|b| b.id);
+
+        match existing {
+            Some(id) => {
+                self.monitor.debugger.remove(id);
+                self.message(&format!("Removed breakpoint at ${:04X}", address));
+            }
+            None => {
+                self.monitor.debugger.add(BreakKind::Exec(address));
+                self.message(&format!("Breakpoint at ${:04X}", address));
+            }
+        }
+    }
+
+    // Move the disassembly cursor by a number of instructions
+    pub fn move_cursor(&mut self, rows: usize, down: bool) {
+        let shown: Vec<u16> = self.disassembly(rows).iter().filter_map(|l| l.0).collect();
+        let current = self.cursor.unwrap_or(self.debugger().cpu.registers.pc);
+        let Some(i) = shown.iter().position(|a| *a == current) else {
+            return;
+        };
+
+        self.cursor = match down {
+            true => shown.get(i + 1).copied(),
+            false => i.checked_sub(1).map(|i| shown[i]),
+        }
+        .or(Some(current));
+
+        // Moving past the top needs a line that isn't shown yet
+        if !down && i == 0 {
+            self.cursor = Some(self.start_before(current, 1));
+        }
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        self.running = false;
+        self.cursor = None;
+        if stop != Stop::Step {
+            self.message(&stop.to_string());
+        }
+        if stop == Stop::Halted {
+            let backtrace = self.debugger().backtrace();
+            self.message(&backtrace);
+        }
+        self.track_writes();
+    }
+
+    // Note which memory was written since the last call, through the access log or by comparing
+    fn track_writes(&mut self) {
+        self.generation += 1;
+
+        let accesses = self.monitor.debugger.cpu.last_accesses();
+        for access in accesses.iter().filter(|a| a.kind == AccessKind::Write) {
+            self.writes[access.address as usize] = self.generation;
+        }
+
+        let now = self.monitor.ram.dump(0, MEM_SIZE);
+        for (i, (old, new)) in self.snapshot.iter().zip(&now).enumerate() {
+            if old != new {
+                self.writes[i] = self.generation;
+            }
+        }
+        self.snapshot = now;
+    }
+
+    fn recently_written(&self, address: u16) -> bool {
+        let written = self.writes[address as usize];
+        written != 0 && self.generation - written < RECENT_WRITES
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.monitor.ram.read(address)
+    }
+
+    fn start_before(&self, address: u16, count: usize) -> u16 {
+        disasm::start_before(address, count, |a| self.peek(a))
+    }
+
+    pub fn registers_pane(&self) -> Vec<Line> {
+        let cpu = &self.debugger().cpu;
+        let r = &cpu.registers;
+
+        let flags: Line = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| match r.p.bits() & (1 << (7 - i)) != 0 {
+                true => span(c, Style::FlagSet),
+                false => span(c.to_ascii_lowercase(), Style::Dim),
+            })
+            .collect();
+
+        vec![
+            plain(format!("PC ${:04X}", r.pc)),
+            plain(format!("A  ${:02X}  X ${:02X}  Y ${:02X}", r.a, r.x, r.y)),
+            plain(format!("SP ${:02X}  P ${:02X}", r.s, r.p.bits())),
+            flags,
+            plain(format!("CYC {}", cpu.total_cycles())),
+        ]
+    }
+
+    /* Disassembly around the cursor (or PC), a third of the lines before it. Each line comes
+    with the address of its instruction, None for label lines. */
+    pub fn disassembly(&self, rows: usize) -> Vec<(Option<u16>, Line)> {
+        let pc = self.debugger().cpu.registers.pc;
+        let focus = self.cursor.unwrap_or(pc);
+
+        let mut lines = Vec::new();
+        let mut address = self.start_before(focus, rows / 3);
+        while lines.len() < rows {
+            if let Some(label) = self.debugger().symbols.name(address) {
+                lines.push((None, vec![span(format!("{}:", label), Style::Title)]));
+            }
+
+            let instr = Instruction::fetch(address, |a| self.peek(a));
+            let breakpoint = self
+                .debugger()
+                .breakpoints()
+                .iter()
+                .any(|b| b.enabled && b.kind == BreakKind::Exec(address));
+            let bytes: Vec<String> = instr.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+
+            let (marker, style) = match (address == pc, breakpoint) {
+                (true, _) => ('>', Style::Pc),
+                (false, true) => ('*', Style::Breakpoint),
+                (false, false) => (' ', Style::Normal),
+            };
+            let style = match Some(address) == self.cursor {
+                true => Style::Cursor,
+                false => style,
+            };
+
+            let text = format!(
+                "{}{:04X}  {:<9} {}",
+                marker,
+                address,
+                bytes.join(" "),
+                self.debugger().disassemble(&instr)
+            );
+            // The source line, on the first instruction assembled from it
+            let source = &self.debugger().lines;
+            let line = match source.line_at(address).and(source.describe(address)) {
+                Some(location) => vec![
+                    span(format!("{:<32}", text), style),
+                    span(format!(" ; {}", location), Style::Dim),
+                ],
+                None => vec![span(text, style)],
+            };
+            lines.push((Some(address), line));
+            address = instr.next();
+        }
+
+        lines.truncate(rows);
+        lines
+    }
+
+    pub fn memory_pane(&self, rows: usize) -> Vec<Line> {
+        (0..rows as u16)
+            .map(|row| {
+                let start = self.memory_address.wrapping_add(row * 16);
+                let mut line = vec![span(format!("{:04X} ", start), Style::Dim)];
+                let mut text = String::new();
+                for i in 0..16 {
+                    let address = start.wrapping_add(i);
+                    let value = self.peek(address);
+                    let style = match self.recently_written(address) {
+                        true => Style::Changed,
+                        false => Style::Normal,
+                    };
+                    line.push(span(format!(" {:02X}", value), style));
+                    text.push(match value.is_ascii_graphic() {
+                        true => value as char,
+                        false => '.',
+                    });
+                }
+                line.push(span(format!("  {}", text), Style::Dim));
+                line
+            })
+            .collect()
+    }
+
+    // The used part of the stack page, top of the stack first
+    pub fn stack_pane(&self, rows: usize) -> Vec<Line> {
+        let s = self.debugger().cpu.registers.s;
+        let mut lines = vec![vec![span(
+            format!("${:04X} <- SP", 0x100 + s as u16),
+            Style::Dim,
+        )]];
+        for offset in (s as u16 + 1)..=0xFF {
+            if lines.len() == rows {
+                break;
+            }
+            let address = 0x100 + offset;
+            let style = match self.recently_written(address) {
+                true => Style::Changed,
+                false => Style::Normal,
+            };
+            lines.push(vec![span(
+                format!("${:04X}  {:02X}", address, self.peek(address)),
+                style,
+            )]);
+        }
+        lines
+    }
+
+    pub fn call_stack_pane(&self, rows: usize) -> Vec<Line> {
+        let backtrace = self.debugger().backtrace();
+        backtrace.lines().take(rows).map(plain).collect()
+    }
+
+    pub fn output_pane(&self, rows: usize) -> Vec<Line> {
+        let skip = self.output.len().saturating_sub(rows);
+        self.output[skip..]
+            .iter()
+            .map(|l| plain(l.as_str()))
+            .collect()
+    }
+}
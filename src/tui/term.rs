@@ -0,0 +1,262 @@
+use super::*;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write};
+use std::time::Duration;
+
+// Cycles run between checking the keyboard and redrawing while the CPU is running
+const SLICE_CYCLES: u64 = 20_000;
+
+const KEYS: &str =
+    "s step  n over  o out  c go  space pause  b break  r run to  \u{2191}\u{2193} cursor  PgUp/PgDn memory  : command  q quit";
+
+const LEFT_WIDTH: u16 = 24;
+const RIGHT_WIDTH: u16 = 40;
+const MEMORY_HEIGHT: u16 = 10;
+const OUTPUT_HEIGHT: u16 = 8;
+
+// Where each pane goes for a given terminal size
+struct Layout {
+    registers: Rect,
+    stack: Rect,
+    disassembly: Rect,
+    call_stack: Rect,
+    memory: Rect,
+    output: Rect,
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl Rect {
+    // Rows available inside the border
+    fn rows(&self) -> usize {
+        self.height.saturating_sub(2) as usize
+    }
+}
+
+impl Layout {
+    fn new(width: u16, height: u16) -> Self {
+        let top = height.saturating_sub(MEMORY_HEIGHT + OUTPUT_HEIGHT);
+        let middle = width.saturating_sub(LEFT_WIDTH + RIGHT_WIDTH);
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        Layout {
+            registers: rect(0, 0, LEFT_WIDTH, 7),
+            stack: rect(0, 7, LEFT_WIDTH, top.saturating_sub(7)),
+            disassembly: rect(LEFT_WIDTH, 0, middle, top),
+            call_stack: rect(LEFT_WIDTH + middle, 0, RIGHT_WIDTH, top),
+            memory: rect(0, top, width, MEMORY_HEIGHT),
+            output: rect(0, top + MEMORY_HEIGHT, width, OUTPUT_HEIGHT),
+        }
+    }
+}
+
+// Take over the terminal until the user quits
+pub fn run(mut tui: Tui) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(stdout, EnterAlternateScreen, Hide)?;
+
+    let result = main_loop(&mut tui, &mut stdout);
+
+    execute!(stdout, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn main_loop(tui: &mut Tui, out: &mut impl Write) -> io::Result<()> {
+    let mut input: Option<String> = None;
+
+    loop {
+        let (width, height) = terminal::size()?;
+        let layout = Layout::new(width, height);
+        draw(tui, &layout, input.as_deref(), out)?;
+
+        // Keep running while no key is waiting
+        if tui.is_running() && !event::poll(Duration::ZERO)? {
+            tui.run_slice(SLICE_CYCLES);
+            continue;
+        }
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+
+        // Typing a monitor command
+        if let Some(line) = &mut input {
+            match key.code {
+                KeyCode::Enter => {
+                    let line = input.take().unwrap();
+                    tui.command(&line);
+                    if tui.monitor.is_done() {
+                        return Ok(());
+                    }
+                }
+                KeyCode::Esc => input = None,
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Char(c) => line.push(c),
+                _ => {}
+            }
+            continue;
+        }
+
+        let rows = layout.disassembly.rows();
+        match key.code {
+            KeyCode::Char('q') => return Ok(()),
+            KeyCode::Char(' ') | KeyCode::Char('p') => tui.pause(),
+            _ if tui.is_running() => {}
+            KeyCode::Char('s') => tui.step(),
+            KeyCode::Char('n') => tui.step_over(),
+            KeyCode::Char('o') => tui.step_out(),
+            KeyCode::Char('c') | KeyCode::Char('g') => tui.cont(),
+            KeyCode::Char('b') => tui.toggle_breakpoint(),
+            KeyCode::Char('r') => tui.run_to_cursor(),
+            KeyCode::Char(':') => input = Some(String::new()),
+            KeyCode::Up => tui.move_cursor(rows, false),
+            KeyCode::Down => tui.move_cursor(rows, true),
+            KeyCode::Esc => tui.cursor = None,
+            KeyCode::PageUp => {
+                let page = layout.memory.rows() as u16 * 16;
+                tui.memory_address = tui.memory_address.wrapping_sub(page);
+            }
+            KeyCode::PageDown => {
+                let page = layout.memory.rows() as u16 * 16;
+                tui.memory_address = tui.memory_address.wrapping_add(page);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(tui: &Tui, layout: &Layout, input: Option<&str>, out: &mut impl Write) -> io::Result<()> {
+    queue!(out, Clear(ClearType::All))?;
+
+    let r = &layout.registers;
+    pane(out, r, "Registers", &tui.registers_pane())?;
+    pane(
+        out,
+        &layout.stack,
+        "Stack",
+        &tui.stack_pane(layout.stack.rows()),
+    )?;
+
+    let d = &layout.disassembly;
+    let lines: Vec<Line> = tui.disassembly(d.rows()).into_iter().map(|l| l.1).collect();
+    pane(out, d, "Disassembly", &lines)?;
+
+    let c = &layout.call_stack;
+    pane(out, c, "Call stack", &tui.call_stack_pane(c.rows()))?;
+
+    let m = &layout.memory;
+    let title = format!("Memory ${:04X}", tui.memory_address);
+    pane(out, m, &title, &tui.memory_pane(m.rows()))?;
+
+    // The bottom line of the output pane is either the command being typed or the key help
+    let o = &layout.output;
+    let mut lines = tui.output_pane(o.rows().saturating_sub(1));
+    lines.resize(o.rows().saturating_sub(1), Vec::new());
+    lines.push(match input {
+        Some(line) => vec![span(format!(": {}_", line), Style::Title)],
+        None if tui.is_running() => vec![span("Running... space to pause", Style::Title)],
+        None => vec![span(KEYS, Style::Dim)],
+    });
+    pane(out, o, "Output", &lines)?;
+
+    out.flush()
+}
+
+// A box with a title and lines of text cut off to fit
+fn pane(out: &mut impl Write, rect: &Rect, title: &str, lines: &[Line]) -> io::Result<()> {
+    if rect.width < 4 || rect.height < 2 {
+        return Ok(());
+    }
+
+    let inner = rect.width as usize - 2;
+    let top = format!("\u{250C}\u{2500}{}", title);
+    let top: String = top
+        .chars()
+        .chain(std::iter::repeat('\u{2500}'))
+        .take(inner + 1)
+        .collect();
+    queue!(
+        out,
+        MoveTo(rect.x, rect.y),
+        SetForegroundColor(Color::DarkGrey),
+        Print(top),
+        Print('\u{2510}')
+    )?;
+
+    for row in 0..rect.rows() {
+        let y = rect.y + 1 + row as u16;
+        queue!(
+            out,
+            MoveTo(rect.x, y),
+            SetForegroundColor(Color::DarkGrey),
+            Print('\u{2502}'),
+            ResetColor
+        )?;
+
+        let mut left = inner;
+        for s in lines.get(row).into_iter().flatten() {
+            let text: String = s.text.chars().take(left).collect();
+            left -= text.chars().count();
+            style(out, s.style)?;
+            queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)?;
+        }
+
+        queue!(
+            out,
+            Print(" ".repeat(left)),
+            SetForegroundColor(Color::DarkGrey),
+            Print('\u{2502}')
+        )?;
+    }
+
+    let bottom = format!("\u{2514}{}\u{2518}", "\u{2500}".repeat(inner));
+    queue!(
+        out,
+        MoveTo(rect.x, rect.y + rect.height - 1),
+        Print(bottom),
+        ResetColor
+    )
+}
+
+fn style(out: &mut impl Write, style: Style) -> io::Result<()> {
+    match style {
+        Style::Normal => Ok(()),
+        Style::Dim => queue!(out, SetForegroundColor(Color::DarkGrey)),
+        Style::Title => queue!(out, SetAttribute(Attribute::Bold)),
+        Style::Pc => queue!(out, SetAttribute(Attribute::Reverse)),
+        Style::Cursor => queue!(out, SetAttribute(Attribute::Underlined)),
+        Style::Breakpoint => queue!(out, SetForegroundColor(Color::Red)),
+        Style::Changed => queue!(
+            out,
+            SetForegroundColor(Color::Yellow),
+            SetAttribute(Attribute::Bold)
+        ),
+        Style::FlagSet => queue!(
+            out,
+            SetForegroundColor(Color::Green),
+            SetAttribute(Attribute::Bold)
+        ),
+    }
+}
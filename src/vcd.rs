@@ -0,0 +1,161 @@
+use super::*;
+
+type VcdOutput<'a> = Box<dyn FnMut(&str) + 'a>;
+
+/* Dumps the bus as a Value Change Dump, the waveform format GTKWave and most logic analyzer
+software read, so an emulator run can be lined up against a capture of real hardware. There's a
+sample for every bus cycle: the address bus, data bus, R/W and SYNC (high while an opcode is
+fetched) change while PHI2 is low and the data is there by the time it's high again. The IRQ,
+NMI and RDY lines are included as well. Pins are named as on a W65C02 datasheet and have their
+real levels, so IRQB and NMIB are low while asserted and RWB is high for a read.
+
+    let mut file = BufWriter::new(File::create("bus.vcd").unwrap());
+    cpu.set_vcd(Some(VcdWriter::new(
+        1_000_000,
+        Box::new(move |text| file.write_all(text.as_bytes()).unwrap()),
+    )));
+
+Timestamps are in nanoseconds from the cycle count, at the clock rate given. Cycles where
+nothing was on the bus (the reset sequence) are left out and show up as a gap. */
+
+const HEADER: &str = "\
+$version rust-6502 $end
+$timescale 1ns $end
+$scope module cpu6502 $end
+$var wire 1 ! PHI2 $end
+$var wire 16 \" A [15:0] $end
+$var wire 8 # D [7:0] $end
+$var wire 1 $ RWB $end
+$var wire 1 % SYNC $end
+$var wire 1 & IRQB $end
+$var wire 1 ' NMIB $end
+$var wire 1 ( RDY $end
+$upscope $end
+$enddefinitions $end
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Sample {
+    address: u16,
+    data: u8,
+    read: bool,
+    sync: bool,
+    irq: bool,
+    nmi: bool,
+    rdy: bool,
+}
+
+pub struct VcdWriter<'a> {
+    output: VcdOutput<'a>,
+    period: u64, // Nanoseconds per cycle
+    last: Option<Sample>,
+    sync: bool, // The next read is an opcode fetch
+}
+
+impl<'a> VcdWriter<'a> {
+    // 'clock' is the CPU's clock rate in Hz
+    pub fn new(clock: u64, output: VcdOutput<'a>) -> Self {
+        VcdWriter {
+            output,
+            period: (1_000_000_000 / clock.max(1)).max(2),
+            last: None,
+            sync: false,
+        }
+    }
+
+    fn sample(&mut self, cycle: u64, sample: Sample) {
+        let time = cycle * self.period;
+        let mut out = String::new();
+
+        // PHI2 low, with everything but the data bus changing
+        match self.last {
+            None => {
+                out += HEADER;
+                out += &format!("#{}\n$dumpvars\n0!\nbx #\n", time);
+            }
+            Some(_) => out += &format!("#{}\n0!\n", time),
+        }
+        let last = self.last;
+        if last.map(|l| l.address) != Some(sample.address) {
+            out += &format!("b{:016b} \"\n", sample.address);
+        }
+        let pins = [
+            (sample.read, last.map(|l| l.read), '$'),
+            (sample.sync, last.map(|l| l.sync), '%'),
+            (!sample.irq, last.map(|l| !l.irq), '&'),
+            (!sample.nmi, last.map(|l| !l.nmi), '\''),
+            (sample.rdy, last.map(|l| l.rdy), '('),
+        ];
+        for (level, last, id) in pins {
+            if last != Some(level) {
+                out += &format!("{}{}\n", level as u8, id);
+            }
+        }
+        if last.is_none() {
+            out += "$end\n";
+        }
+
+        // PHI2 high, the data is on the bus
+        out += &format!("#{}\n1!\n", time + self.period / 2);
+        if last.map(|l| l.data) != Some(sample.data) {
+            out += &format!("b{:08b} #\n", sample.data);
+        }
+
+        (self.output)(&out);
+        self.last = Some(sample);
+    }
+}
+
+impl<'a> Cpu6502<'a> {
+    // Start (or with None stop) dumping the bus
+    pub fn set_vcd(&mut self, vcd: Option<VcdWriter<'a>>) {
+        self.vcd = vcd;
+    }
+
+    pub fn take_vcd(&mut self) -> Option<VcdWriter<'a>> {
+        self.vcd.take()
+    }
+
+    // Called ahead of reading an opcode, which is when SYNC is high
+    pub(crate) fn vcd_fetch(&mut self) {
+        if let Some(vcd) = &mut self.vcd {
+            vcd.sync = true;
+        }
+    }
+
+    // Called by read() and write() once the cycle is counted
+    pub(crate) fn vcd_cycle(&mut self, address: usize, value: u8, read: bool) {
+        if let Some(vcd) = &mut self.vcd {
+            let sample = Sample {
+                address: address as u16,
+                data: value,
+                read,
+                sync: read && vcd.sync,
+                irq: self.irq,
+                nmi: self.nmi,
+                rdy: self.rdy,
+            };
+            vcd.sync = false;
+            vcd.sample(self.total_cycles - 1, sample);
+        }
+    }
+
+    // Called by tick() for a cycle stalled by RDY, the bus just holds on to the last read
+    pub(crate) fn vcd_stall(&mut self) {
+        if let Some(vcd) = &mut self.vcd {
+            let (address, data) = vcd
+                .last
+                .map_or((self.registers.pc, 0), |l| (l.address, l.data));
+            let sample = Sample {
+                address,
+                data,
+                read: true,
+                sync: false,
+                irq: self.irq,
+                nmi: self.nmi,
+                rdy: self.rdy,
+            };
+            vcd.sample(self.total_cycles - 1, sample);
+        }
+    }
+}
@@ -0,0 +1,762 @@
+use crate::debugger::expr::Expr;
+use crate::debugger::*;
+use crate::*;
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/* A server for the VICE binary monitor protocol (API version 2), which IDE plugins and other
+6502 tools use to talk to a running emulator. Requests look like
+
+    0x02 version  body length (u32)  request id (u32)  command  body...
+
+and every response or event like
+
+    0x02 version  body length (u32)  type  error  request id (u32)  body...
+
+with everything little endian and events carrying the request id 0xFFFFFFFF.
+
+Supported are checkpoints (breakpoints and load/store watchpoints over an address range, with
+conditions written in our expression language), memory get/set, registers get/set, advancing by
+instructions, running until return, exit (resume), reset and the informational commands. Like
+VICE, any command stops the CPU, and it stays stopped until an exit command. Stops are reported
+with the checkpoint that was hit, the registers and a stopped (or JAM) event.
+
+ViceServer::handle() answers single requests and run_slice() keeps a resumed CPU going, serve()
+does both on a socket until the client quits:
+
+    rust_6502::vice::serve(&mut debugger, "127.0.0.1:6502")
+*/
+
+const STX: u8 = 0x02;
+const API_VERSION: u8 = 0x02;
+const EVENT: u32 = 0xFFFF_FFFF;
+
+// Cycles run between looking for requests while the CPU runs
+const SLICE_CYCLES: u64 = 20_000;
+
+pub mod command {
+    pub const MEMORY_GET: u8 = 0x01;
+    pub const MEMORY_SET: u8 = 0x02;
+    pub const CHECKPOINT_GET: u8 = 0x11;
+    pub const CHECKPOINT_SET: u8 = 0x12;
+    pub const CHECKPOINT_DELETE: u8 = 0x13;
+    pub const CHECKPOINT_LIST: u8 = 0x14;
+    pub const CHECKPOINT_TOGGLE: u8 = 0x15;
+    pub const CONDITION_SET: u8 = 0x22;
+    pub const REGISTERS_GET: u8 = 0x31;
+    pub const REGISTERS_SET: u8 = 0x32;
+    pub const ADVANCE_INSTRUCTIONS: u8 = 0x71;
+    pub const EXECUTE_UNTIL_RETURN: u8 = 0x73;
+    pub const PING: u8 = 0x81;
+    pub const BANKS_AVAILABLE: u8 = 0x82;
+    pub const REGISTERS_AVAILABLE: u8 = 0x83;
+    pub const VICE_INFO: u8 = 0x85;
+    pub const EXIT: u8 = 0xAA;
+    pub const QUIT: u8 = 0xBB;
+    pub const RESET: u8 = 0xCC;
+
+    // Event types
+    pub const JAM: u8 = 0x61;
+    pub const STOPPED: u8 = 0x62;
+    pub const RESUMED: u8 = 0x63;
+}
+
+pub mod error {
+    pub const OK: u8 = 0x00;
+    pub const NO_OBJECT: u8 = 0x01;
+    pub const INVALID_MEMSPACE: u8 = 0x02;
+    pub const BAD_LENGTH: u8 = 0x80;
+    pub const INVALID_PARAMETER: u8 = 0x81;
+    pub const UNSUPPORTED_VERSION: u8 = 0x82;
+    pub const UNSUPPORTED_COMMAND: u8 = 0x83;
+}
+
+// Checkpoint operations, which can be combined
+const OP_LOAD: u8 = 0x01;
+const OP_STORE: u8 = 0x02;
+const OP_EXEC: u8 = 0x04;
+
+// VICE's register ids, with the names and sizes handed out by registers available
+const REGISTERS: [(u8, &str, u8); 6] = [
+    (0x00, "A", 8),
+    (0x01, "X", 8),
+    (0x02, "Y", 8),
+    (0x03, "PC", 16),
+    (0x04, "SP", 8),
+    (0x05, "FL", 8),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub version: u8,
+    pub id: u32,
+    pub command: u8,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // Take a complete request off the front of 'buffer', if there is one yet
+    pub fn parse(buffer: &mut Vec<u8>) -> Option<Request> {
+        // Skip anything that can't be the start of a request
+        let start = buffer
+            .iter()
+            .position(|b| *b == STX)
+            .unwrap_or(buffer.len());
+        buffer.drain(..start);
+        if buffer.len() < 11 {
+            return None;
+        }
+
+        let len = u32::from_le_bytes(buffer[2..6].try_into().unwrap()) as usize;
+        if buffer.len() < 11 + len {
+            return None;
+        }
+
+        let request = Request {
+            version: buffer[1],
+            id: u32::from_le_bytes(buffer[6..10].try_into().unwrap()),
+            command: buffer[10],
+            body: buffer[11..11 + len].to_vec(),
+        };
+        buffer.drain(..11 + len);
+        Some(request)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![STX, self.version];
+        bytes.extend((self.body.len() as u32).to_le_bytes());
+        bytes.extend(self.id.to_le_bytes());
+        bytes.push(self.command);
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub kind: u8,
+    pub error: u8,
+    pub id: u32,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(kind: u8, id: u32, body: Vec<u8>) -> Self {
+        Response {
+            kind,
+            error: error::OK,
+            id,
+            body,
+        }
+    }
+
+    fn error(kind: u8, id: u32, error: u8) -> Self {
+        Response {
+            kind,
+            error,
+            id,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn is_event(&self) -> bool {
+        self.id == EVENT
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![STX, API_VERSION];
+        bytes.extend((self.body.len() as u32).to_le_bytes());
+        bytes.push(self.kind);
+        bytes.push(self.error);
+        bytes.extend(self.id.to_le_bytes());
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+// A VICE checkpoint, which may take several of the debugger's breakpoints
+#[derive(Clone, Debug)]
+struct Checkpoint {
+    start: u16,
+    end: u16,
+    stop: bool,
+    enabled: bool,
+    operation: u8,
+    temporary: bool,
+    condition: bool,
+    breakpoints: Vec<BreakpointId>,
+}
+
+// Reads the fields of a request body in order
+struct Body<'b>(&'b [u8]);
+
+impl Body<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let (first, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(*first)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn rest(&mut self) -> &[u8] {
+        std::mem::take(&mut self.0)
+    }
+}
+
+pub struct ViceServer<'d, 'a> {
+    pub debugger: &'d mut Debugger<'a>,
+    checkpoints: BTreeMap<u32, Checkpoint>,
+    next_checkpoint: u32,
+    running: bool,
+    resumed: bool,
+    goal: Goal, // Where a run stops, it's Goal::Continue unless it's for an advance or return
+    steps_over: u16, // Instructions still to step over after the one running
+    done: bool,
+}
+
+impl<'d, 'a> ViceServer<'d, 'a> {
+    pub fn new(debugger: &'d mut Debugger<'a>) -> Self {
+        ViceServer {
+            debugger,
+            checkpoints: BTreeMap::new(),
+            next_checkpoint: 1,
+            running: false,
+            resumed: false,
+            goal: Goal::Continue,
+            steps_over: 0,
+            done: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // The client sent a quit
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // Answer a request, along with any events it causes
+    pub fn handle(&mut self, request: &Request) -> Vec<Response> {
+        let mut responses = Vec::new();
+        if !(1..=API_VERSION).contains(&request.version) {
+            let error = Response::error(request.command, request.id, error::UNSUPPORTED_VERSION);
+            return vec![error];
+        }
+
+        // Any command stops a running CPU
+        if self.running && request.command != command::EXIT {
+            self.running = false;
+            self.steps_over = 0;
+            responses.push(self.registers_response(EVENT));
+            responses.push(self.pc_event(command::STOPPED));
+        }
+
+        let id = request.id;
+        let kind = match request.command {
+            command::CHECKPOINT_SET => command::CHECKPOINT_GET,
+            command::REGISTERS_SET => command::REGISTERS_GET,
+            command => command,
+        };
+        let mut body = Body(&request.body);
+
+        let result = match request.command {
+            command::MEMORY_GET => self.memory_get(&mut body),
+            command::MEMORY_SET => self.memory_set(&mut body),
+            command::CHECKPOINT_GET => body
+                .u32()
+                .ok_or(error::BAD_LENGTH)
+                .and_then(|number| self.checkpoint_info(number, false)),
+            command::CHECKPOINT_SET => self.checkpoint_set(&mut body),
+            command::CHECKPOINT_DELETE => self.checkpoint_delete(&mut body),
+            command::CHECKPOINT_LIST => {
+                let numbers: Vec<u32> = self.checkpoints.keys().copied().collect();
+                for number in &numbers {
+                    let info = self.checkpoint_info(*number, false).unwrap();
+                    responses.push(Response::new(command::CHECKPOINT_GET, id, info));
+                }
+                Ok((numbers.len() as u32).to_le_bytes().to_vec())
+            }
+            command::CHECKPOINT_TOGGLE => self.checkpoint_toggle(&mut body),
+            command::CONDITION_SET => self.condition_set(&mut body),
+            command::REGISTERS_GET => match body.u8() {
+                Some(0) => Ok(self.registers_response(id).body),
+                Some(_) => Err(error::INVALID_MEMSPACE),
+                None => Err(error::BAD_LENGTH),
+            },
+            command::REGISTERS_SET => self.registers_set(&mut body),
+            command::ADVANCE_INSTRUCTIONS => {
+                let (Some(over), Some(count)) = (body.u8(), body.u16()) else {
+                    return reply(responses, kind, id, Err(error::BAD_LENGTH));
+                };
+                responses = reply(responses, kind, id, Ok(Vec::new()));
+                match over {
+                    0 => self.advance(count, &mut responses),
+                    _ => {
+                        self.steps_over = count.max(1);
+                        self.step_over(&mut responses);
+                    }
+                }
+                return responses;
+            }
+            command::EXECUTE_UNTIL_RETURN => {
+                responses = reply(responses, kind, id, Ok(Vec::new()));
+                let goal = self.debugger.step_out_goal();
+                self.start(goal);
+                return responses;
+            }
+            command::PING => Ok(Vec::new()),
+            command::BANKS_AVAILABLE => {
+                // One bank, number 0 called "cpu"
+                let mut out = 1u16.to_le_bytes().to_vec();
+                out.extend([6, 0, 0, 3]);
+                out.extend(b"cpu");
+                Ok(out)
+            }
+            command::REGISTERS_AVAILABLE => {
+                let mut out = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+                for (reg, name, bits) in REGISTERS {
+                    out.extend([3 + name.len() as u8, reg, bits, name.len() as u8]);
+                    out.extend(name.bytes());
+                }
+                Ok(out)
+            }
+            command::VICE_INFO => Ok(vec![4, 3, 7, 0, 0, 4, 0, 0, 0, 0]),
+            command::EXIT => {
+                if !self.running {
+                    self.start(Goal::Continue);
+                    responses = reply(responses, kind, id, Ok(Vec::new()));
+                    responses.push(self.pc_event(command::RESUMED));
+                    return responses;
+                }
+                Ok(Vec::new())
+            }
+            command::QUIT => {
+                self.done = true;
+                Ok(Vec::new())
+            }
+            command::RESET => {
+                self.debugger.cpu.reset();
+                Ok(Vec::new())
+            }
+            _ => Err(error::UNSUPPORTED_COMMAND),
+        };
+        reply(responses, kind, id, result)
+    }
+
+    /* Keep a resumed CPU running for about 'cycles' cycles, returning the events for whatever
+    stopped it. Checkpoints that don't stop are reported and execution carries on. */
+    pub fn run_slice(&mut self, cycles: u64) -> Vec<Response> {
+        let mut responses = Vec::new();
+        if !self.running {
+            return responses;
+        }
+
+        let start = self.debugger.cpu.total_cycles();
+        while self.running {
+            let used = self.debugger.cpu.total_cycles() - start;
+            if used >= cycles {
+                break;
+            }
+
+            let resuming = std::mem::take(&mut self.resumed);
+            let stop = self
+                .debugger
+                .run_slice_to(&mut self.goal, cycles - used, resuming);
+            match stop {
+                Stop::CycleLimit => break,
+                Stop::Step if self.steps_over > 0 => self.step_over(&mut responses),
+                stop => self.stopped(stop, &mut responses),
+            }
+        }
+        responses
+    }
+
+    /* Step 'count' instructions and stop. A checkpoint that doesn't stop is reported and the
+    steps carry on, anything else stops them early. */
+    fn advance(&mut self, count: u16, responses: &mut Vec<Response>) {
+        for _ in 0..count.max(1) {
+            let stop = self.debugger.step();
+            if stop != Stop::Step && !self.checkpoint_hit(stop, responses) {
+                return self.stop(stop, responses);
+            }
+        }
+        self.stop(Stop::Step, responses);
+    }
+
+    /* The rest of the instructions to step over. A JSR (or an interrupt) is left running in
+    slices like a resumed CPU, which comes back here once it returned. */
+    fn step_over(&mut self, responses: &mut Vec<Response>) {
+        while self.steps_over > 0 {
+            self.steps_over -= 1;
+            match self.debugger.step_over_goal() {
+                Some(goal) => return self.start(goal),
+                None => {
+                    let stop = self.debugger.step();
+                    if stop != Stop::Step && !self.checkpoint_hit(stop, responses) {
+                        return self.stop(stop, responses);
+                    }
+                }
+            }
+        }
+        self.stop(Stop::Step, responses);
+    }
+
+    fn start(&mut self, goal: Goal) {
+        self.running = true;
+        self.resumed = true;
+        self.goal = goal;
+    }
+
+    // The events for a stop while running. A checkpoint that isn't meant to stop carries on.
+    fn stopped(&mut self, stop: Stop, responses: &mut Vec<Response>) {
+        match self.checkpoint_hit(stop, responses) {
+            true => self.resumed = true,
+            false => self.stop(stop, responses),
+        }
+    }
+
+    // Report the checkpoint a stop was for, if any, true if it's one that doesn't stop the CPU
+    fn checkpoint_hit(&mut self, stop: Stop, responses: &mut Vec<Response>) -> bool {
+        let hit = match stop {
+            Stop::Breakpoint { id, .. } | Stop::Watchpoint { id, .. } => self.checkpoint_of(id),
+            _ => None,
+        };
+        let Some(number) = hit else {
+            return false;
+        };
+
+        let info = self.checkpoint_info(number, true).unwrap();
+        responses.push(Response::new(command::CHECKPOINT_GET, EVENT, info));
+        let checkpoint = &self.checkpoints[&number];
+        let stops = checkpoint.stop;
+        if checkpoint.temporary {
+            self.delete(number);
+        }
+        !stops
+    }
+
+    fn stop(&mut self, stop: Stop, responses: &mut Vec<Response>) {
+        self.running = false;
+        self.steps_over = 0;
+        responses.push(self.registers_response(EVENT));
+        responses.push(match stop {
+            Stop::Halted => self.pc_event(command::JAM),
+            _ => self.pc_event(command::STOPPED),
+        });
+    }
+
+    fn checkpoint_of(&self, id: BreakpointId) -> Option<u32> {
+        self.checkpoints
+            .iter()
+            .find(|(_, c)| c.breakpoints.contains(&id))
+            .map(|(number, _)| *number)
+    }
+
+    fn pc_event(&self, kind: u8) -> Response {
+        let pc = self.debugger.cpu.registers.pc;
+        Response::new(kind, EVENT, pc.to_le_bytes().to_vec())
+    }
+
+    fn registers_response(&self, id: u32) -> Response {
+        let r = &self.debugger.cpu.registers;
+        let values = [
+            r.a as u16,
+            r.x as u16,
+            r.y as u16,
+            r.pc,
+            r.s as u16,
+            r.p.bits() as u16,
+        ];
+
+        let mut body = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+        for ((reg, _, _), value) in REGISTERS.iter().zip(values) {
+            body.extend([3, *reg]);
+            body.extend(value.to_le_bytes());
+        }
+        Response::new(command::REGISTERS_GET, id, body)
+    }
+
+    fn registers_set(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        match body.u8() {
+            Some(0) => {}
+            Some(_) => return Err(error::INVALID_MEMSPACE),
+            None => return Err(error::BAD_LENGTH),
+        }
+
+        let count = body.u16().ok_or(error::BAD_LENGTH)?;
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let size = body.u8().ok_or(error::BAD_LENGTH)?;
+            let item = body.bytes(size as usize).ok_or(error::BAD_LENGTH)?;
+            let [reg, lo, hi, ..] = *item else {
+                return Err(error::BAD_LENGTH);
+            };
+            if !REGISTERS.iter().any(|r| r.0 == reg) {
+                return Err(error::NO_OBJECT);
+            }
+            values.push((reg, u16::from_le_bytes([lo, hi])));
+        }
+
+        let r = &mut self.debugger.cpu.registers;
+        for (reg, value) in values {
+            match reg {
+                0x00 => r.a = value as u8,
+                0x01 => r.x = value as u8,
+                0x02 => r.y = value as u8,
+                0x03 => r.pc = value,
+                0x04 => r.s = value as u8,
+                _ => r.p = StatusFlags::from_bits_retain(value as u8),
+            }
+        }
+        Ok(self.registers_response(0).body)
+    }
+
+    // side effects, start, end (inclusive), memspace, bank
+    fn memory_range(body: &mut Body) -> Result<(u16, u16), u8> {
+        let (Some(_), Some(start), Some(end), Some(memspace), Some(_)) =
+            (body.u8(), body.u16(), body.u16(), body.u8(), body.u16())
+        else {
+            return Err(error::BAD_LENGTH);
+        };
+        match (memspace, start <= end) {
+            (0, true) => Ok((start, end)),
+            (0, false) => Err(error::INVALID_PARAMETER),
+            _ => Err(error::INVALID_MEMSPACE),
+        }
+    }
+
+    fn memory_get(&self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let (start, end) = Self::memory_range(body)?;
+        let cpu = &self.debugger.cpu;
+        let bytes: Option<Vec<u8>> = (start..=end).map(|a| cpu.peek(a as usize)).collect();
+        let bytes = bytes.ok_or(error::INVALID_MEMSPACE)?;
+
+        let mut out = (bytes.len() as u16).to_le_bytes().to_vec();
+        out.extend(bytes);
+        Ok(out)
+    }
+
+    fn memory_set(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let (start, end) = Self::memory_range(body)?;
+        let data = body.rest();
+        if data.len() != (end - start) as usize + 1 {
+            return Err(error::BAD_LENGTH);
+        }
+        for (i, byte) in data.iter().enumerate() {
+            let address = start.wrapping_add(i as u16);
+            self.debugger.cpu.poke(address as usize, *byte);
+        }
+        Ok(Vec::new())
+    }
+
+    // start, end, stop when hit, enabled, operation, temporary (and an optional memspace)
+    fn checkpoint_set(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let (Some(start), Some(end), Some(stop), Some(enabled), Some(operation), Some(temporary)) = (
+            body.u16(),
+            body.u16(),
+            body.u8(),
+            body.u8(),
+            body.u8(),
+            body.u8(),
+        ) else {
+            return Err(error::BAD_LENGTH);
+        };
+        if body.u8().unwrap_or(0) != 0 {
+            return Err(error::INVALID_MEMSPACE);
+        }
+        if start > end || operation & (OP_LOAD | OP_STORE | OP_EXEC) == 0 {
+            return Err(error::INVALID_PARAMETER);
+        }
+
+        let mut kinds = Vec::new();
+        if operation & OP_EXEC != 0 {
+            kinds.push(match start == end {
+                true => BreakKind::Exec(start),
+                false => BreakKind::ExecRange { start, end },
+            });
+        }
+        let watch = match (operation & OP_LOAD != 0, operation & OP_STORE != 0) {
+            (true, true) => Some(WatchKind::Access),
+            (true, false) => Some(WatchKind::Read),
+            (false, true) => Some(WatchKind::Write),
+            (false, false) => None,
+        };
+        if let Some(kind) = watch {
+            kinds.push(BreakKind::Watch { start, end, kind });
+        }
+
+        let breakpoints = kinds
+            .into_iter()
+            .map(|kind| {
+                let id = self.debugger.add(kind);
+                self.debugger.enable(id, enabled != 0);
+                id
+            })
+            .collect();
+
+        let number = self.next_checkpoint;
+        self.next_checkpoint += 1;
+        self.checkpoints.insert(
+            number,
+            Checkpoint {
+                start,
+                end,
+                stop: stop != 0,
+                enabled: enabled != 0,
+                operation,
+                temporary: temporary != 0,
+                condition: false,
+                breakpoints,
+            },
+        );
+        self.checkpoint_info(number, false)
+    }
+
+    fn checkpoint_delete(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let number = body.u32().ok_or(error::BAD_LENGTH)?;
+        match self.delete(number) {
+            true => Ok(Vec::new()),
+            false => Err(error::NO_OBJECT),
+        }
+    }
+
+    fn delete(&mut self, number: u32) -> bool {
+        let Some(checkpoint) = self.checkpoints.remove(&number) else {
+            return false;
+        };
+        for id in checkpoint.breakpoints {
+            self.debugger.remove(id);
+        }
+        true
+    }
+
+    fn checkpoint_toggle(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let (Some(number), Some(enabled)) = (body.u32(), body.u8()) else {
+            return Err(error::BAD_LENGTH);
+        };
+        let checkpoint = self.checkpoints.get_mut(&number).ok_or(error::NO_OBJECT)?;
+
+        checkpoint.enabled = enabled != 0;
+        for id in &checkpoint.breakpoints {
+            self.debugger.enable(*id, enabled != 0);
+        }
+        Ok(Vec::new())
+    }
+
+    fn condition_set(&mut self, body: &mut Body) -> Result<Vec<u8>, u8> {
+        let (Some(number), Some(len)) = (body.u32(), body.u8()) else {
+            return Err(error::BAD_LENGTH);
+        };
+        let text = body.bytes(len as usize).ok_or(error::BAD_LENGTH)?;
+        let text = String::from_utf8_lossy(text);
+        let checkpoint = self.checkpoints.get_mut(&number).ok_or(error::NO_OBJECT)?;
+
+        let condition = match text.trim().is_empty() {
+            true => None,
+            false => Some(Expr::parse(&text).map_err(|_| error::INVALID_PARAMETER)?),
+        };
+        checkpoint.condition = condition.is_some();
+        for id in &checkpoint.breakpoints {
+            self.debugger.set_condition(*id, condition.clone());
+        }
+        Ok(Vec::new())
+    }
+
+    /* number, currently hit, start, end, stop when hit, enabled, operation, temporary, hit
+    count, ignore count, has condition, memspace */
+    fn checkpoint_info(&self, number: u32, hit: bool) -> Result<Vec<u8>, u8> {
+        let checkpoint = self.checkpoints.get(&number).ok_or(error::NO_OBJECT)?;
+        let hits: u64 = checkpoint
+            .breakpoints
+            .iter()
+            .filter_map(|id| self.debugger.breakpoint(*id))
+            .map(|b| b.hits)
+            .sum();
+
+        let mut out = number.to_le_bytes().to_vec();
+        out.push(hit as u8);
+        out.extend(checkpoint.start.to_le_bytes());
+        out.extend(checkpoint.end.to_le_bytes());
+        out.extend([
+            checkpoint.stop as u8,
+            checkpoint.enabled as u8,
+            checkpoint.operation,
+            checkpoint.temporary as u8,
+        ]);
+        out.extend((hits as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend([checkpoint.condition as u8, 0]);
+        Ok(out)
+    }
+}
+
+fn reply(
+    mut responses: Vec<Response>,
+    kind: u8,
+    id: u32,
+    result: Result<Vec<u8>, u8>,
+) -> Vec<Response> {
+    responses.push(match result {
+        Ok(body) => Response::new(kind, id, body),
+        Err(error) => Response::error(kind, id, error),
+    });
+    responses
+}
+
+// Wait for a client to connect, then serve it until it quits or disconnects
+pub fn serve(debugger: &mut Debugger, address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    serve_stream(debugger, stream)
+}
+
+pub fn serve_stream(debugger: &mut Debugger, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut server = ViceServer::new(debugger);
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+
+    while !server.is_done() {
+        // Only wait for requests while the CPU is stopped
+        stream.set_nonblocking(server.is_running())?;
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => buffer.extend(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        stream.set_nonblocking(false)?;
+
+        let mut responses = Vec::new();
+        while let Some(request) = Request::parse(&mut buffer) {
+            responses.extend(server.handle(&request));
+        }
+        responses.extend(server.run_slice(SLICE_CYCLES));
+
+        for response in responses {
+            stream.write_all(&response.to_bytes())?;
+        }
+    }
+    Ok(())
+}
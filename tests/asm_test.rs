@@ -0,0 +1,65 @@
+use rust_6502::asm::*;
+use rust_6502::disasm::{self, Instruction};
+use rust_6502::symbols::SymbolTable;
+
+#[test]
+fn round_trip() {
+    // Every documented opcode survives being disassembled and assembled again
+    for opcode in (0..=0xFF).filter(|op| !disasm::is_illegal(*op)) {
+        let instr = Instruction::decode(0x0400, &[opcode, 0x34, 0x12]);
+        let text = instr.to_string();
+        assert_eq!(
+            assemble(&text, 0x0400, None),
+            Ok(instr.bytes()),
+            "{} (${:02X})",
+            text,
+            opcode
+        );
+    }
+}
+
+#[test]
+fn operands() {
+    let asm = |line: &str| assemble(line, 0x0400, None).unwrap();
+
+    assert_eq!(asm("lda $12"), [0xA5, 0x12]);
+    assert_eq!(asm("LDA $0012"), [0xA5, 0x12]); // Zero page whenever it fits
+    assert_eq!(asm("lda 300,x"), [0xBD, 0x2C, 0x01]);
+    assert_eq!(asm("ldx $12,y"), [0xB6, 0x12]);
+    assert_eq!(asm("lda $12,y"), [0xB9, 0x12, 0x00]); // No zero page,Y form for LDA
+    assert_eq!(asm("sta ( $20 ),y"), [0x91, 0x20]);
+    assert_eq!(asm("jmp ($FFFC)"), [0x6C, 0xFC, 0xFF]);
+    assert_eq!(asm("lsr"), [0x4A]);
+    assert_eq!(asm("ror a"), [0x6A]);
+    assert_eq!(asm("and #%1010"), [0x29, 0x0A]);
+    assert_eq!(asm("bne *"), [0xD0, 0xFE]);
+    assert_eq!(asm("bcc $0382"), [0x90, 0x80]);
+    assert_eq!(asm("brk"), [0x00]);
+    assert_eq!(asm("nop"), [0xEA]);
+    assert_eq!(asm("lax $12"), [0xA7, 0x12]);
+}
+
+#[test]
+fn symbols() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("screen", 0x0400);
+    symbols.insert("ptr", 0x00FB);
+
+    let asm = |line: &str| assemble(line, 0xC000, Some(&symbols)).unwrap();
+    assert_eq!(asm("sta screen+40,x"), [0x9D, 0x28, 0x04]);
+    assert_eq!(asm("lda (ptr),y"), [0xB1, 0xFB]);
+    assert_eq!(asm("ldx #>screen"), [0xA2, 0x04]);
+    assert_eq!(asm("ldy #<screen-1"), [0xA0, 0xFF]);
+}
+
+#[test]
+fn errors() {
+    let err = |line: &str| assemble(line, 0x0400, None).unwrap_err().to_string();
+
+    assert_eq!(err("foo $12"), "Unknown instruction 'foo'");
+    assert_eq!(err("lda ($1234),y"), "Invalid operand for LDA");
+    assert_eq!(err("jmp #$12"), "Invalid operand for JMP");
+    assert_eq!(err("bne $0500"), "Branch to $0500 is out of range");
+    assert_eq!(err("lda nowhere"), "Unknown symbol 'nowhere'");
+    assert_eq!(err("lda $12z"), "Bad value '$12z'");
+}
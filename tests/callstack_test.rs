@@ -0,0 +1,140 @@
+use rust_6502::callstack::*;
+use rust_6502::debugger::*;
+use rust_6502::memory::Ram;
+
+const PROGRAM: [u8; 7] = [
+    0x20, 0x10, 0x05, // $0500: JSR $0510
+    0xA9, 0x01, // $0503: LDA #$01
+    0xEA, // $0505: NOP
+    0xEA, // $0506: NOP
+];
+
+const RECURSIVE: [u8; 7] = [
+    0xCA, // $0510: DEX
+    0xF0, 0x03, // $0511: BEQ $0516
+    0x20, 0x10, 0x05, // $0513: JSR $0510
+    0x60, // $0516: RTS
+];
+
+fn debugger(ram: &Ram) -> Debugger<'static> {
+    ram.load(0x0500, &PROGRAM);
+    ram.load(0x0510, &RECURSIVE);
+
+    let mut debugger = Debugger::new(ram.cpu());
+    debugger.cpu.registers.pc = 0x0500;
+    debugger.cpu.registers.s = 0xFF;
+    debugger.cpu.registers.x = 3;
+    debugger.symbols.insert("main", 0x0500);
+    debugger.symbols.insert("recurse", 0x0510);
+    debugger
+}
+
+#[test]
+fn backtrace() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let id = dbg.add(BreakKind::Exec(0x0516));
+
+    assert_eq!(dbg.cont(None), Stop::Breakpoint { id, pc: 0x0516 });
+    let call_stack = dbg.cpu.call_stack().unwrap();
+    assert_eq!(call_stack.depth(), 3);
+
+    let frame = call_stack.frames()[0];
+    assert_eq!(frame.kind, FrameKind::Call);
+    assert_eq!(frame.caller, 0x0500);
+    assert_eq!(frame.target, 0x0510);
+    assert_eq!(frame.return_address, 0x0503);
+    assert_eq!(frame.sp, 0xFF);
+    assert_eq!(frame.cycle, 0);
+
+    let lines: Vec<_> = dbg.backtrace().lines().map(str::to_string).collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "#0  $0516  recurse+6");
+    assert!(lines[1].starts_with("#1  $0513  recurse+3        S:$FB"));
+    assert!(lines[3].starts_with("#3  $0500  main             S:$FF  cycle 0"));
+
+    // Balanced returns leave nothing behind
+    dbg.remove(id);
+    dbg.run_to(0x0505, None);
+    let call_stack = dbg.cpu.call_stack().unwrap();
+    assert_eq!(call_stack.depth(), 0);
+    assert!(call_stack.anomalies().is_empty());
+}
+
+#[test]
+fn interrupt_frames() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    ram.load(0x0600, &[0x40]); // RTI
+    ram.load(0xFFFA, &[0x00, 0x06]);
+
+    dbg.step();
+    dbg.cpu.set_nmi(true);
+    dbg.step();
+    assert_eq!(dbg.cpu.registers.pc, 0x0600);
+
+    let frames = dbg.cpu.call_stack().unwrap().frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[1].kind, FrameKind::Interrupt { vector: 0xFFFA });
+    assert_eq!(frames[1].caller, 0x0510);
+    assert_eq!(frames[1].return_address, 0x0510);
+    assert!(dbg.backtrace().lines().nth(1).unwrap().ends_with("[NMI]"));
+
+    dbg.step();
+    assert_eq!(dbg.cpu.registers.pc, 0x0510);
+    assert_eq!(dbg.cpu.call_stack().unwrap().depth(), 1);
+}
+
+#[test]
+fn unbalanced_returns() {
+    let ram = Ram::new();
+    ram.load(
+        0x0500,
+        &[
+            0xA9, 0x05, // $0500: LDA #$05
+            0x48, // $0502: PHA
+            0xA9, 0x0F, // $0503: LDA #$0F
+            0x48, // $0505: PHA
+            0x60, // $0506: RTS (to $0510)
+        ],
+    );
+    ram.load(
+        0x0510,
+        &[
+            0x20, 0x20, 0x05, // $0510: JSR $0520
+            0xEA, // $0513: NOP
+        ],
+    );
+    ram.load(
+        0x0520,
+        &[
+            0x68, // $0520: PLA
+            0x68, // $0521: PLA
+            0x4C, 0x13, 0x05, // $0524: JMP $0513
+        ],
+    );
+
+    let mut dbg = Debugger::new(ram.cpu());
+    dbg.cpu.registers.pc = 0x0500;
+    dbg.cpu.registers.s = 0xFF;
+    dbg.run_to(0x0513, None);
+
+    let call_stack = dbg.cpu.call_stack().unwrap();
+    assert_eq!(call_stack.depth(), 0);
+    assert!(matches!(
+        call_stack.anomalies()[0],
+        Anomaly::ComputedJump {
+            pc: 0x0506,
+            target: 0x0510,
+            ..
+        }
+    ));
+    assert!(matches!(
+        call_stack.anomalies()[1],
+        Anomaly::Unwound {
+            pc: 0x0521,
+            frames: 1,
+            ..
+        }
+    ));
+}
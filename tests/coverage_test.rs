@@ -0,0 +1,104 @@
+use rust_6502::coverage::*;
+use rust_6502::memory::Ram;
+use rust_6502::symbols::LineTable;
+
+const PROGRAM: [u8; 19] = [
+    0xA2, 0x00, // $0400: LDX #$00
+    0xBD, 0x20, 0x04, // $0402: LDA $0420,X
+    0x9D, 0x00, 0x03, // $0405: STA $0300,X
+    0xE8, // $0408: INX
+    0xE0, 0x02, // $0409: CPX #$02
+    0xD0, 0xF5, // $040B: BNE $0402
+    0x4C, 0x0D, 0x04, // $040D: JMP $040D
+    0xA9, 0x01, // $0410: LDA #$01 (never runs)
+    0x60, // $0412: RTS
+];
+
+// A table the program only reads half of
+const TABLE: [u8; 4] = [1, 2, 3, 4];
+
+fn load(ram: &Ram) {
+    ram.load(0x0400, &PROGRAM);
+    ram.load(0x0420, &TABLE);
+}
+
+fn lines() -> LineTable {
+    let mut lines = LineTable::new();
+    for (line, address, len) in [
+        (10, 0x0400, 2),
+        (11, 0x0402, 3),
+        (12, 0x0405, 3),
+        (13, 0x0408, 1),
+        (14, 0x0409, 2),
+        (15, 0x040B, 2),
+        (16, 0x040D, 3),
+        (20, 0x0410, 2),
+        (21, 0x0412, 1),
+    ] {
+        lines.insert("src/main.s", line, address, len);
+    }
+    lines.insert("src/data.s", 5, 0x0420, 4);
+    lines
+}
+
+fn run() -> Coverage {
+    let ram = Ram::new();
+    load(&ram);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.set_coverage(Some(Coverage::new()));
+    while cpu.registers.pc != 0x040D {
+        cpu.tick();
+    }
+    cpu.take_coverage().unwrap()
+}
+
+#[test]
+fn byte_usage() {
+    let coverage = run();
+    assert_eq!(coverage.usage(0x0400), Usage::OPCODE);
+    assert_eq!(coverage.usage(0x0401), Usage::OPERAND);
+    assert_eq!(coverage.usage(0x0421), Usage::READ);
+    assert_eq!(coverage.usage(0x0422), Usage::empty());
+    assert_eq!(coverage.executions(0x0402), 2);
+
+    // The taken branch reads the next opcode, but that's the branch's own bytes
+    assert_eq!(coverage.usage(0x040D), Usage::empty());
+
+    // STA abs,X always reads before it writes
+    assert_eq!(coverage.usage(0x0301), Usage::READ | Usage::WRITTEN);
+
+    assert_eq!(
+        coverage.range(0x0400, 0x0412),
+        RangeStats {
+            bytes: 19,
+            opcode: 6,
+            operand: 7,
+            read: 0,
+            written: 0,
+            untouched: 6,
+        }
+    );
+
+    let summary = coverage.summary(&[("code", 0x0400, 0x0412), ("table", 0x0420, 0x0423)]);
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("Range"));
+    assert_eq!(
+        lines[1],
+        "code                 $0400 $0412      19       6       7       0       0         6  68.42%"
+    );
+    assert!(lines[2].ends_with("2  50.00%"));
+}
+
+#[test]
+fn lcov() {
+    let coverage = run();
+    assert_eq!(
+        coverage.lcov(&lines()),
+        "TN:\nSF:src/data.s\nDA:5,1\nLF:1\nLH:1\nend_of_record\n\
+        TN:\nSF:src/main.s\nDA:10,1\nDA:11,2\nDA:12,2\nDA:13,2\nDA:14,2\nDA:15,2\nDA:16,0\n\
+        DA:20,0\nDA:21,0\nLF:9\nLH:6\nend_of_record\n"
+    );
+}
@@ -0,0 +1,281 @@
+use rust_6502::dap::{self, DapServer};
+use rust_6502::monitor::Monitor;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+const PROGRAM: [u8; 13] = [
+    0xA9, 0x42, // $0400: LDA #$42
+    0x20, 0x0A, 0x04, // $0402: JSR $040A
+    0xE8, // $0405: INX
+    0x4C, 0x05, 0x04, // $0406: JMP $0405
+    0xEA, // $0409: NOP
+    0x8D, 0x00, 0x02, // $040A: STA $0200
+];
+
+fn server(name: &str) -> DapServer {
+    let path = env::temp_dir().join(format!("rust_6502_dap_{}.bin", name));
+    let mut program = PROGRAM.to_vec();
+    program.push(0x60); // $040D: RTS
+    fs::write(&path, program).unwrap();
+
+    let mut server = DapServer::new(Monitor::new());
+    let responses = server.handle(&request(
+        "launch",
+        json!({"program": path, "address": "$0400", "stopOnEntry": true}),
+    ));
+    assert_eq!(responses[0]["success"], true);
+
+    let debugger = &mut server.monitor.debugger;
+    debugger.symbols.insert("main", 0x0400);
+    debugger.symbols.insert("store", 0x040A);
+    debugger.lines.insert("src/main.s", 10, 0x0400, 2);
+    debugger.lines.insert("src/main.s", 11, 0x0402, 3);
+    debugger.lines.insert("src/main.s", 13, 0x0405, 4);
+    debugger.lines.insert("src/main.s", 20, 0x040A, 3);
+    server
+}
+
+fn request(command: &str, arguments: Value) -> Value {
+    json!({"seq": 1, "type": "request", "command": command, "arguments": arguments})
+}
+
+fn body(server: &mut DapServer, command: &str, arguments: Value) -> Value {
+    let responses = server.handle(&request(command, arguments));
+    assert_eq!(responses[0]["success"], true, "{}", responses[0]);
+    responses[0]["body"].clone()
+}
+
+// The event names that came after the response
+fn events(responses: &[Value]) -> Vec<&str> {
+    responses[1..]
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut server = server("breakpoints");
+
+    let responses = server.handle(&request("initialize", json!({"adapterID": "6502"})));
+    assert_eq!(responses[0]["body"]["supportsDisassembleRequest"], true);
+    assert_eq!(events(&responses), ["initialized"]);
+
+    // Line 12 has no code, so the breakpoint moves to line 13
+    let breakpoints = body(
+        &mut server,
+        "setBreakpoints",
+        json!({"source": {"path": "/home/me/game/src/main.s"}, "breakpoints": [{"line": 12}, {"line": 30}]}),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    assert_eq!(breakpoints["breakpoints"][0]["line"], 13);
+    assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+
+    let responses = server.handle(&request("configurationDone", json!({})));
+    assert_eq!(responses[1]["body"]["reason"], "entry");
+
+    // Into the subroutine, where the stack trace has two frames
+    server.handle(&request("stepIn", json!({"threadId": 1})));
+    let responses = server.handle(&request("stepIn", json!({"threadId": 1})));
+    assert_eq!(responses[1]["body"]["reason"], "step");
+
+    let trace = body(&mut server, "stackTrace", json!({"threadId": 1}));
+    let frames = trace["stackFrames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["name"], "store");
+    assert_eq!(frames[0]["line"], 20);
+    assert_eq!(frames[0]["source"]["name"], "main.s");
+    assert_eq!(frames[1]["name"], "main+2");
+    assert_eq!(frames[1]["instructionPointerReference"], "0x0402");
+
+    // Continue runs into the breakpoint on line 13
+    let responses = server.handle(&request("continue", json!({"threadId": 1})));
+    assert!(server.is_running());
+    assert_eq!(events(&responses), Vec::<&str>::new());
+    let events = server.run_slice(1000);
+    assert_eq!(events[0]["body"]["reason"], "breakpoint");
+    assert_eq!(server.monitor.debugger.cpu.registers.pc, 0x0405);
+
+    // A data breakpoint on the byte STA wrote
+    let info = body(
+        &mut server,
+        "dataBreakpointInfo",
+        json!({"name": "store + $0200 - $040A"}),
+    );
+    assert_eq!(info["dataId"], "$0200");
+    body(
+        &mut server,
+        "setDataBreakpoints",
+        json!({"breakpoints": [{"dataId": "$0200", "accessType": "read"}]}),
+    );
+    let watched = body(&mut server, "variables", json!({"variablesReference": 3}));
+    assert_eq!(watched["variables"][0]["name"], "$0200");
+    assert_eq!(watched["variables"][0]["value"], "42");
+
+    // Nothing reads it, so the loop runs until paused
+    body(
+        &mut server,
+        "setBreakpoints",
+        json!({"source": {"path": "/home/me/game/src/main.s"}, "breakpoints": []}),
+    );
+    server.handle(&request("continue", json!({"threadId": 1})));
+    assert!(server.run_slice(1000).is_empty());
+    let responses = server.handle(&request("pause", json!({"threadId": 1})));
+    assert_eq!(responses[1]["body"]["reason"], "pause");
+    assert!(!server.is_running());
+}
+
+// Run slices of a few cycles until the CPU stops, returning the events
+fn run(server: &mut DapServer) -> Vec<Value> {
+    let mut events = Vec::new();
+    while server.is_running() {
+        events.extend(server.run_slice(2));
+    }
+    events
+}
+
+#[test]
+fn stepping_in_slices() {
+    let mut server = server("slices");
+    server.handle(&request("configurationDone", json!({})));
+    server.handle(&request("stepIn", json!({"threadId": 1})));
+
+    // Stepping over the JSR runs in slices, then stops like a step
+    let responses = server.handle(&request("next", json!({"threadId": 1})));
+    assert_eq!(events(&responses), Vec::<&str>::new());
+    assert!(server.is_running());
+    let events = run(&mut server);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["body"]["reason"], "step");
+    assert_eq!(server.monitor.debugger.cpu.registers.pc, 0x0405);
+
+    // And so does stepping out
+    for command in ["r pc=40a s=fd", ">1fe 04 04"] {
+        body(
+            &mut server,
+            "evaluate",
+            json!({"expression": command, "context": "repl"}),
+        );
+    }
+    server.handle(&request("stepOut", json!({"threadId": 1})));
+    assert!(server.is_running());
+    assert_eq!(run(&mut server)[0]["body"]["reason"], "step");
+    assert_eq!(server.monitor.debugger.cpu.registers.pc, 0x0405);
+
+    // A monitor command that runs the program can be paused
+    let result = body(
+        &mut server,
+        "evaluate",
+        json!({"expression": "g", "context": "repl"}),
+    );
+    assert_eq!(result["result"], "");
+    assert!(server.is_running());
+    assert!(server.run_slice(1000).is_empty());
+    let responses = server.handle(&request("pause", json!({"threadId": 1})));
+    assert_eq!(responses[1]["body"]["reason"], "pause");
+}
+
+#[test]
+fn variables_and_memory() {
+    let mut server = server("variables");
+    server.handle(&request("stepIn", json!({"threadId": 1})));
+
+    let registers = body(&mut server, "variables", json!({"variablesReference": 1}));
+    assert_eq!(
+        registers["variables"][0],
+        json!({"name": "A", "value": "$42", "variablesReference": 0})
+    );
+    assert_eq!(registers["variables"][4]["value"], "$0402");
+
+    let set = body(
+        &mut server,
+        "setVariable",
+        json!({"variablesReference": 1, "name": "X", "value": "$10"}),
+    );
+    assert_eq!(set["value"], "$10");
+    body(
+        &mut server,
+        "setVariable",
+        json!({"variablesReference": 2, "name": "C", "value": "1"}),
+    );
+    let flags = body(&mut server, "variables", json!({"variablesReference": 2}));
+    assert_eq!(
+        flags["variables"][6],
+        json!({"name": "C", "value": "1", "variablesReference": 0})
+    );
+
+    let result = body(
+        &mut server,
+        "evaluate",
+        json!({"expression": "X + 1", "context": "watch"}),
+    );
+    assert_eq!(result["result"], "$11 (17)");
+    let result = body(
+        &mut server,
+        "evaluate",
+        json!({"expression": "m 400 401", "context": "repl"}),
+    );
+    assert!(result["result"]
+        .as_str()
+        .unwrap()
+        .starts_with("0400  A9 42"));
+
+    let memory = body(
+        &mut server,
+        "readMemory",
+        json!({"memoryReference": "0x0400", "count": 5}),
+    );
+    assert_eq!(memory["data"], "qUIgCgQ=");
+
+    let disassembly = body(
+        &mut server,
+        "disassemble",
+        json!({"memoryReference": "0x0405", "instructionOffset": -2, "instructionCount": 3}),
+    );
+    let instructions = disassembly["instructions"].as_array().unwrap();
+    assert_eq!(instructions[0]["address"], "0x0400");
+    assert_eq!(instructions[0]["symbol"], "main");
+    assert_eq!(instructions[1]["instruction"], "JSR store");
+    assert_eq!(instructions[1]["instructionBytes"], "20 0A 04");
+    assert_eq!(instructions[2]["line"], 13);
+
+    let responses = server.handle(&request("attach", json!({})));
+    assert_eq!(responses[0]["success"], false);
+}
+
+fn message(value: Value) -> String {
+    let body = value.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[test]
+fn stdio_session() {
+    let input: String = [
+        request("initialize", json!({})),
+        request("threads", json!({})),
+        request("disconnect", json!({})),
+    ]
+    .into_iter()
+    .map(message)
+    .collect();
+
+    let mut server = DapServer::new(Monitor::new());
+    let mut output = Vec::new();
+    dap::serve(&mut server, Cursor::new(input), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let messages: Vec<Value> = output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|m| serde_json::from_str(m.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect();
+
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["seq"], 1);
+    assert_eq!(messages[1]["event"], "initialized");
+    assert_eq!(messages[2]["body"]["threads"][0]["name"], "6502");
+    assert_eq!(messages[3]["command"], "disconnect");
+    assert!(server.is_done());
+}
@@ -0,0 +1,294 @@
+use rust_6502::debugger::expr::*;
+use rust_6502::debugger::*;
+use rust_6502::memory::Ram;
+use rust_6502::*;
+
+const PROGRAM: [u8; 14] = [
+    0xA2, 0x00, // $0400: LDX #$00
+    0xE8, // $0402: INX
+    0x8E, 0x10, 0x02, // $0403: STX $0210
+    0xAD, 0x10, 0x02, // $0406: LDA $0210
+    0xE0, 0x03, // $0409: CPX #$03
+    0xD0, 0xF5, // $040B: BNE $0402
+    0x00, // $040D: BRK
+];
+
+fn debugger(ram: &Ram) -> Debugger<'static> {
+    ram.load(0x0400, &PROGRAM);
+    let mut debugger = Debugger::new(ram.cpu());
+    debugger.cpu.registers.pc = 0x0400;
+    debugger
+}
+
+#[test]
+fn exec_breakpoint() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let id = dbg.add(BreakKind::Exec(0x0409));
+
+    for x in 1..=3 {
+        assert_eq!(dbg.cont(None), Stop::Breakpoint { id, pc: 0x0409 });
+        assert_eq!(dbg.cpu.registers.x, x);
+    }
+    assert_eq!(dbg.breakpoint(id).unwrap().hits, 3);
+
+    // Once disabled it no longer stops, the BRK opcode breakpoint does
+    dbg.enable(id, false);
+    let brk = dbg.add(BreakKind::Opcode(OpcodeMatch::Opcode(0x00)));
+    assert_eq!(
+        dbg.cont(None),
+        Stop::Opcode {
+            id: brk,
+            pc: 0x040D,
+            opcode: 0x00
+        }
+    );
+}
+
+#[test]
+fn exec_breakpoint_without_peek() {
+    let ram = Ram::new();
+    ram.load(0x0400, &PROGRAM);
+    let (read, write) = (ram.clone(), ram.clone());
+    let cpu = Cpu6502::new(
+        Box::new(move |address| read.read(address as u16)),
+        Box::new(move |address, value| write.write(address as u16, value)),
+    );
+    let mut dbg = Debugger::new(cpu);
+    dbg.cpu.registers.pc = 0x0400;
+
+    // Opcode breakpoints can't see the opcode, but don't keep the PC breakpoint from stopping
+    dbg.add(BreakKind::Opcode(OpcodeMatch::Opcode(0xE0)));
+    let id = dbg.add(BreakKind::Exec(0x0409));
+    assert_eq!(dbg.cont(None), Stop::Breakpoint { id, pc: 0x0409 });
+}
+
+#[test]
+fn watchpoints() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let write = dbg.add(BreakKind::Watch {
+        start: 0x0210,
+        end: 0x021F,
+        kind: WatchKind::Write,
+    });
+
+    let stop = dbg.cont(None);
+    assert_eq!(
+        stop,
+        Stop::Watchpoint {
+            id: write,
+            access: BusAccess {
+                address: 0x0210,
+                value: 0x01,
+                kind: AccessKind::Write
+            }
+        }
+    );
+
+    // Stops after the instruction, so the PC is on the next one
+    assert_eq!(dbg.cpu.registers.pc, 0x0406);
+
+    dbg.remove(write);
+    let read = dbg.add_temporary(BreakKind::Watch {
+        start: 0x0210,
+        end: 0x0210,
+        kind: WatchKind::Read,
+    });
+    assert!(matches!(dbg.cont(None), Stop::Watchpoint { id, .. } if id == read));
+    assert!(dbg.breakpoint(read).is_none());
+}
+
+#[test]
+fn undocumented_opcode_breakpoint() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    ram.write(0x040D, 0xA7); // LAX $00 in place of the BRK
+
+    let id = dbg.add(BreakKind::Opcode(OpcodeMatch::Undocumented));
+    assert_eq!(
+        dbg.cont(Some(1000)),
+        Stop::Opcode {
+            id,
+            pc: 0x040D,
+            opcode: 0xA7
+        }
+    );
+}
+
+#[test]
+fn expressions() {
+    let ram = Ram::new();
+    let mut cpu = ram.cpu();
+    cpu.registers.a = 0xFF;
+    cpu.registers.p = StatusFlags::E | StatusFlags::C;
+    ram.write(0x00FE, 0x00);
+    ram.write(0x00FF, 0xC0);
+    ram.write(0xD012, 101);
+
+    let eval = |source: &str| Expr::parse(source).unwrap().eval(&cpu);
+    assert_eq!(eval("A == $FF && P.C"), Ok(1));
+    assert_eq!(eval("P.Z || X"), Ok(0));
+    assert_eq!(eval("[$D012] > 100"), Ok(1));
+    assert_eq!(eval("word[$FE] == $C000"), Ok(1));
+    assert_eq!(eval("(%1010 | 1) * 2 + 10 % 4"), Ok(24));
+    assert_eq!(eval("1 << 4 >= 16 & -1"), Ok(1));
+    assert_eq!(eval("!A + ~0"), Ok(-1));
+    assert_eq!(
+        eval("hits > 1"),
+        Err(EvalError::Unavailable(Register::Hits))
+    );
+    assert_eq!(
+        eval("foo + 1"),
+        Err(EvalError::UnknownSymbol("foo".to_string()))
+    );
+
+    let err = Expr::parse("A == (1 + 2").unwrap_err();
+    assert_eq!(err.position, 11);
+    assert!(Expr::parse("A ==").is_err());
+    assert!(Expr::parse("A B").is_err());
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+
+    let id = dbg.add(BreakKind::Exec(0x0409));
+    dbg.set_condition(id, Some(Expr::parse("[$0210] == 2").unwrap()));
+    let watch = dbg.add(BreakKind::Watch {
+        start: 0x0210,
+        end: 0x0210,
+        kind: WatchKind::Write,
+    });
+    dbg.set_condition(watch, Some(Expr::parse("hits == 3").unwrap()));
+
+    assert_eq!(dbg.cont(None), Stop::Breakpoint { id, pc: 0x0409 });
+    assert_eq!(dbg.cpu.registers.x, 2);
+
+    // Hit counts go up even when the condition doesn't hold
+    assert!(matches!(dbg.cont(None), Stop::Watchpoint { id, .. } if id == watch));
+    assert_eq!(ram.read(0x0210), 3);
+    assert_eq!(dbg.breakpoint(id).unwrap().hits, 2);
+}
+
+const SUBROUTINES: [u8; 7] = [
+    0x20, 0x10, 0x05, // $0500: JSR $0510
+    0xA9, 0x01, // $0503: LDA #$01
+    0xEA, // $0505: NOP
+    0xEA, // $0506: NOP
+];
+
+const RECURSIVE: [u8; 7] = [
+    0xCA, // $0510: DEX
+    0xF0, 0x03, // $0511: BEQ $0516
+    0x20, 0x10, 0x05, // $0513: JSR $0510
+    0x60, // $0516: RTS
+];
+
+fn call_debugger(ram: &Ram) -> Debugger<'static> {
+    ram.load(0x0500, &SUBROUTINES);
+    ram.load(0x0510, &RECURSIVE);
+
+    // NMI handler counting how often it ran
+    ram.load(0x0600, &[0xEE, 0x20, 0x02, 0x40]); // INC $0220, RTI
+    ram.load(0xFFFA, &[0x00, 0x06]);
+
+    let mut debugger = Debugger::new(ram.cpu());
+    debugger.cpu.registers.pc = 0x0500;
+    debugger.cpu.registers.s = 0xFF;
+    debugger.cpu.registers.x = 3;
+    debugger
+}
+
+#[test]
+fn step_over_and_out() {
+    let ram = Ram::new();
+    let mut dbg = call_debugger(&ram);
+
+    assert_eq!(dbg.run_to(0x0513, None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.x, 2);
+    assert_eq!(dbg.cpu.registers.s, 0xFD);
+
+    // The recursive calls return to $0516 as well, but deeper in the stack
+    assert_eq!(dbg.step_over(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0516);
+    assert_eq!(dbg.cpu.registers.x, 0);
+    assert_eq!(dbg.cpu.registers.s, 0xFD);
+
+    assert_eq!(dbg.step_out(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0503);
+    assert_eq!(dbg.cpu.registers.s, 0xFF);
+
+    // Not a JSR, so the same as a single step
+    assert_eq!(dbg.step_over(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0505);
+}
+
+#[test]
+fn step_over_in_slices() {
+    let ram = Ram::new();
+    let mut dbg = call_debugger(&ram);
+
+    // Bounded slices get to the same place as one unbounded step over
+    let mut goal = dbg.step_over_goal().unwrap();
+    let mut slices = 0;
+    let mut resuming = false;
+    let stop = loop {
+        match dbg.run_slice_to(&mut goal, 10, resuming) {
+            Stop::CycleLimit => slices += 1,
+            stop => break stop,
+        }
+        resuming = true;
+    };
+    assert_eq!(stop, Stop::Step);
+    assert!(slices > 1);
+    assert_eq!(dbg.cpu.registers.pc, 0x0503);
+    assert_eq!(dbg.cpu.registers.s, 0xFF);
+
+    // Not a JSR
+    assert!(dbg.step_over_goal().is_none());
+}
+
+#[test]
+fn step_over_stops_on_breakpoint() {
+    let ram = Ram::new();
+    let mut dbg = call_debugger(&ram);
+    let id = dbg.add(BreakKind::Exec(0x0516));
+
+    assert_eq!(dbg.step_over(None), Stop::Breakpoint { id, pc: 0x0516 });
+    assert_eq!(dbg.cpu.registers.s, 0xF9);
+
+    // Stepping out of the innermost call only goes back one level
+    assert_eq!(dbg.step_out(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0516);
+    assert_eq!(dbg.cpu.registers.s, 0xFB);
+}
+
+#[test]
+fn step_over_interrupt() {
+    let ram = Ram::new();
+    let mut dbg = call_debugger(&ram);
+
+    // The handler runs first, then the whole subroutine
+    dbg.cpu.set_nmi(true);
+    assert_eq!(dbg.step_over(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0503);
+    assert_eq!(dbg.cpu.registers.s, 0xFF);
+    assert_eq!(ram.read(0x0220), 1);
+
+    dbg.cpu.set_nmi(false);
+    dbg.cpu.set_nmi(true);
+    assert_eq!(dbg.step_over(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0505);
+    assert_eq!(ram.read(0x0220), 2);
+
+    // Stepping out of an interrupt handler returns to where it interrupted
+    dbg.cpu.set_nmi(false);
+    dbg.cpu.set_nmi(true);
+    dbg.step();
+    assert_eq!(dbg.cpu.registers.pc, 0x0600);
+    assert_eq!(dbg.step_out(None), Stop::Step);
+    assert_eq!(dbg.cpu.registers.pc, 0x0505);
+    assert_eq!(ram.read(0x0220), 3);
+}
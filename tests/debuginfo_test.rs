@@ -0,0 +1,160 @@
+use rust_6502::debuginfo::{is_debug_info_path, DebugInfo};
+use rust_6502::memory::Ram;
+use rust_6502::monitor::Monitor;
+use rust_6502::trace::*;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const PROGRAM: [u8; 14] = [
+    0xA9, 0x42, // $0400: LDA #$42
+    0x20, 0x0A, 0x04, // $0402: JSR $040A
+    0xE8, // $0405: INX
+    0x4C, 0x05, 0x04, // $0406: JMP $0405
+    0xEA, // $0409: NOP
+    0x8D, 0x00, 0x02, // $040A: STA $0200
+    0x60, // $040D: RTS
+];
+
+// What ld65 writes for the program above, trimmed down a bit
+const DBG: &str = "\
+version\tmajor=2,minor=0
+info\tcsym=0,file=2,lib=0,line=6,mod=1,scope=2,seg=1,span=5,sym=5,type=0
+file\tid=0,name=\"src/main.s\",size=312,mtime=0x5E2A1B3C,mod=0
+file\tid=1,name=\"src/macros.inc\",size=40,mtime=0x5E2A1B3C,mod=0
+line\tid=0,file=0,line=10,span=0
+line\tid=1,file=0,line=11,span=1
+line\tid=2,file=0,line=13,span=2+3
+line\tid=3,file=0,line=20,span=4
+line\tid=4,file=1,line=3,type=2,span=4
+line\tid=5,file=0,line=1
+mod\tid=0,name=\"main.o\",file=0
+seg\tid=0,name=\"CODE\",start=0x000400,size=0x000E,addrsize=absolute,type=ro,oname=\"game.bin\",ooffs=0
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=1
+span\tid=3,seg=0,start=6,size=3
+span\tid=4,seg=0,start=10,size=4
+scope\tid=0,name=\"\",mod=0,size=14,span=0+1
+scope\tid=1,name=\"store\",mod=0,type=scope,size=4,parent=0,span=4
+sym\tid=0,name=\"main\",addrsize=absolute,size=1,scope=0,def=0,ref=1,val=0x400,seg=0,type=lab
+sym\tid=1,name=\"@loop\",addrsize=absolute,scope=0,def=2,val=0x405,seg=0,type=lab,parent=0
+sym\tid=2,name=\"store\",addrsize=absolute,scope=0,def=3,val=0x40A,seg=0,type=lab
+sym\tid=3,name=\"done\",addrsize=absolute,scope=1,def=3,val=0x40D,seg=0,type=lab
+sym\tid=4,name=\"SCREEN\",addrsize=absolute,scope=0,def=1,val=0x200,type=equ
+";
+
+fn temp_file(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("rust_6502_debuginfo_{}", name));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn ca65_debug_info() {
+    let info = DebugInfo::parse_ca65(DBG).unwrap();
+
+    assert_eq!(info.symbols.len(), 4);
+    assert_eq!(info.symbols.address("main"), Some(0x0400));
+    assert_eq!(info.symbols.address("main@loop"), Some(0x0405));
+    assert_eq!(info.symbols.address("store::done"), Some(0x040D));
+    assert_eq!(info.symbols.address("SCREEN"), None);
+    assert_eq!(info.symbols.describe(0x0407).unwrap(), "main@loop+2");
+
+    // The line in main.s wins over the macro it expanded
+    assert_eq!(info.lines.location(0x040C), Some(("src/main.s", 20)));
+    assert_eq!(info.lines.location(0x0407), Some(("src/main.s", 13)));
+    assert_eq!(info.lines.describe(0x0403).unwrap(), "main.s:11");
+    assert_eq!(info.lines.address("main.s", 12), Some((0x0405, 13)));
+    assert_eq!(info.lines.location(0x040E), None);
+
+    let error = DebugInfo::parse_ca65("version\tmajor=2\nspan\tid=x,seg=0").unwrap_err();
+    assert_eq!(error.to_string(), "Line 2: Bad number 'x' for id");
+}
+
+#[test]
+fn vice_labels() {
+    let path = temp_file(
+        "labels.lbl",
+        "al C:0400 .main\nal 040a .store\nbreak 0400\n",
+    );
+    let info = DebugInfo::load(&path).unwrap();
+    assert_eq!(info.symbols.address("main"), Some(0x0400));
+    assert_eq!(info.symbols.address("store"), Some(0x040A));
+    assert!(info.lines.is_empty());
+
+    let error = DebugInfo::parse_vice_labels("al C:04G0 .main").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1: bad address '04G0'");
+
+    assert!(is_debug_info_path(&path));
+    assert!(is_debug_info_path("build/game.dbg"));
+    assert!(!is_debug_info_path("game.prg"));
+}
+
+#[test]
+fn monitor() {
+    let path = temp_file("game.dbg", DBG);
+    let mut monitor = Monitor::new();
+    monitor.ram.load(0x0400, &PROGRAM);
+
+    let loaded = monitor.execute(&format!("dbg {}", path.display())).unwrap();
+    assert_eq!(loaded, "Loaded 4 symbols and lines from 2 files\n");
+
+    assert_eq!(
+        monitor.execute("d 400 402").unwrap(),
+        "main:\n 0400  A9 42     LDA #$42        ; main.s:10\n 0402  20 0A 04  JSR store       ; main.s:11\n"
+    );
+
+    // Backtraces show where each frame is in the source
+    monitor.execute("r pc=main").unwrap();
+    monitor.execute("z 2").unwrap();
+    let backtrace = monitor.execute("bt").unwrap();
+    let lines: Vec<&str> = backtrace.lines().collect();
+    assert_eq!(lines[0], "#0  $040A  store  main.s:20");
+    assert!(lines[1].starts_with("#1  $0402  main+2"));
+    assert!(lines[1].ends_with("cycle 2  main.s:11"));
+
+    // Breakpoints by source line, listed with both
+    assert_eq!(monitor.execute("b main.s:12").unwrap(), "Breakpoint 1\n");
+    assert!(monitor
+        .execute("bl")
+        .unwrap()
+        .starts_with("  1  exec  $0405 main@loop (main.s:13)"));
+    assert!(monitor.execute("g").unwrap().contains(">0405"));
+    assert_eq!(
+        monitor.execute("b main.s:30").unwrap_err().to_string(),
+        "No code for main.s:30"
+    );
+}
+
+#[test]
+fn tracer() {
+    let ram = Ram::new();
+    ram.load(0x0400, &PROGRAM);
+    let lines = RefCell::new(Vec::new());
+
+    let mut cpu = ram.cpu();
+    let format = TraceFormat::Custom("{pc} {label} {source}".to_string());
+    let tracer = Tracer::new(
+        format,
+        Box::new(|line| lines.borrow_mut().push(line.to_string())),
+    );
+    cpu.set_tracer(tracer.with_debug_info(DebugInfo::parse_ca65(DBG).unwrap()));
+
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    drop(cpu);
+
+    assert_eq!(
+        lines.take(),
+        [
+            "0400 main main.s:10",
+            "0402 main+2 main.s:11",
+            "040A store main.s:20"
+        ]
+    );
+}
@@ -0,0 +1,182 @@
+use rust_6502::debugger::*;
+use rust_6502::gdb::{self, GdbStub};
+use rust_6502::memory::Ram;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const PROGRAM: [u8; 10] = [
+    0xA9, 0x42, // $0400: LDA #$42
+    0x8D, 0x00, 0x02, // $0402: STA $0200
+    0xE8, // $0405: INX
+    0x4C, 0x05, 0x04, // $0406: JMP $0405
+    0xEA, // $0409: NOP
+];
+
+fn debugger(ram: &Ram) -> Debugger<'static> {
+    ram.load(0x0400, &PROGRAM);
+    let mut debugger = Debugger::new(ram.cpu());
+    debugger.cpu.registers.pc = 0x0400;
+    debugger.cpu.registers.s = 0xFD;
+    debugger
+}
+
+fn never() -> bool {
+    false
+}
+
+#[test]
+fn registers_and_memory() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut stub = GdbStub::new(&mut dbg);
+    let mut send = |packet: &str| stub.handle(packet, never).unwrap();
+
+    assert_eq!(send("g"), "000000fd000004");
+    assert_eq!(send("P0=7f"), "OK");
+    assert_eq!(send("P5=1004"), "OK");
+    assert_eq!(send("p0"), "7f");
+    assert_eq!(send("p5"), "1004");
+    assert_eq!(send("p9"), "E01");
+    assert_eq!(send("G01020304a50004"), "OK");
+    assert_eq!(send("g"), "01020304a50004");
+
+    assert_eq!(send("m400,5"), "a9428d0002");
+    assert_eq!(send("M300,3:aabbcc"), "OK");
+    assert_eq!(send("m300,3"), "aabbcc");
+    assert_eq!(send("M300,2:aa"), "E01");
+    assert_eq!(send("mffff,2"), "E01");
+
+    assert!(send("qSupported:multiprocess+").contains("qXfer:features:read+"));
+    let xml = send("qXfer:features:read:target.xml:0,4000");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    assert!(send("qXfer:features:read:target.xml:0,10").starts_with('m'));
+    assert_eq!(send("qUnknown"), "");
+
+    // Anything not ASCII comes out of unescaping as U+FFFD
+    assert_eq!(send("\u{FFFD}"), "");
+    assert_eq!(send("é00"), "");
+
+    drop(stub);
+    assert_eq!(ram.read(0x0301), 0xBB);
+    assert_eq!(dbg.cpu.registers.pc, 0x0400);
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut stub = GdbStub::new(&mut dbg);
+
+    assert_eq!(stub.handle("s", never).unwrap(), "S05");
+    assert_eq!(stub.debugger.cpu.registers.pc, 0x0402);
+
+    assert_eq!(stub.handle("Z2,200,1", never).unwrap(), "OK");
+    assert_eq!(stub.handle("c", never).unwrap(), "T05watch:0200;");
+    assert_eq!(stub.handle("?", never).unwrap(), "T05watch:0200;");
+    assert_eq!(stub.handle("z2,200,1", never).unwrap(), "OK");
+
+    assert_eq!(stub.handle("Z0,406,1", never).unwrap(), "OK");
+    assert_eq!(stub.handle("vCont;c", never).unwrap(), "S05");
+    assert_eq!(stub.debugger.cpu.registers.pc, 0x0406);
+    assert_eq!(stub.debugger.breakpoints().len(), 1);
+
+    // The loop never ends, so only an interrupt stops it
+    assert_eq!(stub.handle("z0,406,1", never).unwrap(), "OK");
+    let mut polls = 0;
+    let reply = stub.handle("c", || {
+        polls += 1;
+        polls == 3
+    });
+    assert_eq!(reply.unwrap(), "S02");
+
+    assert_eq!(stub.handle("Z4,200,1", never).unwrap(), "OK");
+    assert_eq!(stub.handle("D", never).unwrap(), "OK");
+    assert!(stub.is_done());
+    assert!(stub.debugger.breakpoints().is_empty());
+}
+
+fn packet(data: &str) -> Vec<u8> {
+    let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, sum).into_bytes()
+}
+
+// Read a reply, skipping the '+' acks
+fn reply(stream: &mut TcpStream) -> String {
+    let mut data = Vec::new();
+    let mut byte = [0];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        match byte[0] {
+            b'+' | b'$' => continue,
+            b'#' => break,
+            b => data.push(b),
+        }
+    }
+    let mut checksum = [0; 2];
+    stream.read_exact(&mut checksum).unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+fn send(stream: &mut TcpStream, data: &str) -> String {
+    stream.write_all(&packet(data)).unwrap();
+    reply(stream)
+}
+
+#[test]
+fn tcp_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let ram = Ram::new();
+        let mut dbg = debugger(&ram);
+        let (stream, _) = listener.accept().unwrap();
+        gdb::serve_stream(&mut dbg, stream).unwrap();
+        dbg.cpu.registers.pc
+    });
+
+    let mut client = TcpStream::connect(address).unwrap();
+    assert_eq!(send(&mut client, "m400,2"), "a942");
+    assert_eq!(send(&mut client, "Z0,405,1"), "OK");
+    assert_eq!(send(&mut client, "c"), "S05");
+    assert_eq!(send(&mut client, "p5"), "0504");
+    assert_eq!(send(&mut client, "QStartNoAckMode"), "OK");
+    assert_eq!(send(&mut client, "z0,405,1"), "OK");
+
+    // Ctrl-C breaks into the endless loop
+    client.write_all(&packet("c")).unwrap();
+    client.write_all(&[0x03]).unwrap();
+    assert_eq!(reply(&mut client), "S02");
+
+    // A packet that comes in while running isn't lost
+    client.write_all(&packet("c")).unwrap();
+    client.write_all(&packet("m400,1")).unwrap();
+    client.write_all(&[0x03]).unwrap();
+    assert_eq!(reply(&mut client), "S02");
+    assert_eq!(reply(&mut client), "a9");
+
+    assert_eq!(send(&mut client, "D"), "OK");
+    let pc = server.join().unwrap();
+    assert!(pc == 0x0405 || pc == 0x0406);
+}
+
+#[test]
+fn disconnect_while_running() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let ram = Ram::new();
+        let mut dbg = debugger(&ram);
+        let (stream, _) = listener.accept().unwrap();
+        gdb::serve_stream(&mut dbg, stream)
+    });
+
+    // Nothing stops the endless loop but the debugger going away
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(&packet("c")).unwrap();
+    drop(client);
+    assert!(server.join().unwrap().is_ok());
+}
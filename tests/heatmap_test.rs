@@ -0,0 +1,72 @@
+use rust_6502::heatmap::*;
+use rust_6502::memory::Ram;
+
+const PROGRAM: [u8; 13] = [
+    0xA2, 0x00, // $0400: LDX #$00
+    0xE6, 0x10, // $0402: INC $10
+    0xAD, 0x00, 0xD0, // $0404: LDA $D000
+    0xCA, // $0407: DEX
+    0xD0, 0xF8, // $0408: BNE $0402
+    0x4C, 0x0A, 0x04, // $040A: JMP $040A
+];
+
+fn run() -> Heatmap {
+    let ram = Ram::new();
+    ram.load(0x0400, &PROGRAM);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.set_heatmap(Some(Heatmap::new()));
+    while cpu.registers.pc != 0x040A {
+        cpu.tick();
+    }
+    cpu.take_heatmap().unwrap()
+}
+
+#[test]
+fn counts() {
+    let heatmap = run();
+
+    // INC writes twice, the old value and then the new one
+    assert_eq!(heatmap.count(Channel::Read, 0x0010), 256);
+    assert_eq!(heatmap.count(Channel::Write, 0x0010), 512);
+    assert_eq!(heatmap.count(Channel::Read, 0xD000), 256);
+    assert_eq!(heatmap.count(Channel::Execute, 0x0400), 1);
+    assert_eq!(heatmap.count(Channel::Execute, 0x0406), 256);
+    assert_eq!(heatmap.count(Channel::Read, 0x040A), 0);
+    assert_eq!(heatmap.max(Channel::Write), 512);
+
+    let csv = heatmap.csv();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "address,reads,writes,executions");
+    assert_eq!(lines[1], "0010,256,512,0");
+    assert_eq!(lines[2], "0400,0,0,1");
+    assert_eq!(lines.last().unwrap(), &"D000,256,0,0");
+    assert_eq!(lines.len(), 1 + 1 + 10 + 1);
+}
+
+#[test]
+fn images() {
+    let heatmap = run();
+    let header = b"P5\n256 256\n255\n".len();
+
+    // The zero page is the top row, $D000 the start of row $D0
+    let pgm = heatmap.pgm(Channel::Read);
+    assert!(pgm.starts_with(b"P5\n256 256\n255\n"));
+    assert_eq!(pgm.len(), header + 0x10000);
+    assert_eq!(pgm[header + 0x0010], 255);
+    assert_eq!(pgm[header + 0xD000], 255);
+    assert_eq!(pgm[header + 0x0011], 0);
+
+    // Counts go by log, so running once still shows up
+    let pgm = heatmap.pgm(Channel::Execute);
+    assert_eq!(pgm[header + 0x0400], 32);
+    assert_eq!(pgm[header + 0x0402], 255);
+
+    let ppm = heatmap.ppm();
+    assert!(ppm.starts_with(b"P6\n256 256\n255\n"));
+    assert_eq!(ppm.len(), header + 0x10000 * 3);
+    let pixel = |address: usize| &ppm[header + address * 3..header + address * 3 + 3];
+    assert_eq!(pixel(0x0010), [255, 255, 0]);
+    assert_eq!(pixel(0xD000), [0, 255, 0]);
+    assert_eq!(pixel(0x0402), [0, 0, 255]);
+}
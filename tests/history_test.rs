@@ -0,0 +1,172 @@
+use rust_6502::debugger::*;
+use rust_6502::gdb::GdbStub;
+use rust_6502::history::History;
+use rust_6502::memory::Ram;
+use rust_6502::monitor::Monitor;
+use rust_6502::*;
+
+const PROGRAM: [u8; 20] = [
+    0xA2, 0x00, // $0400: LDX #$00
+    0xE8, // $0402: INX
+    0x8E, 0x00, 0x02, // $0403: STX $0200
+    0x8A, // $0406: TXA
+    0x9D, 0x00, 0x03, // $0407: STA $0300,X
+    0x20, 0x20, 0x04, // $040A: JSR $0420
+    0xE0, 0x10, // $040D: CPX #$10
+    0xD0, 0xF1, // $040F: BNE $0402
+    0x4C, 0x11, 0x04, // $0411: JMP $0411
+];
+
+const SUBROUTINE: [u8; 4] = [
+    0xEE, 0x10, 0x02, // $0420: INC $0210
+    0x60, // $0423: RTS
+];
+
+fn load(ram: &Ram) {
+    ram.load(0x0400, &PROGRAM);
+    ram.load(0x0420, &SUBROUTINE);
+}
+
+// What's compared when going back: the registers, cycle count and the memory the program uses
+fn state(cpu: &Cpu6502, ram: &Ram) -> (Registers, u64, Vec<u8>) {
+    (cpu.registers, cpu.total_cycles(), ram.dump(0x0200, 0x120))
+}
+
+#[test]
+fn step_back_and_seek() {
+    let ram = Ram::new();
+    load(&ram);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.set_call_tracking(true);
+    cpu.set_history(Some(History::new(100, 4)));
+
+    let mut states = vec![state(&cpu, &ram)];
+    while cpu.registers.pc != 0x0411 {
+        cpu.tick();
+        states.push(state(&cpu, &ram));
+    }
+    let present = states.last().unwrap().clone();
+
+    // Old history was dropped along with old snapshots, the rest steps back exactly
+    let history = cpu.history().unwrap();
+    let oldest = history.oldest_cycle().unwrap();
+    assert!(oldest > 0);
+    assert_eq!(
+        history.steps_back(),
+        states.len() - 1 - (states.iter().position(|s| s.1 == oldest).unwrap())
+    );
+    let mut back = states.iter().rev().skip(1);
+    while cpu.step_back() {
+        assert_eq!(state(&cpu, &ram), *back.next().unwrap());
+    }
+    assert_eq!(cpu.total_cycles(), oldest);
+
+    // Seeking lands in front of the instruction running at that cycle, both ways
+    let middle = &states[states.len() - 30];
+    assert_eq!(cpu.seek(middle.1 + 1), Some(middle.1));
+    assert_eq!(state(&cpu, &ram), *middle);
+    assert_eq!(cpu.seek(u64::MAX), Some(present.1));
+    assert_eq!(state(&cpu, &ram), present);
+    let early = &states.iter().find(|s| s.1 >= oldest + 10).unwrap();
+    assert_eq!(cpu.seek(early.1), Some(early.1));
+    assert_eq!(state(&cpu, &ram), **early);
+    assert_eq!(cpu.seek(0), None);
+
+    // Back to where $0200 was last written, which is the STX with X at $10
+    cpu.seek(u64::MAX);
+    let cycle = cpu.reverse_to_write(0x0200).unwrap();
+    assert_eq!(cpu.total_cycles(), cycle);
+    assert_eq!(cpu.registers.pc, 0x0403);
+    assert_eq!(cpu.registers.x, 0x10);
+    assert_eq!(ram.read(0x0200), 0x0F);
+    assert_eq!(cpu.call_stack().unwrap().depth(), 0);
+
+    // Going back into the subroutine brings its frame back
+    cpu.seek(cycle);
+    while cpu.registers.pc != 0x0423 {
+        cpu.step_back();
+    }
+    assert_eq!(cpu.call_stack().unwrap().depth(), 1);
+    assert_eq!(cpu.call_stack().unwrap().frames()[0].caller, 0x040A);
+
+    // Running from the past makes a new future
+    assert!(cpu.history().unwrap().steps_forward() > 0);
+    cpu.tick();
+    assert_eq!(cpu.history().unwrap().steps_forward(), 0);
+    assert_eq!(cpu.call_stack().unwrap().depth(), 0);
+}
+
+#[test]
+fn reverse_continue() {
+    let ram = Ram::new();
+    load(&ram);
+    let mut dbg = Debugger::new(ram.cpu());
+    dbg.cpu.registers.pc = 0x0400;
+    dbg.cpu.registers.s = 0xFF;
+    assert_eq!(dbg.step_back(), Stop::HistoryStart);
+
+    dbg.cpu.set_history(Some(History::default()));
+    dbg.run_to(0x0411, None);
+
+    // The INC in the subroutine is the last write to $0210
+    let watch = dbg.add(BreakKind::Watch {
+        start: 0x0210,
+        end: 0x0210,
+        kind: WatchKind::Write,
+    });
+    match dbg.reverse_cont() {
+        Stop::Watchpoint { id, access } => {
+            assert_eq!(id, watch);
+            assert_eq!(access.value, 0x10);
+        }
+        stop => panic!("Stopped with {:?}", stop),
+    }
+    assert_eq!(dbg.cpu.registers.pc, 0x0420);
+    assert_eq!(ram.read(0x0210), 0x0F);
+
+    dbg.remove(watch);
+    let id = dbg.add(BreakKind::Exec(0x0406));
+    assert_eq!(dbg.reverse_cont(), Stop::Breakpoint { id, pc: 0x0406 });
+    assert_eq!(dbg.cpu.registers.x, 0x10);
+
+    dbg.clear();
+    assert_eq!(dbg.reverse_cont(), Stop::HistoryStart);
+    assert_eq!(dbg.cpu.registers.pc, 0x0400);
+    assert_eq!(ram.read(0x0200), 0);
+
+    let mut stub = GdbStub::new(&mut dbg);
+    assert!(stub
+        .handle("qSupported", || false)
+        .unwrap()
+        .contains("ReverseContinue+"));
+    assert_eq!(stub.handle("bc", || false).unwrap(), "T05replaylog:begin;");
+    assert_eq!(stub.handle("s", || false).unwrap(), "S05");
+    assert_eq!(stub.handle("bs", || false).unwrap(), "S05");
+}
+
+#[test]
+fn monitor() {
+    let mut monitor = Monitor::new();
+    load(&monitor.ram);
+    assert_eq!(monitor.execute("hist").unwrap(), "History is off\n");
+    assert_eq!(
+        monitor.execute("hist on").unwrap(),
+        "History from cycle 0: 0 steps back, 0 forward\n"
+    );
+
+    monitor.execute("r pc=400").unwrap();
+    monitor.execute("u 411").unwrap();
+    assert!(monitor.execute("bw 200").unwrap().starts_with(">0403"));
+    assert!(monitor.execute("bz 2").unwrap().starts_with(">040F"));
+    assert!(monitor.execute("seek 2").unwrap().starts_with(">0402"));
+    assert_eq!(
+        monitor.execute("bw 2000").unwrap_err().to_string(),
+        "No write to $2000 in the history"
+    );
+    assert!(monitor
+        .execute("bz 5")
+        .unwrap()
+        .starts_with("Reached the start"));
+}
@@ -0,0 +1,169 @@
+use rust_6502::monitor::Monitor;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+// Run commands, returning the output of the last one
+fn run(monitor: &mut Monitor, commands: &[&str]) -> String {
+    let mut output = String::new();
+    for command in commands {
+        output = monitor.execute(command).unwrap();
+    }
+    output
+}
+
+fn program(monitor: &mut Monitor) {
+    run(
+        monitor,
+        &[
+            "a 0400 ldx #0",
+            "inx",
+            "stx $0210",
+            "cpx #3",
+            "bne $0402",
+            "brk",
+            "",
+        ],
+    );
+}
+
+#[test]
+fn assemble_and_disassemble() {
+    let mut monitor = Monitor::new();
+    assert_eq!(monitor.prompt(), ". ");
+    assert_eq!(
+        monitor.execute("a 400 lda #$01").unwrap(),
+        " 0400  A9 01     LDA #$01\n"
+    );
+    assert_eq!(monitor.prompt(), "A 0402 ");
+    monitor.execute("").unwrap();
+    assert_eq!(monitor.prompt(), ". ");
+
+    program(&mut monitor);
+    run(&mut monitor, &["sym loop 402", "b 40a"]);
+    assert_eq!(
+        run(&mut monitor, &["d 400 40a"]),
+        [
+            " 0400  A2 00     LDX #$00",
+            "loop:",
+            " 0402  E8        INX",
+            " 0403  8E 10 02  STX $0210",
+            " 0406  E0 03     CPX #$03",
+            " 0408  D0 F8     BNE loop",
+            "*040A  00        BRK",
+            ""
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn memory() {
+    let mut monitor = Monitor::new();
+    run(&mut monitor, &[">0200 48 49", "f 0202 0207 2e"]);
+    assert_eq!(
+        run(&mut monitor, &["m 200 20f"]),
+        "0200  48 49 2E 2E 2E 2E 2E 2E 00 00 00 00 00 00 00 00  HI......\
+         ........\n"
+    );
+
+    // Save and load it back elsewhere
+    let path = std::env::temp_dir().join("rust_6502_monitor_test.bin");
+    let path = path.to_str().unwrap();
+    assert_eq!(
+        run(&mut monitor, &[&format!("s {} 200 207", path)]),
+        "Saved $0200-$0207\n"
+    );
+    assert_eq!(fs::read(path).unwrap(), b"HI......");
+    assert_eq!(
+        run(&mut monitor, &[&format!("l {} 1000", path)]),
+        "Loaded $1000-$1007\n"
+    );
+    assert_eq!(monitor.ram.dump(0x1000, 2), b"HI");
+
+    // A full 64K image wraps around to end just before it starts, anything bigger doesn't fit
+    fs::write(path, vec![0xEA; 0x10000]).unwrap();
+    assert_eq!(
+        run(&mut monitor, &[&format!("l {} 1000", path)]),
+        "Loaded $1000-$0FFF\n"
+    );
+    fs::write(path, vec![0xEA; 0x10001]).unwrap();
+    assert_eq!(
+        monitor
+            .execute(&format!("l {} 0", path))
+            .unwrap_err()
+            .to_string(),
+        format!("{} is bigger than 64K", path)
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn run_and_break() {
+    let mut monitor = Monitor::new();
+    program(&mut monitor);
+
+    let output = run(&mut monitor, &["b 408 if x == 2", "g 400"]);
+    assert_eq!(
+        output,
+        "Breakpoint 1 at $0408\n\
+         >0408  D0 F8     BNE $0402\n\
+         PC:0408 A:00 X:02 Y:00 SP:FF P:A4 Nv-bdIzc CYC:21\n"
+    );
+    assert_eq!(
+        run(&mut monitor, &["bl"]),
+        "  1  exec  $0408  if x == 2  hits 2\n"
+    );
+
+    let output = run(&mut monitor, &["del 1", "w w 210", "g"]);
+    assert!(output.starts_with("Watchpoint 2: write $03 at $0210\n"));
+
+    let output = run(&mut monitor, &["r a=ff pc=0406 p=ff", "t 2"]);
+    assert_eq!(
+        output,
+        ">0406  E0 03     CPX #$03\n\
+         >0408  D0 F8     BNE $0402\n\
+         >040A  00        BRK\n\
+         PC:040A A:FF X:03 Y:00 SP:FF P:7F nV-BDIZC CYC:34\n"
+    );
+    assert_eq!(run(&mut monitor, &["p x * 2 + [$0210]"]), "$9 9 %1001\n");
+}
+
+#[test]
+fn interrupt() {
+    let mut monitor = Monitor::new();
+    run(
+        &mut monitor,
+        &["a 0400 jsr $0410", "brk", "", "a 0410 jmp $0410", ""],
+    );
+
+    // Neither ever gets anywhere, so they only stop once interrupted from elsewhere
+    for command in ["g 400", "n", "ret", "u 403"] {
+        let interrupt = monitor.interrupt_handle();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            interrupt.stop();
+        });
+        let output = run(&mut monitor, &["r pc=400 sp=ff", command]);
+        assert!(output.starts_with("Interrupted\n"), "{}", output);
+        stopper.join().unwrap();
+    }
+}
+
+#[test]
+fn errors() {
+    let mut monitor = Monitor::new();
+    let err = |monitor: &mut Monitor, command: &str| monitor.execute(command).unwrap_err().0;
+
+    assert_eq!(err(&mut monitor, "zap"), "Unknown command 'zap'");
+    assert_eq!(
+        err(&mut monitor, "a 400 lda (1)"),
+        "Invalid operand for LDA"
+    );
+    assert_eq!(err(&mut monitor, "r q=1"), "Unknown register 'q'");
+    assert_eq!(err(&mut monitor, "r a=100"), "'100' is more than a byte");
+    assert_eq!(err(&mut monitor, "d nowhere"), "Unknown symbol 'nowhere'");
+
+    run(&mut monitor, &["x"]);
+    assert!(monitor.is_done());
+}
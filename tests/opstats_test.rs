@@ -0,0 +1,57 @@
+use rust_6502::memory::Ram;
+use rust_6502::opstats::*;
+use rust_6502::AddrMode;
+
+const PROGRAM: [u8; 16] = [
+    0xA2, 0x02, // $04F0: LDX #$02
+    0xBD, 0xFF, 0x02, // $04F2: LDA $02FF,X (always crosses into $0300)
+    0xCA, // $04F5: DEX
+    0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, // $04F6: NOP x8
+    0xD0, 0xF2, // $04FE: BNE $04F2 (taken from the next page)
+];
+
+const END: [u8; 4] = [
+    0x1A, // $0500: NOP (undocumented)
+    0x4C, 0x01, 0x05, // $0501: JMP $0501
+];
+
+fn load(ram: &Ram) {
+    ram.load(0x04F0, &PROGRAM);
+    ram.load(0x0500, &END);
+}
+
+#[test]
+fn opcodes_and_modes() {
+    let ram = Ram::new();
+    load(&ram);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x04F0;
+    cpu.set_opcode_histogram(Some(OpcodeHistogram::new()));
+    while cpu.registers.pc != 0x0501 {
+        cpu.tick();
+    }
+    let histogram = cpu.opcode_histogram().unwrap();
+
+    let stats = |count, cycles, page_crossings| OpcodeStats {
+        count,
+        cycles,
+        page_crossings,
+    };
+    assert_eq!(histogram.opcode(0xBD), stats(2, 10, 2));
+    assert_eq!(histogram.opcode(0xD0), stats(2, 4 + 2, 1));
+    assert_eq!(histogram.opcode(0xEA), stats(16, 32, 0));
+    assert_eq!(histogram.opcode(0x4C), stats(0, 0, 0));
+    assert_eq!(histogram.total(), stats(24, 2 + 10 + 4 + 32 + 6 + 2, 3));
+
+    assert_eq!(histogram.used()[0].0, 0xEA);
+    assert_eq!(histogram.illegal(), [(0x1A, stats(1, 2, 0))]);
+    assert_eq!(histogram.modes()[0], (AddrMode::IMP0, stats(19, 38, 0)));
+    assert_eq!(histogram.modes().len(), 4);
+
+    let report = histogram.report();
+    assert!(
+        report.contains("\n$BD     LDA   ABSX           2   8.33%         10  17.86%        2\n")
+    );
+    assert!(report.contains("\n$1A    *NOP   IMP0           1"));
+    assert!(report.ends_with("6 of 256 opcodes used, 1 of them undocumented ($1A)\n"));
+}
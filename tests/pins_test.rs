@@ -0,0 +1,216 @@
+use rust_6502::memory::Ram;
+use rust_6502::pins::*;
+use rust_6502::*;
+
+const PROGRAM: [u8; 12] = [
+    0xA2, 0x05, // $0400: LDX #$05
+    0x20, 0x10, 0x04, // $0402: JSR $0410
+    0xCA, // $0405: DEX
+    0xD0, 0xFA, // $0406: BNE $0402
+    0x58, // $0408: CLI
+    0x4C, 0x09, 0x04, // $0409: JMP $0409
+];
+
+const SUBROUTINE: [u8; 8] = [
+    0xBD, 0xFE, 0x02, // $0410: LDA $02FE,X
+    0x48, // $0413: PHA
+    0x68, // $0414: PLA
+    0x91, 0x20, // $0415: STA ($20),Y
+    0x60, // $0417: RTS
+];
+
+fn memory() -> Vec<u8> {
+    let mut memory = vec![0; 0x10000];
+    memory[0x0400..0x0400 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    memory[0x0410..0x0410 + SUBROUTINE.len()].copy_from_slice(&SUBROUTINE);
+    memory[0x0020..0x0022].copy_from_slice(&[0x00, 0x03]);
+    memory[0x0300] = 0x77;
+    memory[0xFFFA..].copy_from_slice(&[0x00, 0x06, 0x00, 0x04, 0x00, 0x05]);
+    memory[0x0500] = 0x40; // RTI
+    memory[0x0600] = 0x40;
+    memory
+}
+
+// Both halves of a cycle against memory, returning what was on the bus while PHI2 was high
+fn cycle(cpu: &mut PinCpu, memory: &mut [u8], inputs: &mut PinInputs) -> PinOutputs {
+    let low = cpu.half_cycle(inputs);
+    assert!(!low.phi2);
+    assert_eq!(low.data, None);
+    inputs.data = memory[low.address as usize];
+
+    let high = cpu.half_cycle(inputs);
+    assert!(high.phi2);
+    assert_eq!(
+        (high.address, high.read, high.sync),
+        (low.address, low.read, low.sync)
+    );
+    if let Some(value) = high.data {
+        memory[high.address as usize] = value;
+    }
+    high
+}
+
+// The addresses of some cycles, with R or W and an S when SYNC is high
+fn cycles(cpu: &mut PinCpu, memory: &mut [u8], inputs: &mut PinInputs, n: usize) -> Vec<String> {
+    (0..n)
+        .map(|_| {
+            let out = cycle(cpu, memory, inputs);
+            let kind = match out.read {
+                true => "R",
+                false => "W",
+            };
+            let sync = match out.sync {
+                true => "S",
+                false => "",
+            };
+            format!("{:04X}{}{}", out.address, kind, sync)
+        })
+        .collect()
+}
+
+#[test]
+fn reset_sequence() {
+    let mut memory = memory();
+    let mut cpu = PinCpu::new();
+    let mut inputs = PinInputs::default();
+
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 8),
+        ["0000R", "0000R", "0100R", "01FFR", "01FER", "FFFCR", "FFFDR", "0400RS"]
+    );
+    assert_eq!(cpu.registers().pc, 0x0400);
+    assert_eq!(cpu.registers().s, 0xFD);
+    assert_eq!(cpu.registers().p, StatusFlags::E | StatusFlags::I);
+    assert_eq!(cpu.cycles(), 8);
+
+    // Held in reset it only reads, then starts over
+    cycles(&mut cpu, &mut memory, &mut inputs, 5);
+    inputs.res = true;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 2),
+        ["0402R", "0402R"]
+    );
+    inputs.res = false;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 8),
+        ["0402R", "0402R", "01FDR", "01FCR", "01FBR", "FFFCR", "FFFDR", "0400RS"]
+    );
+}
+
+#[test]
+fn same_as_cpu() {
+    let ram = Ram::new();
+    ram.load(0, &memory());
+    let mut cpu = ram.cpu();
+    cpu.reset();
+    cpu.registers.s = 0xFD;
+    cpu.set_access_log(true);
+    let mut expected = Vec::new();
+    while expected.len() < 300 {
+        cpu.tick();
+        expected.extend(cpu.last_accesses().iter().map(|access| {
+            (
+                access.address,
+                access.value,
+                access.kind != AccessKind::Write,
+                access.kind == AccessKind::Fetch,
+            )
+        }));
+    }
+
+    let mut memory = memory();
+    let mut pins = PinCpu::new();
+    let mut inputs = PinInputs::default();
+    cycles(&mut pins, &mut memory, &mut inputs, 7);
+    for (i, expected) in expected.into_iter().enumerate() {
+        let out = cycle(&mut pins, &mut memory, &mut inputs);
+        let value = out.data.unwrap_or(inputs.data);
+        assert_eq!(
+            (out.address, value, out.read, out.sync),
+            expected,
+            "cycle {}",
+            i
+        );
+    }
+    assert_eq!(memory, ram.dump(0, 0x10000));
+}
+
+#[test]
+fn rdy() {
+    let mut memory = memory();
+    let mut cpu = PinCpu::new();
+    let mut inputs = PinInputs::default();
+    cycles(&mut cpu, &mut memory, &mut inputs, 7);
+
+    // A read waits for as long as RDY is low
+    assert_eq!(cycles(&mut cpu, &mut memory, &mut inputs, 1), ["0400RS"]);
+    inputs.rdy = false;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 3),
+        ["0401R", "0401R", "0401R"]
+    );
+    inputs.rdy = true;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 2),
+        ["0401R", "0402RS"]
+    );
+    assert_eq!(cpu.cycles(), 13);
+
+    // But a write goes ahead
+    cycles(&mut cpu, &mut memory, &mut inputs, 2);
+    inputs.rdy = false;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 4),
+        ["01FDW", "01FCW", "0404R", "0404R"]
+    );
+    assert_eq!(memory[0x01FD], 0x04);
+    assert_eq!(memory[0x01FC], 0x04);
+}
+
+#[test]
+fn interrupts() {
+    let mut memory = memory();
+    let mut cpu = PinCpu::new();
+    let mut inputs = PinInputs::default();
+    cycles(&mut cpu, &mut memory, &mut inputs, 7);
+    while cpu.registers().pc != 0x0409 {
+        cycle(&mut cpu, &mut memory, &mut inputs);
+    }
+
+    // IRQ is taken at the next instruction, here it's asserted after the JMP's opcode fetch
+    inputs.irq = true;
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 9),
+        ["040AR", "040BR", "0409RS", "0409R", "01FDW", "01FCW", "01FBW", "FFFER", "FFFFR"]
+    );
+    inputs.irq = false;
+    assert_eq!(memory[0x01FB], 0x22); // E and Z, B is clear for an interrupt
+
+    // NMI is taken even though a pulse was over by the time the RTI finished
+    assert_eq!(cycles(&mut cpu, &mut memory, &mut inputs, 1), ["0500RS"]);
+    inputs.nmi = true;
+    cycles(&mut cpu, &mut memory, &mut inputs, 1);
+    inputs.nmi = false;
+    cycles(&mut cpu, &mut memory, &mut inputs, 4);
+    assert_eq!(
+        cycles(&mut cpu, &mut memory, &mut inputs, 9),
+        ["0409RS", "0409R", "01FDW", "01FCW", "01FBW", "FFFAR", "FFFBR", "0600RS", "0601R"]
+    );
+}
+
+#[test]
+fn set_overflow() {
+    let mut memory = memory();
+    let mut cpu = PinCpu::new();
+    let mut inputs = PinInputs::default();
+    cycles(&mut cpu, &mut memory, &mut inputs, 8);
+    assert!(!cpu.registers().p.contains(StatusFlags::V));
+
+    // Set in front of the next instruction
+    inputs.so = true;
+    cycles(&mut cpu, &mut memory, &mut inputs, 1);
+    assert!(!cpu.registers().p.contains(StatusFlags::V));
+    cycles(&mut cpu, &mut memory, &mut inputs, 1);
+    assert_eq!(cpu.registers().pc, 0x0402);
+    assert!(cpu.registers().p.contains(StatusFlags::V));
+}
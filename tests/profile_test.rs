@@ -0,0 +1,198 @@
+use rust_6502::memory::Ram;
+use rust_6502::profile::*;
+use rust_6502::symbols::SymbolTable;
+use serde_json::Value;
+
+const PROGRAM: [u8; 13] = [
+    0xA2, 0x00, // $0400: LDX #$00
+    0x20, 0x20, 0x04, // $0402: JSR $0420
+    0xE8, // $0405: INX
+    0xE0, 0x04, // $0406: CPX #$04
+    0xD0, 0xF8, // $0408: BNE $0402
+    0x4C, 0x0A, 0x04, // $040A: JMP $040A
+];
+
+const SUBROUTINES: [u8; 9] = [
+    0xBD, 0xFE, 0x02, // $0420: LDA $02FE,X (crosses a page from X=2 on)
+    0x20, 0x30, 0x04, // $0423: JSR $0430
+    0x60, // $0426: RTS
+    0x00, 0x00, // Padding
+];
+
+fn load(ram: &Ram) {
+    ram.load(0x0400, &PROGRAM);
+    ram.load(0x0420, &SUBROUTINES);
+    ram.load(0x0430, &[0xEA, 0x60]); // $0430: NOP, RTS
+}
+
+fn symbols() -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("main", 0x0400);
+    symbols.insert("draw", 0x0420);
+    symbols.insert("plot", 0x0430);
+    symbols
+}
+
+fn profile(max_spans: usize) -> (Ram, Profiler) {
+    let ram = Ram::new();
+    load(&ram);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.set_profiler(Some(Profiler::new().with_timeline(max_spans)));
+    while cpu.registers.pc != 0x040A {
+        cpu.tick();
+    }
+
+    let profiler = cpu.take_profiler().unwrap();
+    (ram, profiler)
+}
+
+#[test]
+fn address_and_subroutine_cycles() {
+    let (_, profiler) = profile(0);
+
+    // Page crossings and taken branches cost their extra cycle
+    let stats = |count, cycles| AddressStats { count, cycles };
+    assert_eq!(profiler.address(0x0400), stats(1, 2));
+    assert_eq!(profiler.address(0x0420), stats(4, 4 + 4 + 5 + 5));
+    assert_eq!(profiler.address(0x0408), stats(4, 3 + 3 + 3 + 2));
+    assert_eq!(profiler.address(0x040A), stats(0, 0));
+    assert_eq!(profiler.total_cycles(), 151);
+    assert_eq!(profiler.outside_cycles(), 2 + 24 + 8 + 8 + 11);
+
+    assert_eq!(
+        profiler.subroutines(),
+        [
+            SubroutineStats {
+                address: 0x0420,
+                calls: 4,
+                self_cycles: 18 + 24 + 24,
+                total_cycles: 18 + 24 + 24 + 32,
+            },
+            SubroutineStats {
+                address: 0x0430,
+                calls: 4,
+                self_cycles: 32,
+                total_cycles: 32,
+            },
+        ]
+    );
+}
+
+#[test]
+fn listing() {
+    let (ram, profiler) = profile(0);
+    let symbols = symbols();
+
+    let listing = profiler.listing(|a| ram.read(a), Some(&symbols));
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 11);
+    assert_eq!(
+        lines[1],
+        "        24  15.89%          4  $0402  main+2            20 20 04  JSR draw"
+    );
+    assert!(lines[5].ends_with("$0420  draw              BD FE 02  LDA $02FE,X"));
+    assert!(lines[10].ends_with("$0400  main              A2 00     LDX #$00"));
+
+    let report = profiler.subroutine_report(Some(&symbols));
+    assert_eq!(
+        report.lines().nth(1).unwrap(),
+        "        98  64.90%         66  43.71%        4  $0420  draw"
+    );
+}
+
+#[test]
+fn flamegraph_and_timeline() {
+    let (_, profiler) = profile(100);
+    let symbols = symbols();
+    assert_eq!(
+        profiler.folded_stacks(Some(&symbols)),
+        "[top] 53\n[top];draw 66\n[top];draw;plot 32\n"
+    );
+    assert!(profiler
+        .folded_stacks(None)
+        .contains("[top];$0420;$0430 32\n"));
+
+    // Each call spans from its JSR to the end of its RTS
+    let spans = profiler.spans();
+    assert_eq!(spans.len(), 8);
+    assert_eq!(
+        (spans[0].address, spans[0].start, spans[0].end),
+        (0x0420, 2, 32)
+    );
+    assert_eq!((spans[1].address, spans[1].depth), (0x0430, 1));
+    assert_eq!((spans[1].start, spans[1].end), (12, 26));
+    assert_eq!(spans[1].caller, 0x0423);
+
+    let trace: Value = serde_json::from_str(&profiler.chrome_trace(Some(&symbols))).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 8);
+    assert_eq!(events[1]["name"], "plot");
+    assert_eq!(events[1]["ph"], "X");
+    assert_eq!(
+        (&events[1]["ts"], &events[1]["dur"]),
+        (&12.into(), &14.into())
+    );
+    assert_eq!(events[1]["args"]["caller"], "$0423");
+
+    let (_, profiler) = profile(3);
+    assert_eq!(profiler.spans().len(), 3);
+    assert_eq!(profiler.dropped_spans(), 5);
+}
+
+#[test]
+fn unfinished_calls() {
+    let ram = Ram::new();
+    load(&ram);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.set_profiler(Some(Profiler::new().with_timeline(100)));
+    for _ in 0..4 {
+        cpu.tick();
+    }
+
+    // Still in both subroutines, so they end where profiling got to
+    let spans = cpu.profiler().unwrap().spans();
+    assert_eq!(spans.len(), 2);
+    assert_eq!((spans[0].start, spans[0].end), (2, 18));
+    assert_eq!((spans[1].start, spans[1].end), (12, 18));
+    assert!(cpu
+        .profiler()
+        .unwrap()
+        .folded_stacks(None)
+        .ends_with(";$0420 10\n"));
+}
+
+#[test]
+fn interrupts() {
+    let ram = Ram::new();
+    ram.load(0x0400, &[0xEA, 0x4C, 0x00, 0x04]); // NOP, JMP $0400
+    ram.load(0x0500, &[0x40]); // RTI
+    ram.load(0xFFFE, &[0x00, 0x05]);
+
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.set_profiler(Some(Profiler::new()));
+    cpu.tick();
+    cpu.set_irq(true);
+    cpu.tick();
+    cpu.set_irq(false);
+    cpu.tick();
+
+    let profiler = cpu.profiler().unwrap();
+    assert_eq!(profiler.interrupt_cycles(), 7);
+    assert_eq!(profiler.outside_cycles(), 2);
+    assert_eq!(
+        profiler.subroutine(0x0500),
+        Some(SubroutineStats {
+            address: 0x0500,
+            calls: 1,
+            self_cycles: 6,
+            total_cycles: 6,
+        })
+    );
+    assert_eq!(profiler.total_cycles(), 15);
+}
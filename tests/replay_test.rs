@@ -0,0 +1,150 @@
+use rust_6502::memory::Ram;
+use rust_6502::replay::*;
+use rust_6502::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+const PROGRAM: [u8; 13] = [
+    0x58, // $0400: CLI
+    0xAD, 0x00, 0xD0, // $0401: LDA $D000
+    0x9D, 0x00, 0x03, // $0404: STA $0300,X
+    0xE8, // $0407: INX
+    0xD0, 0xF7, // $0408: BNE $0401
+    0x4C, 0x0A, 0x04, // $040A: JMP $040A
+];
+
+const HANDLER: [u8; 7] = [
+    0xEE, 0x10, 0x02, // $0500: INC $0210
+    0xAD, 0x01, 0xD0, // $0503: LDA $D001 (acknowledges the interrupt)
+    0x40, // $0506: RTI
+];
+
+// Registers, cycles and the memory the program writes
+fn result(cpu: &Cpu6502, ram: &Ram) -> (Registers, u64, Vec<u8>) {
+    (cpu.registers, cpu.total_cycles(), ram.dump(0x0200, 0x200))
+}
+
+/* Runs the program against a device at $D000 that returns made up values and interrupts every
+300 cycles, with RDY pulled low for a while in between. */
+fn record() -> (Recording, (Registers, u64, Vec<u8>)) {
+    let ram = Ram::new();
+    ram.load(0x0400, &PROGRAM);
+    ram.load(0x0500, &HANDLER);
+    ram.load(0xFFFE, &[0x00, 0x05]);
+
+    let seed = Rc::new(Cell::new(1u8));
+    let acked = Rc::new(Cell::new(false));
+    let (read_ram, write_ram, peek_ram) = (ram.clone(), ram.clone(), ram.clone());
+    let (device, ack) = (seed.clone(), acked.clone());
+    let mut cpu = Cpu6502::new(
+        Box::new(move |address| match address {
+            0xD000 => {
+                device.set(device.get().wrapping_mul(37).wrapping_add(11));
+                device.get()
+            }
+            0xD001 => {
+                ack.set(true);
+                0
+            }
+            _ => read_ram.read(address as u16),
+        }),
+        Box::new(move |address, value| write_ram.write(address as u16, value)),
+    );
+    cpu.set_mem_peek(Box::new(move |address| peek_ram.read(address as u16)));
+    cpu.registers.pc = 0x0400;
+    cpu.registers.s = 0xFF;
+    cpu.registers.p = StatusFlags::E | StatusFlags::I;
+
+    cpu.start_recording(&[(0xD000, 0xD0FF)], 16).unwrap();
+    let mut next_irq = 300;
+    while cpu.registers.pc != 0x040A {
+        if cpu.total_cycles() >= next_irq {
+            cpu.set_irq(true);
+            next_irq += 300;
+        }
+        if acked.take() {
+            cpu.set_irq(false);
+        }
+        cpu.set_rdy(!(1000..1020).contains(&cpu.total_cycles()));
+        cpu.tick();
+    }
+
+    let recording = cpu.stop_recording().unwrap();
+    (recording, result(&cpu, &ram))
+}
+
+#[test]
+fn replay_matches_recording() {
+    let (recording, expected) = record();
+    assert_eq!(recording.reads.len(), 256 + expected.2[0x10] as usize);
+    assert!(recording
+        .line_changes
+        .iter()
+        .any(|c| c.line == Line::Rdy && !c.level));
+
+    let bytes = recording.to_bytes();
+    assert!(bytes.len() < 2000, "{} bytes", bytes.len());
+    assert_eq!(&bytes[..6], b"R6502\x01");
+    let recording = Recording::from_bytes(&bytes).unwrap();
+
+    // No device this time, everything it did comes from the recording
+    let (ram, mut cpu) = recording.replay();
+    while !cpu.replay_finished() {
+        cpu.tick();
+    }
+    assert_eq!(cpu.replay_desync(), None);
+    assert_eq!(result(&cpu, &ram), expected);
+}
+
+#[test]
+fn desync_is_detected() {
+    let (mut recording, _) = record();
+    let (cycle, pc) = recording.checkpoints[5];
+    recording.checkpoints[5].1 = 0x1234;
+
+    let (_, mut cpu) = recording.replay();
+    while !cpu.replay_finished() {
+        cpu.tick();
+    }
+    assert_eq!(
+        cpu.replay_desync(),
+        Some(Desync::Checkpoint {
+            cycle,
+            expected_pc: 0x1234,
+            pc
+        })
+    );
+}
+
+#[test]
+fn bad_recordings() {
+    let (mut recording, _) = record();
+    let mut bytes = recording.to_bytes();
+
+    let error = |bytes: &[u8]| Recording::from_bytes(bytes).unwrap_err().to_string();
+    assert_eq!(error(&bytes[..bytes.len() - 1]), "Recording is cut short");
+    assert_eq!(error(b"PK\x03\x04"), "Not a recording");
+    bytes[5] = 9;
+    assert_eq!(error(&bytes), "Unsupported recording version 9");
+
+    // A checkpoint every 0 instructions doesn't load, and replays like every instruction
+    recording.checkpoint_interval = 0;
+    assert_eq!(error(&recording.to_bytes()), "Bad checkpoint interval");
+    let (_, mut cpu) = recording.replay();
+    cpu.tick();
+    assert_eq!(cpu.replay_desync(), None);
+}
+
+#[test]
+fn recording_needs_peek() {
+    let ram = Ram::new();
+    let (read_ram, write_ram) = (ram.clone(), ram.clone());
+    let mut cpu = Cpu6502::new(
+        Box::new(move |address| read_ram.read(address as u16)),
+        Box::new(move |address, value| write_ram.write(address as u16, value)),
+    );
+
+    let error = cpu.start_recording(&[], 16).unwrap_err();
+    assert_eq!(error.to_string(), "Recording needs the peek callback");
+    assert!(cpu.stop_recording().is_none());
+}
@@ -0,0 +1,143 @@
+use rust_6502::memory::Ram;
+use rust_6502::run::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn cpu_with(ram: &Ram, program: &[u8]) -> rust_6502::Cpu6502<'static> {
+    ram.load(0x0400, program);
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu
+}
+
+#[test]
+fn stops_on_branch_trap() {
+    let ram = Ram::new();
+    let mut cpu = cpu_with(
+        &ram,
+        &[
+            0xA2, 0x05, // $0400: LDX #$05
+            0xCA, // $0402: DEX
+            0xD0, 0xFD, // $0403: BNE $0402
+            0xF0, 0xFE, // $0405: BEQ $0405
+        ],
+    );
+
+    let result = cpu.run(&RunOptions::default());
+    assert_eq!(result.reason, StopReason::Trap);
+    assert_eq!(result.pc, 0x0405);
+    assert_eq!(result.instructions, 1 + 5 * 2 + 1);
+    assert_eq!(result.cycles, 2 + 5 * 2 + 4 * 3 + 2 + 3);
+}
+
+#[test]
+fn detects_idle_loop() {
+    let ram = Ram::new();
+    let program = [
+        0xEA, // $0400: NOP
+        0xEA, // $0401: NOP
+        0x4C, 0x00, 0x04, // $0402: JMP $0400
+    ];
+
+    let mut cpu = cpu_with(&ram, &program);
+    let options = RunOptions {
+        max_cycles: Some(1000),
+        ..Default::default()
+    };
+    assert_eq!(cpu.run(&options).reason, StopReason::CycleLimit);
+
+    let mut cpu = cpu_with(&ram, &program);
+    let options = RunOptions {
+        loop_window: 8,
+        ..Default::default()
+    };
+    let result = cpu.run(&options);
+    assert_eq!(result.reason, StopReason::Loop(3));
+    assert_eq!(result.pc, 0x0401);
+}
+
+#[test]
+fn loop_with_writes_is_not_idle() {
+    let ram = Ram::new();
+    let mut cpu = cpu_with(
+        &ram,
+        &[
+            0x8D, 0x00, 0x02, // $0400: STA $0200
+            0x4C, 0x00, 0x04, // $0403: JMP $0400
+        ],
+    );
+    let options = RunOptions {
+        max_cycles: Some(1000),
+        loop_window: 8,
+        ..Default::default()
+    };
+    assert_eq!(cpu.run(&options).reason, StopReason::CycleLimit);
+}
+
+#[test]
+fn stops_when_halted() {
+    let ram = Ram::new();
+    let mut cpu = cpu_with(&ram, &[0xEA, 0x02]);
+    let result = cpu.run(&RunOptions::default());
+    assert_eq!(result.reason, StopReason::Halted);
+    assert_eq!(result.pc, 0x0401);
+}
+
+#[test]
+fn stops_when_asked() {
+    let stop = StopHandle::new();
+    let options = RunOptions {
+        stop: Some(stop.clone()),
+        ..Default::default()
+    };
+
+    // The CPU isn't Send, so it's made on the worker thread
+    let worker = thread::spawn(move || {
+        let ram = Ram::new();
+        let mut cpu = cpu_with(
+            &ram,
+            &[
+                0xEA, // $0400: NOP
+                0x4C, 0x00, 0x04, // $0401: JMP $0400
+            ],
+        );
+        cpu.run(&options)
+    });
+    thread::sleep(Duration::from_millis(10));
+    stop.stop();
+    let result = worker.join().unwrap();
+    assert_eq!(result.reason, StopReason::Stopped);
+    assert!(result.instructions > 0);
+
+    // Until it's reset the next run stops right away
+    let ram = Ram::new();
+    let mut cpu = cpu_with(&ram, &[0xEA, 0x4C, 0x00, 0x04]);
+    let options = RunOptions {
+        stop: Some(stop.clone()),
+        max_cycles: Some(100),
+        ..Default::default()
+    };
+    let result = cpu.run(&options);
+    assert_eq!(result.reason, StopReason::Stopped);
+    assert_eq!(
+        (result.pc, result.instructions, result.cycles),
+        (0x0400, 0, 0)
+    );
+    stop.reset();
+    assert_eq!(cpu.run(&options).reason, StopReason::CycleLimit);
+}
+
+#[test]
+fn times_out() {
+    let ram = Ram::new();
+    let mut cpu = cpu_with(&ram, &[0xEA, 0x4C, 0x00, 0x04]);
+    let options = RunOptions {
+        timeout: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let result = cpu.run(&options);
+    assert_eq!(result.reason, StopReason::Timeout);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(result.instructions % 1000, 0);
+}
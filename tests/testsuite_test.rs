@@ -0,0 +1,82 @@
+use rust_6502::testsuite::decimal;
+use rust_6502::testsuite::klaus::*;
+
+// Counts test cases at $0200, then traps at $040A if X ended up as 3
+fn program(x: u8) -> Vec<u8> {
+    let program = [
+        0xA2, x, // $0400: LDX #x
+        0xEE, 0x00, 0x02, // $0402: INC $0200
+        0xE8, // $0405: INX
+        0xE0, 0x03, // $0406: CPX #$03
+        0xD0, 0x03, // $0408: BNE $040D
+        0x4C, 0x0A, 0x04, // $040A: JMP $040A
+        0x4C, 0x0D, 0x04, // $040D: JMP $040D
+    ];
+    image(&program)
+}
+
+fn image(program: &[u8]) -> Vec<u8> {
+    let mut image = vec![0; 0x10000];
+    image[0x0400..0x0400 + program.len()].copy_from_slice(program);
+    image
+}
+
+#[test]
+fn functional_success_trap() {
+    let report = TestRom::functional(program(0x02), 0x040A).run();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.test_case, Some(1));
+}
+
+#[test]
+fn functional_failure_trap() {
+    let report = TestRom::functional(program(0x00), 0x040A).run();
+    assert_eq!(report.outcome, Outcome::Failed);
+    assert_eq!(report.pc, 0x040D);
+    assert!(report.to_string().contains("test case $01"));
+}
+
+#[test]
+fn interrupt_port() {
+    let program = [
+        0x58, // $0400: CLI
+        0xA9, 0x01, // $0401: LDA #$01
+        0x8D, 0xFC, 0xBF, // $0403: STA $BFFC (assert IRQ)
+        0xAD, 0x00, 0x03, // $0406: LDA $0300
+        0xF0, 0x03, // $0409: BEQ $040E
+        0x4C, 0x0B, 0x04, // $040B: JMP $040B
+        0x4C, 0x0E, 0x04, // $040E: JMP $040E
+    ];
+    let handler = [
+        0xA9, 0x00, // $0500: LDA #$00
+        0x8D, 0xFC, 0xBF, // $0502: STA $BFFC (release IRQ)
+        0xEE, 0x00, 0x03, // $0505: INC $0300
+        0x40, // $0508: RTI
+    ];
+
+    let mut image = image(&program);
+    image[0x0500..0x0500 + handler.len()].copy_from_slice(&handler);
+    image[0xFFFE] = 0x00;
+    image[0xFFFF] = 0x05;
+
+    let report = TestRom::interrupt(image, 0x040B).run();
+    assert!(report.passed(), "{}", report);
+}
+
+// Runs the real thing if it has been fetched with clone_tests.sh
+#[test]
+fn klaus_functional_test() {
+    let Ok(image) = std::fs::read("tests/roms/6502_functional_test.bin") else {
+        return;
+    };
+
+    let report = TestRom::functional(image, 0x3469).run();
+    assert!(report.passed(), "{}", report);
+}
+
+#[test]
+fn decimal_mode_exhaustive() {
+    let report = decimal::run(decimal::Inputs::All, 20);
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.checked, 2 * 2 * 256 * 256);
+}
@@ -0,0 +1,120 @@
+use rust_6502::trace::diff::*;
+use rust_6502::trace::*;
+use rust_6502::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const MEM_SIZE: usize = 0x10000;
+
+fn run_traced(program: &[u8], format: TraceFormat, steps: usize) -> Vec<String> {
+    let ram = Rc::new(RefCell::new(vec![0u8; MEM_SIZE]));
+    ram.borrow_mut()[0xC000..0xC000 + program.len()].copy_from_slice(program);
+    ram.borrow_mut()[0x020F] = 0x42;
+
+    let lines = Rc::new(RefCell::new(Vec::new()));
+
+    let mem_read = |address: usize| -> u8 { ram.borrow()[address] };
+    let mem_write = |address: usize, value: u8| ram.borrow_mut()[address] = value;
+    let mem_peek = |address: usize| -> u8 { ram.borrow()[address] };
+
+    let mut cpu = Cpu6502::new(Box::new(mem_read), Box::new(mem_write));
+    cpu.set_mem_peek(Box::new(mem_peek));
+    cpu.set_tracer(Tracer::new(
+        format,
+        Box::new(|line| lines.borrow_mut().push(line.to_string())),
+    ));
+
+    cpu.registers.pc = 0xC000;
+    cpu.registers.s = 0xFD;
+    cpu.registers.p = StatusFlags::from_bits(0x24).unwrap();
+
+    for _ in 0..steps {
+        cpu.tick();
+    }
+    drop(cpu);
+
+    lines.take()
+}
+
+#[test]
+fn nestest_format() {
+    let program = [
+        0xA2, 0x10, // LDX #$10
+        0x8E, 0x00, 0x02, // STX $0200
+        0xBD, 0xFF, 0x01, // LDA $01FF,X
+        0x20, 0x10, 0xC0, // JSR $C010
+    ];
+    let lines = run_traced(&program, TraceFormat::Nestest, 4);
+
+    assert_eq!(
+        lines,
+        [
+            "C000  A2 10     LDX #$10                        A:00 X:00 Y:00 P:24 SP:FD CYC:0",
+            "C002  8E 00 02  STX $0200 = 00                  A:00 X:10 Y:00 P:24 SP:FD CYC:2",
+            "C005  BD FF 01  LDA $01FF,X @ 020F = 42         A:00 X:10 Y:00 P:24 SP:FD CYC:6",
+            "C008  20 10 C0  JSR $C010                       A:42 X:10 Y:00 P:24 SP:FD CYC:11",
+        ]
+    );
+}
+
+#[test]
+fn custom_format() {
+    let program = [
+        0xA5, 0x33, // LDA $33
+        0x38, // SEC
+        0xEA, // NOP
+    ];
+    let format = TraceFormat::Custom("{pc} [{bytes}] {instr} {flags} @{cyc}".to_string());
+    let lines = run_traced(&program, format, 3);
+
+    assert_eq!(
+        lines,
+        [
+            "C000 [A5 33] LDA $33 = 00 nv-bdIzc @0",
+            "C002 [38] SEC nv-bdIZc @3",
+            "C003 [EA] NOP nv-bdIZC @5",
+        ]
+    );
+}
+
+#[test]
+fn diff_reports_first_divergence() {
+    let program = [
+        0xA2, 0x10, // LDX #$10
+        0x8E, 0x00, 0x02, // STX $0200
+        0xBD, 0xFF, 0x01, // LDA $01FF,X
+        0x20, 0x10, 0xC0, // JSR $C010
+    ];
+    let ours = run_traced(&program, TraceFormat::Nestest, 4).join("\n");
+
+    // Reference in Mesen's format, with a bogus carry flag on the third instruction
+    let reference = "\
+        C000  A2 10     LDX #$10     A:00 X:00 Y:00 S:FD P:nvUbdIzc\n\
+        C002  8E 00 02  STX $0200    A:00 X:10 Y:00 S:FD P:nvUbdIzc\n\
+        C005  BD FF 01  LDA $01FF,X  A:00 X:10 Y:00 S:FD P:nvUbdIzC\n\
+        C008  20 10 C0  JSR $C010    A:42 X:10 Y:00 S:FD P:nvUbdIzc\n";
+
+    let report = diff(&ours, reference, &DiffOptions::default());
+    let divergence = report.divergence.expect("Traces should diverge");
+    assert_eq!(divergence.index, 2);
+    assert_eq!(divergence.reference.line, 3);
+    assert_eq!(
+        divergence.deltas,
+        [FieldDelta {
+            field: Field::P,
+            ours: 0x24,
+            reference: 0x25
+        }]
+    );
+    assert_eq!(divergence.context.len(), 2);
+
+    // Tolerating the carry bit lets the rest of the trace match
+    let options = DiffOptions {
+        p_mask: !0x01,
+        ..Default::default()
+    };
+    let report = diff(&ours, reference, &options);
+    assert!(report.is_match());
+    assert_eq!(report.compared, 4);
+}
@@ -0,0 +1,171 @@
+use rust_6502::monitor::Monitor;
+use rust_6502::tui::*;
+
+fn tui() -> Tui {
+    let mut monitor = Monitor::new();
+    for line in [
+        "a 0400 ldx #0",
+        "inx",
+        "stx $0210",
+        "jsr $0500",
+        "cpx #3",
+        "bne $0402",
+        "brk",
+        "",
+        "a 0500 rts",
+        "",
+        "r pc=0400",
+    ] {
+        monitor.execute(line).unwrap();
+    }
+    Tui::new(monitor)
+}
+
+fn text(line: &Line) -> String {
+    line.iter().map(|s| s.text.as_str()).collect()
+}
+
+#[test]
+fn disassembly() {
+    let mut tui = tui();
+    tui.step();
+    tui.step();
+
+    let lines = tui.disassembly(6);
+    let addresses: Vec<_> = lines.iter().map(|l| l.0).collect();
+    assert_eq!(
+        addresses,
+        [
+            Some(0x0400),
+            Some(0x0402),
+            Some(0x0403),
+            Some(0x0406),
+            Some(0x0409),
+            Some(0x040B)
+        ]
+    );
+    assert_eq!(text(&lines[2].1), ">0403  8E 10 02  STX $0210");
+    assert_eq!(lines[2].1[0].style, Style::Pc);
+
+    // The cursor moves over instructions and can get a breakpoint
+    tui.move_cursor(6, true);
+    tui.toggle_breakpoint();
+    assert_eq!(tui.cursor, Some(0x0406));
+    let lines = tui.disassembly(6);
+    let (_, line) = lines.iter().find(|l| l.0 == Some(0x0406)).unwrap();
+    assert_eq!(text(line), "*0406  20 00 05  JSR $0500");
+    assert_eq!(line[0].style, Style::Cursor);
+}
+
+#[test]
+fn recent_writes() {
+    let mut tui = tui();
+    tui.memory_address = 0x0200;
+    let written = |tui: &Tui| tui.memory_pane(2)[1][1].style;
+
+    tui.step();
+    tui.step();
+    assert_eq!(written(&tui), Style::Normal);
+    tui.step();
+    assert_eq!(text(&tui.memory_pane(2)[1])[..9], *"0210  01 ");
+    assert_eq!(written(&tui), Style::Changed);
+
+    // Fades after a few steps
+    for _ in 0..4 {
+        tui.step();
+    }
+    assert_eq!(written(&tui), Style::Normal);
+}
+
+#[test]
+fn stack_and_call_stack() {
+    let mut tui = tui();
+    for _ in 0..4 {
+        tui.step();
+    }
+
+    let stack: Vec<_> = tui.stack_pane(10).iter().map(text).collect();
+    assert_eq!(stack, ["$01FD <- SP", "$01FE  08", "$01FF  04"]);
+
+    let calls: Vec<_> = tui.call_stack_pane(10).iter().map(text).collect();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[1].starts_with("#1  $0406"));
+
+    let registers: Vec<_> = tui.registers_pane().iter().map(text).collect();
+    assert_eq!(registers[0], "PC $0500");
+    assert_eq!(registers[3], "nv-bdIzc");
+}
+
+#[test]
+fn running_in_slices() {
+    let mut tui = tui();
+    tui.command("b 0409");
+    tui.cont();
+
+    // Small slices, so the breakpoint is hit on a slice boundary at some point
+    while tui.is_running() {
+        tui.run_slice(1);
+    }
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x0409);
+    assert_eq!(tui.output.last().unwrap(), "Breakpoint 1 at $0409");
+
+    // Continuing from the breakpoint doesn't stop on it straight away
+    tui.cont();
+    tui.run_slice(1);
+    tui.run_slice(1);
+    assert!(tui.is_running());
+    assert_ne!(tui.monitor.debugger.cpu.registers.pc, 0x0409);
+    tui.pause();
+    assert_eq!(tui.output.last().unwrap(), "Paused");
+}
+
+#[test]
+fn stepping_in_slices() {
+    let mut tui = tui();
+    tui.cursor = Some(0x0406);
+    tui.run_to_cursor();
+    assert!(tui.is_running());
+    while tui.is_running() {
+        tui.run_slice(1);
+    }
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x0406);
+
+    // Stepping over the JSR runs until it returns, and can be paused on the way
+    tui.step_over();
+    tui.run_slice(1);
+    assert!(tui.is_running());
+    tui.pause();
+    assert_eq!(tui.output.last().unwrap(), "Paused");
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x0500);
+
+    tui.step_out();
+    while tui.is_running() {
+        tui.run_slice(1);
+    }
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x0409);
+
+    // Not a JSR, so just a step
+    tui.step_over();
+    assert!(!tui.is_running());
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x040B);
+}
+
+#[test]
+fn running_command() {
+    let mut tui = tui();
+    tui.command("a 0420 jmp $0420");
+    tui.command("");
+
+    // g from the command line runs in slices like continuing, so it can be paused
+    tui.command("g 420");
+    assert!(tui.is_running());
+    tui.run_slice(1000);
+    assert!(tui.is_running());
+    tui.pause();
+    assert!(!tui.is_running());
+    assert_eq!(tui.monitor.debugger.cpu.registers.pc, 0x0420);
+
+    tui.command("u");
+    assert!(!tui.is_running());
+    assert_eq!(tui.output.last().unwrap(), "? Missing address");
+}
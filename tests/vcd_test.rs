@@ -0,0 +1,105 @@
+use rust_6502::memory::Ram;
+use rust_6502::vcd::VcdWriter;
+use rust_6502::StatusFlags;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+const PROGRAM: [u8; 7] = [
+    0xA9, 0x42, // $0400: LDA #$42
+    0x8D, 0x00, 0x02, // $0402: STA $0200
+    0xEA, // $0405: NOP
+    0xEA, // $0406: NOP
+];
+
+// What's on the pins while PHI2 is high: A, D, RWB, SYNC, IRQB, NMIB, RDY
+type Pins = (u16, u8, u8, u8, u8, u8, u8);
+
+// Reads back the value of every signal on each rising edge of PHI2, along with its time
+fn samples(vcd: &str) -> Vec<(u64, Pins)> {
+    let mut values: HashMap<&str, u16> = HashMap::new();
+    let mut time = 0;
+    let mut samples = Vec::new();
+    let mut sample = |values: &HashMap<&str, u16>, time| {
+        if values.get("!") == Some(&1) {
+            let pin = |id: &str| values[id];
+            samples.push((
+                time,
+                (
+                    pin("\""),
+                    pin("#") as u8,
+                    pin("$") as u8,
+                    pin("%") as u8,
+                    pin("&") as u8,
+                    pin("'") as u8,
+                    pin("(") as u8,
+                ),
+            ));
+        }
+    };
+
+    let body = &vcd[vcd.find("$enddefinitions $end\n").unwrap()..];
+    for line in body.lines().skip(1) {
+        if let Some(t) = line.strip_prefix('#') {
+            sample(&values, time);
+            time = t.parse().unwrap();
+        } else if let Some(vector) = line.strip_prefix('b') {
+            let (value, id) = vector.split_once(' ').unwrap();
+            values.insert(id, u16::from_str_radix(value, 2).unwrap_or(0xFFFF));
+        } else if !line.starts_with('$') {
+            values.insert(&line[1..], line[..1].parse().unwrap());
+        }
+    }
+    sample(&values, time);
+    samples
+}
+
+#[test]
+fn bus_cycles() {
+    let ram = Ram::new();
+    ram.load(0x0400, &PROGRAM);
+    let out = RefCell::new(String::new());
+    let mut cpu = ram.cpu();
+    cpu.registers.pc = 0x0400;
+    cpu.registers.p = StatusFlags::I;
+    cpu.set_vcd(Some(VcdWriter::new(
+        1_000_000,
+        Box::new(|text| out.borrow_mut().push_str(text)),
+    )));
+
+    cpu.tick();
+    cpu.tick();
+    cpu.tick();
+    cpu.set_irq(true);
+    cpu.set_rdy(false);
+    cpu.tick();
+    cpu.tick();
+    cpu.set_rdy(true);
+    cpu.tick();
+    drop(cpu);
+
+    let vcd = out.take();
+    assert!(vcd.starts_with("$version rust-6502 $end\n$timescale 1ns $end\n"));
+    assert!(vcd.contains("$var wire 16 \" A [15:0] $end\n"));
+
+    let samples = samples(&vcd);
+    let times: Vec<u64> = samples.iter().map(|(time, _)| *time).collect();
+    assert_eq!(times, (0..12).map(|c| c * 1000 + 500).collect::<Vec<_>>());
+    let pins: Vec<Pins> = samples.into_iter().map(|(_, pins)| pins).collect();
+    assert_eq!(
+        pins,
+        [
+            (0x0400, 0xA9, 1, 1, 1, 1, 1), // LDA #$42
+            (0x0401, 0x42, 1, 0, 1, 1, 1),
+            (0x0402, 0x8D, 1, 1, 1, 1, 1), // STA $0200
+            (0x0403, 0x00, 1, 0, 1, 1, 1),
+            (0x0404, 0x02, 1, 0, 1, 1, 1),
+            (0x0200, 0x42, 0, 0, 1, 1, 1),
+            (0x0405, 0xEA, 1, 1, 1, 1, 1), // NOP
+            (0x0406, 0xEA, 1, 0, 1, 1, 1),
+            (0x0406, 0xEA, 1, 0, 0, 1, 0), // Stalled with IRQ asserted
+            (0x0406, 0xEA, 1, 0, 0, 1, 0),
+            (0x0406, 0xEA, 1, 1, 0, 1, 1), // NOP
+            (0x0407, 0x00, 1, 0, 0, 1, 1),
+        ]
+    );
+}
@@ -0,0 +1,334 @@
+use rust_6502::debugger::*;
+use rust_6502::memory::Ram;
+use rust_6502::vice::{self, command, error, Request, Response, ViceServer};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const PROGRAM: [u8; 12] = [
+    0xA9, 0x42, // $0400: LDA #$42
+    0x20, 0x0A, 0x04, // $0402: JSR $040A
+    0xE8, // $0405: INX
+    0x4C, 0x05, 0x04, // $0406: JMP $0405
+    0xEA, // $0409: NOP
+    0x8D, 0x00, // $040A: STA $0200 (continued below)
+];
+
+fn debugger(ram: &Ram) -> Debugger<'static> {
+    ram.load(0x0400, &PROGRAM);
+    ram.load(0x040C, &[0x02, 0x60]); // ... $040D: RTS
+    let mut debugger = Debugger::new(ram.cpu());
+    debugger.cpu.registers.pc = 0x0400;
+    debugger.cpu.registers.s = 0xFF;
+    debugger
+}
+
+fn request(command: u8, body: &[u8]) -> Request {
+    Request {
+        version: 2,
+        id: 7,
+        command,
+        body: body.to_vec(),
+    }
+}
+
+// The one response that isn't an event
+fn answer(responses: &[Response]) -> &Response {
+    let mut answers = responses.iter().filter(|r| !r.is_event());
+    let answer = answers.next().unwrap();
+    assert!(answers.next().is_none());
+    answer
+}
+
+fn events(responses: &[Response]) -> Vec<u8> {
+    responses
+        .iter()
+        .filter(|r| r.is_event())
+        .map(|r| r.kind)
+        .collect()
+}
+
+#[test]
+fn memory_and_registers() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut server = ViceServer::new(&mut dbg);
+
+    let get = request(command::MEMORY_GET, &[0, 0x00, 0x04, 0x01, 0x04, 0, 0, 0]);
+    let responses = server.handle(&get);
+    assert_eq!(answer(&responses).body, [2, 0, 0xA9, 0x42]);
+    assert_eq!(answer(&responses).id, 7);
+
+    let set = request(
+        command::MEMORY_SET,
+        &[0, 0x00, 0x03, 0x01, 0x03, 0, 0, 0, 1, 2],
+    );
+    assert_eq!(answer(&server.handle(&set)).error, error::OK);
+    let bad = request(
+        command::MEMORY_SET,
+        &[0, 0x00, 0x03, 0x01, 0x03, 0, 0, 0, 1],
+    );
+    assert_eq!(answer(&server.handle(&bad)).error, error::BAD_LENGTH);
+    let bank = request(command::MEMORY_GET, &[0, 0x00, 0x04, 0x01, 0x04, 1, 0, 0]);
+    assert_eq!(answer(&server.handle(&bank)).error, error::INVALID_MEMSPACE);
+
+    let responses = server.handle(&request(command::REGISTERS_GET, &[0]));
+    let body = &answer(&responses).body;
+    assert_eq!(body[..2], [6, 0]);
+    assert_eq!(body[2..6], [3, 0x00, 0x00, 0x00]); // A
+    assert_eq!(body[14..18], [3, 0x03, 0x00, 0x04]); // PC
+
+    // Set X and PC
+    let set = request(
+        command::REGISTERS_SET,
+        &[0, 2, 0, 3, 0x01, 0x10, 0x00, 3, 0x03, 0x05, 0x04],
+    );
+    let responses = server.handle(&set);
+    assert_eq!(answer(&responses).kind, command::REGISTERS_GET);
+    assert_eq!(server.debugger.cpu.registers.x, 0x10);
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0405);
+
+    let responses = server.handle(&request(command::REGISTERS_AVAILABLE, &[0]));
+    let body = &answer(&responses).body;
+    assert_eq!(body[..7], [6, 0, 4, 0x00, 8, 1, b'A']);
+
+    let responses = server.handle(&request(0x42, &[]));
+    assert_eq!(answer(&responses).error, error::UNSUPPORTED_COMMAND);
+    let mut old = request(command::PING, &[]);
+    old.version = 9;
+    assert_eq!(
+        answer(&server.handle(&old)).error,
+        error::UNSUPPORTED_VERSION
+    );
+
+    drop(server);
+    assert_eq!(ram.read(0x0301), 2);
+}
+
+#[test]
+fn checkpoints() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut server = ViceServer::new(&mut dbg);
+
+    // A store checkpoint on $0200 and an exec checkpoint on $0405
+    let store = request(command::CHECKPOINT_SET, &[0, 2, 0, 2, 1, 1, 0x02, 0]);
+    let responses = server.handle(&store);
+    let info = &answer(&responses).body;
+    assert_eq!(answer(&responses).kind, command::CHECKPOINT_GET);
+    assert_eq!(info[..5], [1, 0, 0, 0, 0]);
+    assert_eq!(info[5..13], [0, 2, 0, 2, 1, 1, 0x02, 0]);
+
+    let exec = request(command::CHECKPOINT_SET, &[5, 4, 5, 4, 1, 1, 0x04, 0]);
+    server.handle(&exec);
+    let condition = request(
+        command::CONDITION_SET,
+        &[2, 0, 0, 0, 6, b'X', b'=', b'=', b'$', b'0', b'3'],
+    );
+    assert_eq!(answer(&server.handle(&condition)).error, error::OK);
+
+    let responses = server.handle(&request(command::CHECKPOINT_LIST, &[]));
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[2].body, [2, 0, 0, 0]);
+
+    // Running stops after the store
+    let responses = server.handle(&request(command::EXIT, &[]));
+    assert_eq!(events(&responses), [command::RESUMED]);
+    assert!(server.is_running());
+
+    let responses = server.run_slice(1000);
+    assert_eq!(
+        events(&responses),
+        [
+            command::CHECKPOINT_GET,
+            command::REGISTERS_GET,
+            command::STOPPED
+        ]
+    );
+    assert_eq!(responses[0].body[4], 1); // Currently hit
+    assert_eq!(responses[2].body, [0x0D, 0x04]);
+    assert!(!server.is_running());
+
+    // The conditional checkpoint stops with X at 3
+    server.handle(&request(command::EXIT, &[]));
+    let responses = server.run_slice(1000);
+    assert_eq!(events(&responses).last(), Some(&command::STOPPED));
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0405);
+    assert_eq!(server.debugger.cpu.registers.x, 3);
+
+    let delete = request(command::CHECKPOINT_DELETE, &[2, 0, 0, 0]);
+    assert_eq!(answer(&server.handle(&delete)).error, error::OK);
+    assert_eq!(answer(&server.handle(&delete)).error, error::NO_OBJECT);
+
+    // The loop now runs until any command stops it
+    server.handle(&request(command::EXIT, &[]));
+    assert!(server.run_slice(1000).is_empty());
+    let responses = server.handle(&request(command::PING, &[]));
+    assert_eq!(
+        events(&responses),
+        [command::REGISTERS_GET, command::STOPPED]
+    );
+    assert!(!server.is_running());
+}
+
+#[test]
+fn exec_range_checkpoint() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut server = ViceServer::new(&mut dbg);
+
+    // One breakpoint however many addresses it covers, which stops in the subroutine
+    let exec = request(command::CHECKPOINT_SET, &[0x0A, 4, 0xFF, 4, 1, 1, 0x04, 0]);
+    assert_eq!(answer(&server.handle(&exec)).error, error::OK);
+    assert_eq!(server.debugger.breakpoints().len(), 1);
+
+    server.handle(&request(command::EXIT, &[]));
+    let responses = server.run_slice(1000);
+    assert_eq!(events(&responses).last(), Some(&command::STOPPED));
+    assert_eq!(server.debugger.cpu.registers.pc, 0x040A);
+
+    let delete = request(command::CHECKPOINT_DELETE, &[1, 0, 0, 0]);
+    assert_eq!(answer(&server.handle(&delete)).error, error::OK);
+    assert!(server.debugger.breakpoints().is_empty());
+}
+
+#[test]
+fn advance() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut server = ViceServer::new(&mut dbg);
+
+    let responses = server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[0, 2, 0]));
+    assert_eq!(answer(&responses).error, error::OK);
+    assert_eq!(
+        events(&responses),
+        [command::REGISTERS_GET, command::STOPPED]
+    );
+    assert_eq!(server.debugger.cpu.registers.pc, 0x040A);
+
+    // Running until the return is done in slices like a resume
+    let responses = server.handle(&request(command::EXECUTE_UNTIL_RETURN, &[]));
+    assert_eq!(answer(&responses).error, error::OK);
+    assert!(server.is_running());
+    let responses = server.run_slice(1000);
+    assert_eq!(
+        events(&responses),
+        [command::REGISTERS_GET, command::STOPPED]
+    );
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0405);
+
+    // Stepping over the JSR, then the INX, with a few cycles at a time
+    server.debugger.cpu.registers.pc = 0x0402;
+    server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[1, 2, 0]));
+    let mut responses = Vec::new();
+    while server.is_running() {
+        responses.extend(server.run_slice(2));
+    }
+    assert_eq!(
+        events(&responses),
+        [command::REGISTERS_GET, command::STOPPED]
+    );
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0406);
+
+    // Over anything else it's just steps
+    let responses = server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[1, 1, 0]));
+    assert!(!server.is_running());
+    assert_eq!(events(&responses).last(), Some(&command::STOPPED));
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0405);
+}
+
+#[test]
+fn advance_over_tracepoint() {
+    let ram = Ram::new();
+    let mut dbg = debugger(&ram);
+    let mut server = ViceServer::new(&mut dbg);
+
+    // A store checkpoint that doesn't stop, over a range the STA writes into
+    let trace = request(command::CHECKPOINT_SET, &[0, 2, 3, 2, 0, 1, 0x02, 0]);
+    server.handle(&trace);
+    server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[0, 2, 0]));
+    assert_eq!(server.debugger.cpu.registers.pc, 0x040A);
+
+    // The store is reported and the steps carry on, without resuming
+    let responses = server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[0, 2, 0]));
+    assert_eq!(
+        events(&responses),
+        [
+            command::CHECKPOINT_GET,
+            command::REGISTERS_GET,
+            command::STOPPED
+        ]
+    );
+    assert!(!server.is_running());
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0405);
+
+    // Same stepping over the JSR
+    server.debugger.cpu.registers.pc = 0x0402;
+    server.handle(&request(command::ADVANCE_INSTRUCTIONS, &[1, 2, 0]));
+    let mut responses = Vec::new();
+    while server.is_running() {
+        responses.extend(server.run_slice(2));
+    }
+    assert_eq!(
+        events(&responses),
+        [
+            command::CHECKPOINT_GET,
+            command::REGISTERS_GET,
+            command::STOPPED
+        ]
+    );
+    assert_eq!(server.debugger.cpu.registers.pc, 0x0406);
+}
+
+fn read_response(stream: &mut TcpStream) -> Response {
+    let mut header = [0; 12];
+    stream.read_exact(&mut header).unwrap();
+    let len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+    Response {
+        kind: header[6],
+        error: header[7],
+        id: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+        body,
+    }
+}
+
+#[test]
+fn tcp_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let ram = Ram::new();
+        let mut dbg = debugger(&ram);
+        let (stream, _) = listener.accept().unwrap();
+        vice::serve_stream(&mut dbg, stream).unwrap();
+    });
+
+    let mut client = TcpStream::connect(address).unwrap();
+    let get = request(command::MEMORY_GET, &[0, 0x00, 0x04, 0x01, 0x04, 0, 0, 0]);
+    client.write_all(&get.to_bytes()).unwrap();
+    assert_eq!(read_response(&mut client).body, [2, 0, 0xA9, 0x42]);
+
+    let exec = request(command::CHECKPOINT_SET, &[9, 4, 9, 4, 1, 1, 0x04, 0]);
+    client.write_all(&exec.to_bytes()).unwrap();
+    read_response(&mut client);
+    let exit = request(command::EXIT, &[]);
+    client.write_all(&exit.to_bytes()).unwrap();
+    assert_eq!(read_response(&mut client).kind, command::EXIT);
+    assert_eq!(read_response(&mut client).kind, command::RESUMED);
+
+    // The endless loop keeps running until a command comes in
+    let ping = request(command::PING, &[]);
+    client.write_all(&ping.to_bytes()).unwrap();
+    assert_eq!(read_response(&mut client).kind, command::REGISTERS_GET);
+    assert_eq!(read_response(&mut client).kind, command::STOPPED);
+    assert_eq!(read_response(&mut client).kind, command::PING);
+
+    client
+        .write_all(&request(command::QUIT, &[]).to_bytes())
+        .unwrap();
+    assert_eq!(read_response(&mut client).kind, command::QUIT);
+    server.join().unwrap();
+}